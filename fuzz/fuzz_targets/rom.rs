@@ -0,0 +1,20 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use chip8::Chip8;
+
+// enough to get through a few frames worth of instructions
+const CYCLES: usize = 1000;
+
+// loads arbitrary bytes as a rom and runs it, errors are fine, panics are not
+fuzz_target!(|program: &[u8]| {
+    let mut chip = match Chip8::load(program.to_vec()) {
+        Ok(chip) => chip,
+        Err(_) => return,
+    };
+    for _ in 0..CYCLES {
+        if chip.cycle().is_err() {
+            break;
+        }
+    }
+});
@@ -0,0 +1,16 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use chip8::Chip8;
+
+const CYCLES: usize = 1000;
+
+// starts from arbitrary registers, stack and program, errors are fine, panics are not
+fuzz_target!(|chip: Chip8| {
+    let mut chip = chip;
+    for _ in 0..CYCLES {
+        if chip.cycle().is_err() {
+            break;
+        }
+    }
+});
@@ -0,0 +1,112 @@
+// addresses the rom stops at before running the instruction there, shared
+// by the debugger, the gdb stub and the debug adapter
+use std::collections::BTreeSet;
+
+#[derive(Debug, Default)]
+pub struct Breakpoints {
+    addresses: BTreeSet<u16>,
+    // the ones an editor set through the debug adapter. it replaces all of
+    // them at once, so they are kept apart from the others.
+    editor: BTreeSet<u16>,
+    // the breakpoint the rom stopped on, passed over once when it goes on
+    stopped: Option<u16>,
+    // a one-off stop for stepping over calls and out of subroutines
+    until: Option<u16>,
+}
+
+impl Breakpoints {
+    pub fn insert(&mut self, address: u16) {
+        self.addresses.insert(address & 0xfff);
+    }
+
+    pub fn remove(&mut self, address: u16) {
+        self.addresses.remove(&(address & 0xfff));
+    }
+
+    // clears a breakpoint whoever set it
+    pub fn toggle(&mut self, address: u16) {
+        let address = address & 0xfff;
+        let editor = self.editor.remove(&address);
+        if !self.addresses.remove(&address) && !editor {
+            self.insert(address);
+        }
+    }
+
+    // replaces the editor's breakpoints, the ones set elsewhere stay
+    pub fn set_editor(&mut self, addresses: impl IntoIterator<Item = u16>) {
+        self.editor = addresses
+            .into_iter()
+            .map(|address| address & 0xfff)
+            .collect();
+    }
+
+    pub fn addresses(&self) -> impl Iterator<Item = u16> + '_ {
+        self.addresses.union(&self.editor).copied()
+    }
+
+    // true when hit can't stop the rom anywhere and has no stop to pass over
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+            && self.editor.is_empty()
+            && self.until.is_none()
+            && self.stopped.is_none()
+    }
+
+    // stops the rom the next time it gets to address, None to cancel
+    pub fn run_until(&mut self, address: Option<u16>) {
+        self.until = address.map(|address| address & 0xfff);
+    }
+
+    // called before each cycle, true when the rom should stop at pc
+    pub fn hit(&mut self, pc: u16) -> bool {
+        let passed = self.stopped.take() == Some(pc);
+        if self.until == Some(pc) {
+            self.until = None;
+        } else if passed || !(self.addresses.contains(&pc) || self.editor.contains(&pc)) {
+            return false;
+        }
+        self.stopped = Some(pc);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_breakpoints() {
+        let mut breakpoints = Breakpoints::default();
+        breakpoints.insert(0x206);
+        breakpoints.toggle(0x300);
+        breakpoints.toggle(0x300);
+        assert_eq!(breakpoints.addresses().collect::<Vec<_>>(), [0x206]);
+
+        assert!(!breakpoints.hit(0x204));
+        assert!(breakpoints.hit(0x206));
+        // going on from the breakpoint passes over it once
+        assert!(!breakpoints.hit(0x206));
+        assert!(breakpoints.hit(0x206));
+        breakpoints.remove(0x206);
+        assert!(!breakpoints.hit(0x206));
+
+        assert!(breakpoints.is_empty());
+
+        breakpoints.run_until(Some(0x210));
+        assert!(!breakpoints.is_empty());
+        assert!(breakpoints.hit(0x210));
+        assert!(!breakpoints.is_empty());
+        assert!(!breakpoints.hit(0x210));
+        assert!(breakpoints.is_empty());
+
+        // the editor's breakpoints are replaced without touching the others
+        breakpoints.insert(0x206);
+        breakpoints.set_editor(vec![0x206, 0x208]);
+        assert!(breakpoints.hit(0x208));
+        assert!(!breakpoints.hit(0x20a));
+        breakpoints.set_editor(vec![]);
+        assert_eq!(breakpoints.addresses().collect::<Vec<_>>(), [0x206]);
+        breakpoints.toggle(0x206);
+        assert!(breakpoints.is_empty());
+    }
+}
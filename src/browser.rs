@@ -0,0 +1,116 @@
+use crate::overlay::Panel;
+use sdl2::keyboard::Keycode;
+use std::path::{Path, PathBuf};
+
+// picks a rom from the file system, one directory at a time
+pub struct Browser {
+    dir: PathBuf,
+    // directories first, then files, both sorted by name
+    entries: Vec<Entry>,
+    selected: usize,
+    message: Option<String>,
+}
+
+struct Entry {
+    name: String,
+    path: PathBuf,
+    is_dir: bool,
+}
+
+impl Browser {
+    pub fn new(dir: &Path) -> Browser {
+        let mut browser = Browser {
+            dir: dir.to_path_buf(),
+            entries: Vec::new(),
+            selected: 0,
+            message: None,
+        };
+        browser.enter(dir);
+        browser
+    }
+
+    // shown in place of the help until the next key press
+    pub fn set_message(&mut self, message: String) {
+        self.message = Some(message);
+    }
+
+    // returns the file picked with return
+    pub fn key_down(&mut self, keycode: Keycode) -> Option<PathBuf> {
+        self.message = None;
+        let last = self.entries.len().saturating_sub(1);
+        match keycode {
+            Keycode::Up => self.selected = self.selected.saturating_sub(1),
+            Keycode::Down => self.selected = (self.selected + 1).min(last),
+            Keycode::PageUp => self.selected = self.selected.saturating_sub(10),
+            Keycode::PageDown => self.selected = (self.selected + 10).min(last),
+            Keycode::Backspace => {
+                if let Some(parent) = self.dir.parent().map(Path::to_path_buf) {
+                    self.enter(&parent);
+                }
+            }
+            Keycode::Return => {
+                let entry = self.entries.get(self.selected)?;
+                if !entry.is_dir {
+                    return Some(entry.path.clone());
+                }
+                let dir = entry.path.clone();
+                self.enter(&dir);
+            }
+            _ => {}
+        }
+        None
+    }
+
+    pub fn panel(&self) -> Panel {
+        Panel {
+            title: self.dir.display().to_string(),
+            items: self
+                .entries
+                .iter()
+                .map(|entry| match entry.is_dir {
+                    true => format!("{}/", entry.name),
+                    false => entry.name.clone(),
+                })
+                .collect(),
+            selected: self.selected,
+            footer: self
+                .message
+                .clone()
+                .unwrap_or_else(|| "return: open  backspace: up  escape: back".to_string()),
+            marks: Vec::new(),
+        }
+    }
+
+    fn enter(&mut self, dir: &Path) {
+        let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+        let mut entries: Vec<Entry> = match std::fs::read_dir(&dir) {
+            Ok(read) => read
+                .filter_map(|entry| entry.ok())
+                .map(|entry| Entry {
+                    name: entry.file_name().to_string_lossy().into_owned(),
+                    is_dir: entry.path().is_dir(),
+                    path: entry.path(),
+                })
+                .filter(|entry| !entry.name.starts_with('.'))
+                .collect(),
+            Err(err) => {
+                self.message = Some(format!("unable to read {}: {}", dir.display(), err));
+                return;
+            }
+        };
+        entries.sort_by(|a, b| (!a.is_dir, &a.name).cmp(&(!b.is_dir, &b.name)));
+        if let Some(parent) = dir.parent() {
+            entries.insert(
+                0,
+                Entry {
+                    name: "..".to_string(),
+                    path: parent.to_path_buf(),
+                    is_dir: true,
+                },
+            );
+        }
+        self.dir = dir;
+        self.entries = entries;
+        self.selected = 0;
+    }
+}
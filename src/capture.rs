@@ -0,0 +1,150 @@
+// png screenshots and gif recordings of the framebuffer, drawn with a
+// palette and scaled up by a whole factor. needs no window so it works headless.
+use crate::palette::Palette;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+// gif delays are in hundredths of a second, frames come in at 60hz
+const FRAMES_PER_SECOND: u32 = 60;
+
+pub fn save_png(
+    path: &Path,
+    gfx: &[bool],
+    width: usize,
+    height: usize,
+    palette: &Palette,
+    scale: u32,
+) -> io::Result<()> {
+    let scale = scale as usize;
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        (width * scale) as u32,
+        (height * scale) as u32,
+    );
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+
+    let mut data = Vec::with_capacity(width * height * scale * scale * 3);
+    for index in scale_up(gfx, width, height, scale) {
+        let colour = match index {
+            0 => palette.background,
+            _ => palette.foreground,
+        };
+        data.extend_from_slice(&colour);
+    }
+    writer.write_image_data(&data).map_err(io::Error::other)
+}
+
+// writes an animated gif, a frame is only written once the screen changes so
+// static screens cost a single frame
+pub struct Recorder {
+    encoder: gif::Encoder<BufWriter<File>>,
+    width: usize,
+    height: usize,
+    scale: usize,
+    // last frame seen and for how many 60hz frames it has been shown
+    pending: Option<(Vec<bool>, u32)>,
+    // rounding left over from previous delays, in 6000ths of a second
+    carry: u32,
+}
+
+impl Recorder {
+    pub fn create(
+        path: &Path,
+        width: usize,
+        height: usize,
+        palette: &Palette,
+        scale: u32,
+    ) -> io::Result<Recorder> {
+        let scale = scale as usize;
+        let mut colours = Vec::with_capacity(6);
+        colours.extend_from_slice(&palette.background);
+        colours.extend_from_slice(&palette.foreground);
+        let mut encoder = gif::Encoder::new(
+            BufWriter::new(File::create(path)?),
+            (width * scale) as u16,
+            (height * scale) as u16,
+            &colours,
+        )
+        .map_err(io::Error::other)?;
+        encoder
+            .set_repeat(gif::Repeat::Infinite)
+            .map_err(io::Error::other)?;
+        Ok(Recorder {
+            encoder,
+            width,
+            height,
+            scale,
+            pending: None,
+            carry: 0,
+        })
+    }
+
+    // called once per displayed frame
+    pub fn add_frame(&mut self, gfx: &[bool]) -> io::Result<()> {
+        match self.pending.as_mut() {
+            Some((last, shown)) if last.as_slice() == gfx => {
+                *shown += 1;
+                return Ok(());
+            }
+            _ => {}
+        }
+        self.flush()?;
+        self.pending = Some((gfx.to_vec(), 1));
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.flush()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let (gfx, shown) = match self.pending.take() {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+        // convert to hundredths, keeping the remainder so long recordings don't drift
+        let total = shown * 100 + self.carry;
+        let delay = total / FRAMES_PER_SECOND;
+        self.carry = total % FRAMES_PER_SECOND;
+
+        let pixels = scale_up(&gfx, self.width, self.height, self.scale);
+        let mut frame = gif::Frame::from_indexed_pixels(
+            (self.width * self.scale) as u16,
+            (self.height * self.scale) as u16,
+            pixels,
+            None,
+        );
+        frame.delay = delay.min(u16::MAX as u32) as u16;
+        self.encoder.write_frame(&frame).map_err(io::Error::other)
+    }
+}
+
+// one palette index (0 background, 1 foreground) per output pixel, row by row
+fn scale_up(gfx: &[bool], width: usize, height: usize, scale: usize) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(width * height * scale * scale);
+    for row in gfx.chunks(width).take(height) {
+        for _ in 0..scale {
+            for &pixel in row {
+                pixels.extend(std::iter::repeat_n(pixel as u8, scale));
+            }
+        }
+    }
+    pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scale_up() {
+        let gfx = [true, false, false, true];
+        assert_eq!(
+            scale_up(&gfx, 2, 2, 2),
+            vec![1, 1, 0, 0, 1, 1, 0, 0, 0, 0, 1, 1, 0, 0, 1, 1]
+        );
+    }
+}
@@ -0,0 +1,177 @@
+// finding and freezing values in memory, e.g. to keep the number of lives
+// from going down. a search starts with every address as a candidate and
+// narrows them down by comparing memory with the previous step.
+use crate::chip8::Chip8;
+
+// what a cheat keeps at its value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Memory(u16),
+    Register(usize),
+}
+
+// "0x3f0=3" or "v3=0x05", the address and value are hex with 0x or decimal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cheat {
+    pub target: Target,
+    pub value: u8,
+}
+
+impl Cheat {
+    pub fn parse(s: &str) -> Result<Cheat, String> {
+        let invalid = || format!("invalid cheat {}, expected ADDRESS=VALUE or vX=VALUE", s);
+        let (target, value) = s.split_once('=').ok_or_else(invalid)?;
+        let target = target.trim().to_lowercase();
+        let target = match target.strip_prefix('v') {
+            Some(x) => match usize::from_str_radix(x, 16) {
+                Ok(x) if x < 16 => Target::Register(x),
+                _ => return Err(invalid()),
+            },
+            None => match parse_number(&target) {
+                Some(address) if address < 0x1000 => Target::Memory(address),
+                _ => return Err(invalid()),
+            },
+        };
+        match parse_number(value.trim()) {
+            Some(value) if value <= 0xff => Ok(Cheat {
+                target,
+                value: value as u8,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+
+    // called every frame before the rom runs
+    pub fn apply(&self, chip: &mut Chip8) {
+        match self.target {
+            Target::Memory(address) => chip.poke(address, self.value),
+            Target::Register(x) => chip.set_register(x, self.value),
+        }
+    }
+}
+
+// written back the way Cheat::parse reads it
+impl std::fmt::Display for Cheat {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.target {
+            Target::Memory(address) => write!(f, "{:#05x}={}", address, self.value),
+            Target::Register(x) => write!(f, "v{:x}={}", x, self.value),
+        }
+    }
+}
+
+// how a search step compares memory now with memory at the previous step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    Equal(u8),
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+}
+
+impl Condition {
+    fn matches(self, before: u8, now: u8) -> bool {
+        match self {
+            Condition::Equal(value) => now == value,
+            Condition::Changed => now != before,
+            Condition::Unchanged => now == before,
+            Condition::Increased => now > before,
+            Condition::Decreased => now < before,
+        }
+    }
+}
+
+pub struct Search {
+    candidates: Vec<u16>,
+    // memory at the previous step
+    snapshot: [u8; 4096],
+}
+
+impl Search {
+    pub fn new(chip: &Chip8) -> Search {
+        Search {
+            candidates: (0..0x1000).collect(),
+            snapshot: *chip.memory(),
+        }
+    }
+
+    // keeps the candidates that match and remembers memory for the next step
+    pub fn step(&mut self, chip: &Chip8, condition: Condition) {
+        let memory = chip.memory();
+        let snapshot = &self.snapshot;
+        self.candidates.retain(|&address| {
+            let address = address as usize;
+            condition.matches(snapshot[address], memory[address])
+        });
+        self.snapshot = *memory;
+    }
+
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+
+    // the value of a candidate at the previous step
+    pub fn previous(&self, address: u16) -> u8 {
+        self.snapshot[address as usize & 0xfff]
+    }
+}
+
+fn parse_number(s: &str) -> Option<u16> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cheat() {
+        let lives = Cheat::parse("0x3f0=3").unwrap();
+        assert_eq!(lives.target, Target::Memory(0x3f0));
+        assert_eq!(Cheat::parse(&lives.to_string()), Ok(lives));
+        assert_eq!(
+            Cheat::parse("VA = 0x10"),
+            Ok(Cheat {
+                target: Target::Register(0xa),
+                value: 0x10
+            })
+        );
+        assert!(Cheat::parse("0x1000=1").is_err());
+        assert!(Cheat::parse("v3=256").is_err());
+        assert!(Cheat::parse("v3").is_err());
+
+        let mut chip = Chip8::load(vec![0x63, 0x2a]).unwrap();
+        lives.apply(&mut chip);
+        Cheat::parse("v3=9").unwrap().apply(&mut chip);
+        assert_eq!(chip.memory()[0x3f0], 3);
+        assert_eq!(chip.registers()[3], 9);
+    }
+
+    #[test]
+    fn test_search() {
+        let mut chip = Chip8::load(vec![]).unwrap();
+        chip.poke(0x300, 5);
+        chip.poke(0x301, 5);
+        let mut search = Search::new(&chip);
+
+        search.step(&chip, Condition::Equal(5));
+        assert_eq!(search.candidates(), &[0x300, 0x301]);
+
+        chip.poke(0x300, 4);
+        search.step(&chip, Condition::Decreased);
+        assert_eq!(search.candidates(), &[0x300]);
+        assert_eq!(search.previous(0x300), 4);
+
+        search.step(&chip, Condition::Unchanged);
+        assert_eq!(search.candidates(), &[0x300]);
+        chip.poke(0x300, 6);
+        search.step(&chip, Condition::Increased);
+        assert_eq!(search.candidates(), &[0x300]);
+        search.step(&chip, Condition::Changed);
+        assert!(search.candidates().is_empty());
+    }
+}
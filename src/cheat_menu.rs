@@ -0,0 +1,184 @@
+use crate::overlay::Panel;
+use crate::Settings;
+use chip8::cheat::{Cheat, Condition, Search, Target};
+use chip8::Chip8;
+use sdl2::keyboard::Keycode;
+
+// candidates are only listed once the search has narrowed them down to this many
+const LISTED: usize = 64;
+
+// searches memory for a value between frames and freezes what it finds. the
+// search is kept by the session so it goes on after the menu is closed and
+// the rom has run for a while.
+pub struct CheatMenu {
+    selected: usize,
+    // decimal digits typed so far, the value to search for
+    typed: String,
+    message: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+enum Row {
+    Status,
+    Candidate(u16),
+    Frozen,
+    Cheat(usize),
+}
+
+impl CheatMenu {
+    pub fn new() -> CheatMenu {
+        CheatMenu {
+            selected: 0,
+            typed: String::new(),
+            message: None,
+        }
+    }
+
+    // returns false once the menu is closed
+    pub fn key_down(
+        &mut self,
+        keycode: Keycode,
+        search: &mut Option<Search>,
+        settings: &mut Settings,
+        chip: &Chip8,
+    ) -> bool {
+        self.message = None;
+        if let Some(digit) = decimal_digit(keycode) {
+            if self.typed.len() < 3 {
+                self.typed.push(digit);
+            }
+            return true;
+        }
+        let listed = rows(search, settings);
+        let last = listed.len() - 1;
+        match keycode {
+            Keycode::Escape | Keycode::F3 => return false,
+            Keycode::Up => self.selected = self.selected.saturating_sub(1),
+            Keycode::Down => self.selected = (self.selected + 1).min(last),
+            Keycode::PageUp => self.selected = self.selected.saturating_sub(10),
+            Keycode::PageDown => self.selected = (self.selected + 10).min(last),
+            Keycode::N => {
+                *search = Some(Search::new(chip));
+                self.message = Some("new search, all of memory".to_string());
+            }
+            Keycode::Return if !self.typed.is_empty() => {
+                match self.typed.parse::<u8>() {
+                    Ok(value) => self.step(search, chip, Condition::Equal(value)),
+                    Err(_) => self.message = Some(format!("{} is not a byte", self.typed)),
+                }
+                self.typed.clear();
+            }
+            Keycode::Return => {
+                if let Some(Row::Candidate(address)) = listed.get(self.selected) {
+                    let cheat = Cheat {
+                        target: Target::Memory(*address),
+                        value: chip.memory()[*address as usize],
+                    };
+                    self.message = Some(format!("froze {}", cheat));
+                    settings.cheats.push(cheat);
+                }
+            }
+            Keycode::Backspace if !self.typed.is_empty() => {
+                self.typed.pop();
+            }
+            Keycode::Backspace | Keycode::Delete => {
+                if let Some(Row::Cheat(index)) = listed.get(self.selected) {
+                    let cheat = settings.cheats.remove(*index);
+                    self.message = Some(format!("unfroze {}", cheat));
+                }
+            }
+            Keycode::C => self.step(search, chip, Condition::Changed),
+            Keycode::U => self.step(search, chip, Condition::Unchanged),
+            Keycode::I => self.step(search, chip, Condition::Increased),
+            Keycode::D => self.step(search, chip, Condition::Decreased),
+            _ => {}
+        }
+        self.selected = self.selected.min(rows(search, settings).len() - 1);
+        true
+    }
+
+    pub fn panel(&self, search: &Option<Search>, settings: &Settings, chip: &Chip8) -> Panel {
+        let items = rows(search, settings)
+            .into_iter()
+            .map(|row| match row {
+                Row::Status => match search {
+                    None => "no search, type a value or press n".to_string(),
+                    Some(search) if search.candidates().len() > LISTED => {
+                        format!("{} candidates", search.candidates().len())
+                    }
+                    Some(search) => format!("{} candidates:", search.candidates().len()),
+                },
+                Row::Candidate(address) => format!(
+                    "  {:03x}: {} (was {})",
+                    address,
+                    chip.memory()[address as usize],
+                    search.as_ref().map_or(0, |search| search.previous(address))
+                ),
+                Row::Frozen => "frozen:".to_string(),
+                Row::Cheat(index) => format!("  {}", settings.cheats[index]),
+            })
+            .collect();
+        let footer = match (&self.message, self.typed.is_empty()) {
+            (Some(message), _) => message.clone(),
+            (None, false) => format!("search for {}, return to go", self.typed),
+            (None, true) => "0-9: value  c/u/i/d: changed/same/up/down  return: freeze".to_string(),
+        };
+        Panel {
+            title: format!("cheats - {}", settings.title),
+            items,
+            selected: self.selected,
+            footer,
+            marks: Vec::new(),
+        }
+    }
+
+    // a step without a search only starts one, there is nothing to compare with yet
+    fn step(&mut self, search: &mut Option<Search>, chip: &Chip8, condition: Condition) {
+        match search {
+            Some(search) => search.step(chip, condition),
+            None if matches!(condition, Condition::Equal(_)) => {
+                let mut started = Search::new(chip);
+                started.step(chip, condition);
+                *search = Some(started);
+            }
+            None => {
+                *search = Some(Search::new(chip));
+                self.message = Some("search started, play on and compare again".to_string());
+            }
+        }
+    }
+}
+
+fn rows(search: &Option<Search>, settings: &Settings) -> Vec<Row> {
+    let mut rows = vec![Row::Status];
+    if let Some(search) = search {
+        if search.candidates().len() <= LISTED {
+            rows.extend(
+                search
+                    .candidates()
+                    .iter()
+                    .map(|&address| Row::Candidate(address)),
+            );
+        }
+    }
+    rows.push(Row::Frozen);
+    rows.extend((0..settings.cheats.len()).map(Row::Cheat));
+    rows
+}
+
+fn decimal_digit(keycode: Keycode) -> Option<char> {
+    let digit = match keycode {
+        Keycode::Num0 | Keycode::Kp0 => '0',
+        Keycode::Num1 | Keycode::Kp1 => '1',
+        Keycode::Num2 | Keycode::Kp2 => '2',
+        Keycode::Num3 | Keycode::Kp3 => '3',
+        Keycode::Num4 | Keycode::Kp4 => '4',
+        Keycode::Num5 | Keycode::Kp5 => '5',
+        Keycode::Num6 | Keycode::Kp6 => '6',
+        Keycode::Num7 | Keycode::Kp7 => '7',
+        Keycode::Num8 | Keycode::Kp8 => '8',
+        Keycode::Num9 | Keycode::Kp9 => '9',
+        _ => return None,
+    };
+    Some(digit)
+}
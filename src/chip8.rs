@@ -1,9 +1,117 @@
 extern crate rand;
 
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 use std::time::Instant;
 
+mod cached;
+use self::cached::Op;
+#[cfg(feature = "jit")]
+mod jit;
+
 const PROGRAM_START_LOCATION: usize = 0x200;
+// first bytes of a save state, the digit is bumped when the layout changes
+const SAVE_STATE_MAGIC: &[u8; 4] = b"C8S1";
+// magic, opcode, v, index, pc, sp and timers, stack, memory, gfx, flags
+const SAVE_STATE_LEN: usize = 4 + 2 + 16 + 2 + 2 + 3 + 32 + 4096 + 2048 + 2;
+
+// errors the interpreter reports instead of panicking, pc is the address of
+// the instruction that caused them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip8Error {
+    ProgramTooLarge { size: usize },
+    StackOverflow { pc: u16 },
+    StackUnderflow { pc: u16 },
+    MemoryOutOfBounds { pc: u16, address: usize },
+    InvalidSaveState,
+    // the lockstep engine found the jit disagreeing with the interpreter
+    // about the block starting at pc
+    Diverged { pc: u16 },
+}
+
+impl std::fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Chip8Error::ProgramTooLarge { size } => write!(
+                f,
+                "program is {} bytes, only {} fit in memory",
+                size,
+                4096 - PROGRAM_START_LOCATION
+            ),
+            Chip8Error::StackOverflow { pc } => write!(f, "stack overflow at {:#05x}", pc),
+            Chip8Error::StackUnderflow { pc } => write!(f, "stack underflow at {:#05x}", pc),
+            Chip8Error::MemoryOutOfBounds { pc, address } => write!(
+                f,
+                "memory access out of bounds at {:#05x} (address {:#x})",
+                pc, address
+            ),
+            Chip8Error::InvalidSaveState => write!(f, "not a save state of this version"),
+            Chip8Error::Diverged { pc } => write!(
+                f,
+                "the jit and the interpreter disagree on the block at {:#05x}",
+                pc
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {}
+
+// behaviours that differ between chip8 platforms, named like the quirks in
+// the chip-8-database. the defaults match what this interpreter always did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Quirks {
+    // 8XY6/8XYE shift vx in place instead of shifting vy into vx
+    pub shift: bool,
+    // FX55/FX65 increment i by x instead of x + 1
+    pub memory_increment_by_x: bool,
+    // FX55/FX65 leave i unchanged
+    pub memory_leave_i_unchanged: bool,
+    // sprites wrap around the screen edges instead of being clipped
+    pub wrap: bool,
+    // BNNN jumps to vx + nnn (BXNN) instead of v0 + nnn
+    pub jump: bool,
+    // DXYN waits for the next frame before execution continues
+    pub vblank: bool,
+    // 8XY1/8XY2/8XY3 reset vf to 0
+    pub logic: bool,
+    // levels of subroutine calls, 12 on the COSMAC VIP and 16 on the
+    // interpreters after it. at most 16.
+    pub stack_depth: u8,
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks {
+            shift: true,
+            memory_increment_by_x: false,
+            memory_leave_i_unchanged: true,
+            wrap: true,
+            jump: false,
+            vblank: false,
+            logic: false,
+            stack_depth: 16,
+        }
+    }
+}
+
+// how instructions are run. the reference interpreter decodes every
+// instruction as it runs it and prints what it does, the cached engine keeps
+// the decoded instructions until memory under them is written and prints
+// nothing, for running roms in bulk. with the jit feature the jit compiles
+// what it can to x86-64 code, and the lockstep engine checks every block it
+// runs against the cached engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Engine {
+    #[default]
+    Reference,
+    Cached,
+    #[cfg(feature = "jit")]
+    Jit,
+    #[cfg(feature = "jit")]
+    Lockstep,
+}
 
 #[allow(dead_code)]
 pub struct Chip8 {
@@ -33,17 +141,37 @@ pub struct Chip8 {
     memory: [u8; 4096],
     // 64*32 pixel display, top left is (0,0) bottom right is (63, 31)
     pub gfx: [bool; 64 * 32],
+    // false after a clear or a sprite draw that erased pixels, until a draw
+    // that only turns pixels on. used to skip frames that are mid redraw.
+    settled: bool,
 
     //keyboard has 16 keys, the array is used to indicate the state of each key
     keyboard: [bool; 16],
+    // the key FX0A saw pressed, it is stored in vx once it is let go
+    held_key: Option<u8>,
+
+    // SCHIP RPL user flags written by FX75 and read by FX85. the HP48 kept
+    // them between runs, SCHIP has 8 of them and XO-CHIP 16.
+    rpl_flags: [u8; 16],
+
+    quirks: Quirks,
+    // set by DXYN with the vblank quirk, no instructions run until Chip8::vblank
+    waiting_for_vblank: bool,
+
+    engine: Engine,
+    // what the cached engine decoded at each address
+    decoded: Box<[Op]>,
+    // the compiled blocks, made the first time the jit runs
+    #[cfg(feature = "jit")]
+    jit: Option<Box<jit::Jit>>,
 }
 
 #[allow(dead_code)]
 impl Chip8 {
-    pub fn load(program: Vec<u8>) -> Chip8 {
+    pub fn load(program: Vec<u8>) -> Result<Chip8, Chip8Error> {
         let mut chip = Chip8::new();
-        chip.load_instructions(&program[..]);
-        chip
+        chip.load_instructions(&program[..])?;
+        Ok(chip)
     }
 
     pub fn new() -> Chip8 {
@@ -57,10 +185,19 @@ impl Chip8 {
             sp: 0,
             stack: [0u16; 16],
             gfx: [false; 64 * 32],
+            settled: true,
             memory: Chip8::init_memory(),
             keyboard: [false; 16],
+            held_key: None,
             delay_set_time: None,
             sound_set_time: None,
+            rpl_flags: [0; 16],
+            quirks: Quirks::default(),
+            waiting_for_vblank: false,
+            engine: Engine::default(),
+            decoded: vec![Op::Undecoded; 4096].into_boxed_slice(),
+            #[cfg(feature = "jit")]
+            jit: None,
         }
     }
 
@@ -86,69 +223,195 @@ impl Chip8 {
         // load 4 digit
         mem[0x14..0x19].copy_from_slice(&[0x90, 0x90, 0xf0, 0x10, 0x10]);
 
-        // load 4 digit
-        mem[0x19..0x1e].copy_from_slice(&[0x90, 0x90, 0xf0, 0x10, 0x10]);
-
         // load 5 digit
-        mem[0x1e..0x23].copy_from_slice(&[0xf0, 0x80, 0xf0, 0x10, 0xf0]);
+        mem[0x19..0x1e].copy_from_slice(&[0xf0, 0x80, 0xf0, 0x10, 0xf0]);
 
         // load 6 digit
-        mem[0x23..0x28].copy_from_slice(&[0xf0, 0x80, 0xf0, 0x90, 0xf0]);
+        mem[0x1e..0x23].copy_from_slice(&[0xf0, 0x80, 0xf0, 0x90, 0xf0]);
 
         // load 7 digit
-        mem[0x28..0x2d].copy_from_slice(&[0xf0, 0x10, 0x20, 0x40, 0x40]);
+        mem[0x23..0x28].copy_from_slice(&[0xf0, 0x10, 0x20, 0x40, 0x40]);
 
         // load 8 digit
-        mem[0x2d..0x32].copy_from_slice(&[0xf0, 0x90, 0xf0, 0x90, 0xf0]);
+        mem[0x28..0x2d].copy_from_slice(&[0xf0, 0x90, 0xf0, 0x90, 0xf0]);
 
         // load 9 digit
-        mem[0x32..0x37].copy_from_slice(&[0xf0, 0x90, 0xf0, 0x10, 0xf0]);
+        mem[0x2d..0x32].copy_from_slice(&[0xf0, 0x90, 0xf0, 0x10, 0xf0]);
 
         // load a digit
-        mem[0x37..0x3c].copy_from_slice(&[0xf0, 0x90, 0xf0, 0x90, 0x90]);
+        mem[0x32..0x37].copy_from_slice(&[0xf0, 0x90, 0xf0, 0x90, 0x90]);
 
         // load b digit
-        mem[0x3c..0x41].copy_from_slice(&[0xe0, 0x90, 0xe0, 0x90, 0xe0]);
+        mem[0x37..0x3c].copy_from_slice(&[0xe0, 0x90, 0xe0, 0x90, 0xe0]);
 
         // load c digit
-        mem[0x41..0x46].copy_from_slice(&[0xf0, 0x80, 0x80, 0x90, 0xf0]);
+        mem[0x3c..0x41].copy_from_slice(&[0xf0, 0x80, 0x80, 0x80, 0xf0]);
 
         // load d digit
-        mem[0x46..0x4b].copy_from_slice(&[0xe0, 0x90, 0x90, 0x90, 0xe0]);
+        mem[0x41..0x46].copy_from_slice(&[0xe0, 0x90, 0x90, 0x90, 0xe0]);
 
         // load e digit
-        mem[0x4b..0x50].copy_from_slice(&[0xf0, 0x80, 0xf0, 0x80, 0xf0]);
+        mem[0x46..0x4b].copy_from_slice(&[0xf0, 0x80, 0xf0, 0x80, 0xf0]);
 
         // load f digit
-        mem[0x50..0x55].copy_from_slice(&[0xf0, 0x80, 0xf0, 0x80, 0x80]);
+        mem[0x4b..0x50].copy_from_slice(&[0xf0, 0x80, 0xf0, 0x80, 0x80]);
     }
 
     pub fn test_drawing(&mut self) {
         for i in 0..=0xf {
             self.opcode = 0xf029;
             self.v[0] = i;
-            self.process_opcode();
+            self.process_opcode().unwrap();
             self.opcode = 0xd015;
-            self.v[0] = 0x3 + (i as u8).wrapping_mul(0x10);
-            println!("{}: {}", i, i/5);
-            self.v[1] = 0x3 + (i/4 as u8).wrapping_mul(0x6);
-            self.process_opcode();
+            self.v[0] = 0x3 + i.wrapping_mul(0x10);
+            println!("{}: {}", i, i / 5);
+            self.v[1] = 0x3 + (i / 4).wrapping_mul(0x6);
+            self.process_opcode().unwrap();
         }
     }
 
-    pub fn load_instructions(&mut self, instructions: &[u8]) {
+    // soft reset: back to the state after loading, with memory left as it is
+    // so the rom (and anything it wrote there) stays. quirks, rpl flags and
+    // the engine are kept.
+    pub fn reset(&mut self) {
+        let memory = self.memory;
+        *self = Chip8 {
+            memory,
+            quirks: self.quirks,
+            rpl_flags: self.rpl_flags,
+            engine: self.engine,
+            ..Chip8::new()
+        };
+    }
+
+    pub fn load_instructions(&mut self, instructions: &[u8]) -> Result<(), Chip8Error> {
         let program_memory = &mut self.memory[PROGRAM_START_LOCATION..];
-        program_memory[..instructions.len()].copy_from_slice(&instructions);
+        if instructions.len() > program_memory.len() {
+            return Err(Chip8Error::ProgramTooLarge {
+                size: instructions.len(),
+            });
+        }
+        program_memory[..instructions.len()].copy_from_slice(instructions);
+        self.invalidate_all();
+        Ok(())
+    }
+
+    // mark one of the 16 keypad keys (0x0 - 0xf) as pressed or released
+    pub fn set_key(&mut self, key: usize, pressed: bool) {
+        self.keyboard[key] = pressed;
+    }
+
+    // the RPL user flags, so a frontend can keep them between runs like the HP48 did
+    pub fn rpl_flags(&self) -> [u8; 16] {
+        self.rpl_flags
+    }
+
+    pub fn set_rpl_flags(&mut self, flags: [u8; 16]) {
+        self.rpl_flags = flags;
+    }
+
+    // read access to the machine for debuggers and tools
+    pub fn memory(&self) -> &[u8; 4096] {
+        &self.memory
+    }
+
+    pub fn registers(&self) -> [u8; 16] {
+        self.v
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
+
+    // the return addresses of the subroutines being run, innermost last
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..(self.sp as usize).min(self.stack.len())]
+    }
+
+    // how many calls deep the rom can go before a stack overflow
+    pub fn stack_depth(&self) -> usize {
+        (self.quirks.stack_depth as usize).clamp(1, self.stack.len())
+    }
+
+    // the vblank quirk holds the chip after a draw until the next frame
+    pub fn waiting_for_vblank(&self) -> bool {
+        self.waiting_for_vblank
+    }
+
+    // write access for debuggers, addresses wrap around the 4 KiB of memory
+    pub fn poke(&mut self, address: u16, value: u8) {
+        self.memory[address as usize & 0xfff] = value;
+        self.invalidate(address as usize & 0xfff, 1);
+    }
+
+    pub fn set_register(&mut self, x: usize, value: u8) {
+        self.v[x] = value;
+    }
+
+    pub fn set_index(&mut self, index: u16) {
+        self.index = index;
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc & 0xfff;
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+        // blocks are compiled for the quirks they were made with
+        #[cfg(feature = "jit")]
+        if let Some(jit) = &mut self.jit {
+            jit.flush();
+        }
+    }
+
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.engine = engine;
+    }
+
+    // called by the frontend at the start of every frame
+    pub fn vblank(&mut self) {
+        self.waiting_for_vblank = false;
+    }
+
+    // runs the next instruction for a debugger single stepping the rom. no
+    // frame starts while it is paused, so a draw waiting for one is let go
+    // first instead of holding the chip on the instruction after it.
+    pub fn step(&mut self) -> Result<(), Chip8Error> {
+        self.vblank();
+        self.cycle()
+    }
+
+    // resolution of gfx as (width, height)
+    pub fn screen_size(&self) -> (usize, usize) {
+        (64, 32)
+    }
+
+    // whether the display is not in the middle of being redrawn, see Chip8::settled
+    pub fn display_settled(&self) -> bool {
+        self.settled
     }
 
     pub fn get_sound_timer(&self) -> u8 {
         if let Some(elapsed) = self.sound_set_time {
             let delta = (elapsed.elapsed().as_millis() * 60 / 1000) as u8;
-            if delta > self.sound_timer {
-                0
-            } else {
-                self.sound_timer - delta
-            }
+            self.sound_timer.saturating_sub(delta)
         } else {
             0
         }
@@ -157,34 +420,192 @@ impl Chip8 {
     pub fn get_delay_timer(&self) -> u8 {
         if let Some(elapsed) = self.delay_set_time {
             let delta = (elapsed.elapsed().as_millis() * 60 / 1000) as u8;
-            if delta > self.delay_timer {
-                0
-            } else {
-                self.delay_timer - delta
-            }
+            self.delay_timer.saturating_sub(delta)
         } else {
             0
         }
     }
 
-    pub fn cycle(&mut self) {
+    // the machine as bytes for a save state. keys, quirks and rpl flags are
+    // left out as the frontend owns them, timers are stored as their current value.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(SAVE_STATE_LEN);
+        state.extend_from_slice(SAVE_STATE_MAGIC);
+        state.extend_from_slice(&self.opcode.to_be_bytes());
+        state.extend_from_slice(&self.v);
+        state.extend_from_slice(&self.index.to_be_bytes());
+        state.extend_from_slice(&self.pc.to_be_bytes());
+        state.extend_from_slice(&[self.sp, self.get_delay_timer(), self.get_sound_timer()]);
+        for address in self.stack.iter() {
+            state.extend_from_slice(&address.to_be_bytes());
+        }
+        state.extend_from_slice(&self.memory);
+        state.extend(self.gfx.iter().map(|&pixel| pixel as u8));
+        state.extend_from_slice(&[self.settled as u8, self.waiting_for_vblank as u8]);
+        state
+    }
+
+    // restores a state from save_state, the chip is left untouched when it is invalid
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), Chip8Error> {
+        if state.len() != SAVE_STATE_LEN || &state[..4] != SAVE_STATE_MAGIC {
+            return Err(Chip8Error::InvalidSaveState);
+        }
+        // pc comes after the magic, opcode, v and index, sp right after it.
+        // both are checked before anything is restored, the next fetch reads
+        // two bytes at pc.
+        let pc = u16::from_be_bytes([state[24], state[25]]);
+        if pc > 0xffe || state[26] as usize > self.stack.len() {
+            return Err(Chip8Error::InvalidSaveState);
+        }
+        let mut rest = &state[4..];
+        let mut take = |len: usize| {
+            let (bytes, after) = rest.split_at(len);
+            rest = after;
+            bytes
+        };
+        let word = |bytes: &[u8]| u16::from_be_bytes([bytes[0], bytes[1]]);
+
+        self.opcode = word(take(2));
+        self.v.copy_from_slice(take(16));
+        self.index = word(take(2));
+        self.pc = word(take(2));
+        let bytes = take(3);
+        self.sp = bytes[0];
+        self.delay_timer = bytes[1];
+        self.sound_timer = bytes[2];
+        self.delay_set_time = Some(Instant::now());
+        self.sound_set_time = Some(Instant::now());
+        for address in self.stack.iter_mut() {
+            *address = word(take(2));
+        }
+        self.memory.copy_from_slice(take(4096));
+        self.invalidate_all();
+        for (pixel, &byte) in self.gfx.iter_mut().zip(take(2048)) {
+            *pixel = byte != 0;
+        }
+        let bytes = take(2);
+        self.settled = bytes[0] != 0;
+        self.waiting_for_vblank = bytes[1] != 0;
+        // not saved, FX0A waits for a whole key press again
+        self.held_key = None;
+        Ok(())
+    }
+
+    // FX0A waits for a key to be pressed and released like on the COSMAC VIP.
+    // returns the key once it is let go, None while FX0A has to run again.
+    fn wait_key(&mut self) -> Option<u8> {
+        match self.held_key {
+            Some(key) if !self.keyboard[key as usize] => {
+                self.held_key = None;
+                Some(key)
+            }
+            Some(_) => None,
+            None => {
+                let pressed = self.keyboard.iter().position(|&pressed| pressed);
+                self.held_key = pressed.map(|key| key as u8);
+                None
+            }
+        }
+    }
+
+    // runs one instruction with the engine that is set
+    pub fn cycle(&mut self) -> Result<(), Chip8Error> {
+        match self.engine {
+            Engine::Reference => self.interpret(),
+            Engine::Cached => self.cycle_cached(),
+            #[cfg(feature = "jit")]
+            Engine::Jit | Engine::Lockstep => self.run_jit(1).map(|_| ()),
+        }
+    }
+
+    // runs up to cycles instructions, stopping early when a draw waits for
+    // the next frame. returns how many ran.
+    pub fn run(&mut self, cycles: u32) -> Result<u32, Chip8Error> {
+        match self.engine {
+            Engine::Reference => {}
+            Engine::Cached => return self.run_cached(cycles),
+            #[cfg(feature = "jit")]
+            Engine::Jit | Engine::Lockstep => return self.run_jit(cycles),
+        }
+        for ran in 0..cycles {
+            if self.waiting_for_vblank {
+                return Ok(ran);
+            }
+            self.interpret()?;
+        }
+        Ok(cycles)
+    }
+
+    fn interpret(&mut self) -> Result<(), Chip8Error> {
+        if self.waiting_for_vblank {
+            return Ok(());
+        }
+        let pc = self.pc;
         // read current opcode from memory to self.opcode
-        self.opcode = ((self.memory[self.pc as usize] as u16) << 8)
-            + self.memory[self.pc as usize + 1] as u16;
-        // process opcode
-        self.process_opcode();
+        let opcode = self.read_memory(pc, pc as usize, 2)?;
+        self.opcode = ((opcode[0] as u16) << 8) + opcode[1] as u16;
+        // process opcode, on error leave pc on the failing instruction
+        let result = self.process_opcode();
+        if result.is_err() {
+            self.pc = pc;
+        }
+        result
     }
 
-    fn process_opcode(&mut self) {
+    // bounds checked view of len bytes of memory starting at address,
+    // pc is the instruction reported if the access is out of bounds
+    fn read_memory(&self, pc: u16, address: usize, len: usize) -> Result<&[u8], Chip8Error> {
+        self.memory
+            .get(address..address + len)
+            .ok_or_else(|| Chip8Error::MemoryOutOfBounds {
+                pc,
+                address: address + len - 1,
+            })
+    }
+
+    fn write_memory(
+        &mut self,
+        pc: u16,
+        address: usize,
+        len: usize,
+    ) -> Result<&mut [u8], Chip8Error> {
+        if address + len > self.memory.len() {
+            return Err(Chip8Error::MemoryOutOfBounds {
+                pc,
+                address: address + len - 1,
+            });
+        }
+        self.invalidate(address, len);
+        Ok(&mut self.memory[address..address + len])
+    }
+
+    // FX55/FX65 move i past the registers on the original interpreter
+    fn increment_index_after_memory(&mut self, x: usize) {
+        if self.quirks.memory_increment_by_x {
+            self.index = self.index.wrapping_add(x as u16);
+        } else if !self.quirks.memory_leave_i_unchanged {
+            self.index = self.index.wrapping_add(x as u16 + 1);
+        }
+    }
+
+    fn process_opcode(&mut self) -> Result<(), Chip8Error> {
         println!("processing opcode: {:#x?}", self.opcode);
+        let pc = self.pc;
         self.pc += 2;
         match self.opcode {
             0x00e0 => {
                 self.gfx = [false; 64 * 32];
+                self.settled = false;
                 println!("clear display");
             }
             0x00ee => {
-                self.pc = self.stack[(self.sp - 1) as usize];
+                if self.sp == 0 {
+                    return Err(Chip8Error::StackUnderflow { pc });
+                }
+                self.pc = *self
+                    .stack
+                    .get((self.sp - 1) as usize)
+                    .ok_or(Chip8Error::StackOverflow { pc })?;
                 self.sp -= 1;
                 println!("return from subroutine");
             }
@@ -201,6 +622,9 @@ impl Chip8 {
             }
             0x2000..=0x2fff => {
                 // initialize a new function routine
+                if self.sp as usize >= self.stack_depth() {
+                    return Err(Chip8Error::StackOverflow { pc });
+                }
                 self.sp += 1;
                 self.stack[(self.sp - 1) as usize] = self.pc;
                 self.pc = self.opcode & 0x0fff;
@@ -244,19 +668,22 @@ impl Chip8 {
                 println!("set v{} to {}", self.opcode & 0x0f00, self.opcode & 0x00ff);
             }
             0x7000..=0x7fff => {
-                self.v[((self.opcode & 0x0f00) >> 8) as usize]
-                    .wrapping_add((self.opcode & 0x00ff) as u8);
+                let x = ((self.opcode & 0x0f00) >> 8) as usize;
+                self.v[x] = self.v[x].wrapping_add((self.opcode & 0x00ff) as u8);
                 println!("set v{} to {}", self.opcode & 0x0f00, self.opcode & 0x00ff);
             }
             0x8000..=0x8fff => {
                 let x = ((self.opcode & 0x0f00) >> 8) as usize;
                 let y = ((self.opcode & 0x00f0) >> 4) as usize;
                 let n = self.opcode & 0x000f;
+                if !self.quirks.shift && (n == 6 || n == 0xe) {
+                    self.v[x] = self.v[y];
+                }
                 match n {
                     0 => self.v[x] = self.v[y],
-                    1 => self.v[x] = self.v[x] | self.v[y],
-                    2 => self.v[x] = self.v[x] & self.v[y],
-                    3 => self.v[x] = self.v[x] ^ self.v[y],
+                    1 => self.v[x] |= self.v[y],
+                    2 => self.v[x] &= self.v[y],
+                    3 => self.v[x] ^= self.v[y],
                     4 => {
                         let (result, has_overflown) = self.v[x].overflowing_add(self.v[y]);
                         self.v[x] = result;
@@ -265,33 +692,38 @@ impl Chip8 {
                             false => 0,
                         };
                     }
+                    // vf is written after vx so that it holds the flag when x is f
                     5 => {
-                        self.v[0xf] = match self.v[x] > self.v[y] {
+                        let no_borrow = self.v[x] >= self.v[y];
+                        self.v[x] = self.v[x].wrapping_sub(self.v[y]);
+                        self.v[0xf] = match no_borrow {
                             true => 1,
                             false => 0,
                         };
-                        self.v[x] = self.v[x].wrapping_sub(self.v[y]);
                     }
                     6 => {
-                        self.v[0xf] = self.v[x] & 0x0001;
-                        self.v[x] = self.v[x] >> 2;
+                        let shifted_out = self.v[x] & 0x01;
+                        self.v[x] >>= 1;
+                        self.v[0xf] = shifted_out;
                     }
                     7 => {
-                        self.v[0xf] = match self.v[y] > self.v[x] {
+                        let no_borrow = self.v[y] >= self.v[x];
+                        self.v[x] = self.v[y].wrapping_sub(self.v[x]);
+                        self.v[0xf] = match no_borrow {
                             true => 1,
                             false => 0,
                         };
-                        self.v[x] = self.v[y].wrapping_sub(self.v[x]);
                     }
                     0xe => {
-                        self.v[0xf] = match self.v[x] & 0x0008 != 0 {
-                            true => 1,
-                            false => 0,
-                        };
-                        self.v[x] = self.v[x] << 1;
+                        let shifted_out = self.v[x] >> 7;
+                        self.v[x] <<= 1;
+                        self.v[0xf] = shifted_out;
                     }
                     _ => {}
                 }
+                if self.quirks.logic && (1..=3).contains(&n) {
+                    self.v[0xf] = 0;
+                }
             }
             0x9000..=0x9fff => {
                 let x = ((self.opcode & 0x0f00) >> 8) as usize;
@@ -304,7 +736,11 @@ impl Chip8 {
                 self.index = self.opcode & 0x0fff;
             }
             0xb000..=0xbfff => {
-                self.pc = (self.v[0] as u16) + (self.opcode & 0x0fff);
+                let register = match self.quirks.jump {
+                    true => ((self.opcode & 0x0f00) >> 8) as usize,
+                    false => 0,
+                };
+                self.pc = (self.v[register] as u16) + (self.opcode & 0x0fff);
             }
             0xc000..=0xcfff => {
                 let mut rng = rand::thread_rng();
@@ -314,20 +750,27 @@ impl Chip8 {
             0xd000..=0xdfff => {
                 let x = self.v[((self.opcode & 0x0f00) >> 8) as usize];
                 let y = self.v[((self.opcode & 0x00f0) >> 4) as usize];
-                let n = self.opcode & 0x000f;
+                let n = (self.opcode & 0x000f) as usize;
 
-                let end_slice = (self.index + n) as usize;
-                let sprite = &self.memory[(self.index as usize)..end_slice];
+                let mut sprite = [0u8; 15];
+                sprite[..n].copy_from_slice(self.read_memory(pc, self.index as usize, n)?);
+                let sprite = &sprite[..n];
+                self.v[0xf] = 0;
                 for xx in 0..8 {
-                    for yy in 0..sprite.len() {
-                        let gx = (x as usize + xx) % 64;
-                        let gy = (y as usize + yy) % 32;
+                    for (yy, row) in sprite.iter().enumerate() {
+                        // the starting position always wraps, the rest of the sprite
+                        // is clipped at the edges unless the wrap quirk is set
+                        let (gx, gy) = (x as usize % 64 + xx, y as usize % 32 + yy);
+                        if !self.quirks.wrap && (gx >= 64 || gy >= 32) {
+                            continue;
+                        }
+                        let (gx, gy) = (gx % 64, gy % 32);
 
-                        let pixel = (sprite[yy] & (1 << (7 - xx))) != 0;
-                        if self.gfx[gy * 64 + gx] {
+                        let pixel = (row & (1 << (7 - xx))) != 0;
+                        if pixel && self.gfx[gy * 64 + gx] {
                             self.v[0xf] = 1;
                         }
-                        self.gfx[gy * 64 + gx] = self.gfx[gy * 64 + gx] ^ pixel;
+                        self.gfx[gy * 64 + gx] ^= pixel;
                         println!(
                             "{}: drawing at x: {}, y: {}",
                             self.gfx[gy * 64 + gx],
@@ -336,19 +779,17 @@ impl Chip8 {
                         );
                     }
                 }
+                self.settled = self.v[0xf] == 0;
+                self.waiting_for_vblank = self.quirks.vblank;
             }
             0xe000..=0xefff => {
-                let x = (self.opcode & 0x0f00 >> 8) as usize;
+                let x = ((self.opcode & 0x0f00) >> 8) as usize;
                 match self.opcode & 0x00ff {
-                    0x9e => {
-                        if self.keyboard[self.v[x] as usize] {
-                            self.pc += 2;
-                        }
+                    0x9e if self.keyboard[(self.v[x] & 0xf) as usize] => {
+                        self.pc += 2;
                     }
-                    0xa1 => {
-                        if !self.keyboard[self.v[x] as usize] {
-                            self.pc += 2;
-                        }
+                    0xa1 if !self.keyboard[(self.v[x] & 0xf) as usize] => {
+                        self.pc += 2;
                     }
                     _ => {
                         //panic!("unimplemented opcode: {}", self.opcode);
@@ -362,16 +803,11 @@ impl Chip8 {
                     0x07 => {
                         self.v[x] = self.get_delay_timer();
                     }
-                    0x0a => {
-                        // wait for a key press
-                        // TODO: add logic to suspend program execution while no key is pressed
-                        if self.keyboard.contains(&true) {
-                            self.v[x] = self.keyboard.iter().position(|&x| x).unwrap() as u8;
-                        } else {
-                            // return the program counter so it will stay on the current instruction if theres no key pressed
-                            self.pc -= 0;
-                        }
-                    }
+                    0x0a => match self.wait_key() {
+                        Some(key) => self.v[x] = key,
+                        // stay on the instruction until a key was pressed and released
+                        None => self.pc = pc,
+                    },
                     0x15 => {
                         self.delay_timer = self.v[x];
                         self.delay_set_time = Some(Instant::now());
@@ -381,7 +817,7 @@ impl Chip8 {
                         self.sound_set_time = Some(Instant::now());
                     }
                     0x1e => {
-                        self.index += self.v[x] as u16;
+                        self.index = self.index.wrapping_add(self.v[x] as u16);
                     }
                     0x29 => {
                         println!("{}: {}", x, self.v[x]);
@@ -392,17 +828,28 @@ impl Chip8 {
                         let tens = (self.v[x] / 10) % 10;
                         let ones = self.v[x] % 10;
 
-                        self.memory[self.index as usize] = hundrents;
-                        self.memory[self.index as usize + 1] = tens;
-                        self.memory[self.index as usize + 2] = ones;
+                        let i = self.index as usize;
+                        self.write_memory(pc, i, 3)?
+                            .copy_from_slice(&[hundrents, tens, ones]);
                     }
                     0x55 => {
                         let i = self.index as usize;
-                        self.memory[i..=(i + x)].copy_from_slice(&self.v[0..=x]);
+                        let v = self.v;
+                        self.write_memory(pc, i, x + 1)?.copy_from_slice(&v[0..=x]);
+                        self.increment_index_after_memory(x);
                     }
                     0x65 => {
                         let i = self.index as usize;
-                        self.v[0..=x].copy_from_slice(&self.memory[i..=(i + x)]);
+                        let mut v = self.v;
+                        v[0..=x].copy_from_slice(self.read_memory(pc, i, x + 1)?);
+                        self.v = v;
+                        self.increment_index_after_memory(x);
+                    }
+                    0x75 => {
+                        self.rpl_flags[0..=x].copy_from_slice(&self.v[0..=x]);
+                    }
+                    0x85 => {
+                        self.v[0..=x].copy_from_slice(&self.rpl_flags[0..=x]);
                     }
                     _ => {
                         //panic!("unimplemented opcode: {}", self.opcode);
@@ -410,6 +857,36 @@ impl Chip8 {
                 }
             }
         }
+        Ok(())
+    }
+}
+
+// lets fuzz targets start the interpreter from any state, the program area
+// is filled with whatever bytes are left over
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Chip8 {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Chip8> {
+        let mut chip = Chip8::new();
+        chip.v = u.arbitrary()?;
+        chip.index = u.arbitrary()?;
+        chip.pc = u.arbitrary()?;
+        // only stack pointers load_state accepts, others can't be reached
+        chip.sp = u.int_in_range(0..=chip.stack_depth() as u8)?;
+        chip.stack = u.arbitrary()?;
+        chip.delay_timer = u.arbitrary()?;
+        chip.sound_timer = u.arbitrary()?;
+        chip.keyboard = u.arbitrary()?;
+        chip.rpl_flags = u.arbitrary()?;
+        let program = u.bytes(u.len().min(4096 - PROGRAM_START_LOCATION))?;
+        chip.memory[PROGRAM_START_LOCATION..PROGRAM_START_LOCATION + program.len()]
+            .copy_from_slice(program);
+        Ok(chip)
+    }
+}
+
+impl Default for Chip8 {
+    fn default() -> Chip8 {
+        Chip8::new()
     }
 }
 
@@ -423,6 +900,8 @@ impl std::fmt::Debug for Chip8 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
+    use rand::Rng;
 
     #[test]
     fn test_jump_opcode() {
@@ -430,12 +909,12 @@ mod tests {
 
         // assert SYS addr opcode is working
         chip.opcode = 0x1111;
-        chip.process_opcode();
+        chip.process_opcode().unwrap();
         assert_eq!(chip.pc, 0x0111);
 
         // assert JP addr opcode is working
         chip.opcode = 0x0134;
-        chip.process_opcode();
+        chip.process_opcode().unwrap();
         assert_eq!(chip.pc, 0x0134);
     }
 
@@ -447,7 +926,7 @@ mod tests {
         chip.pc = 0x0111;
         let previous_pc = chip.pc;
         chip.opcode = 0x2123;
-        chip.process_opcode();
+        chip.process_opcode().unwrap();
         assert_eq!(chip.pc, 0x0123);
         assert_eq!(chip.sp, 1);
         assert_eq!(chip.stack[(chip.sp - 1) as usize], previous_pc + 2);
@@ -455,7 +934,7 @@ mod tests {
         // test returning from a subroutine
         chip.opcode = 0x00ee;
         chip.pc = 0x0111;
-        chip.process_opcode();
+        chip.process_opcode().unwrap();
         assert_eq!(chip.pc, 0x0111 + 2);
         assert_eq!(chip.sp, 0);
     }
@@ -468,12 +947,12 @@ mod tests {
         chip.pc = 0x0111;
         let previous_pc = chip.pc;
         chip.opcode = 0x3244;
-        chip.process_opcode();
+        chip.process_opcode().unwrap();
         assert_eq!(chip.pc, previous_pc + 2);
 
         chip.v[2] = 0x44;
         chip.pc = 0x0111;
-        chip.process_opcode();
+        chip.process_opcode().unwrap();
         assert_eq!(chip.pc, previous_pc + 4);
     }
 
@@ -486,13 +965,13 @@ mod tests {
         let previous_pc = chip.pc;
         chip.v[2] = 0x11;
         chip.opcode = 0x4244;
-        chip.process_opcode();
+        chip.process_opcode().unwrap();
         assert_eq!(chip.pc, previous_pc + 4);
 
         chip.pc = 0x0111;
         let previous_pc = chip.pc;
         chip.v[2] = 0x44;
-        chip.process_opcode();
+        chip.process_opcode().unwrap();
         assert_eq!(chip.pc, previous_pc + 2);
     }
 
@@ -507,7 +986,7 @@ mod tests {
         chip.v[2] = 0x11;
         chip.v[3] = 0x11;
         chip.opcode = 0x5230;
-        chip.process_opcode();
+        chip.process_opcode().unwrap();
         assert_eq!(chip.pc, previous_pc + 4);
 
         chip.pc = 0x0111;
@@ -515,7 +994,7 @@ mod tests {
         chip.v[2] = 0xff;
         chip.v[3] = 0x11;
         chip.opcode = 0x5230;
-        chip.process_opcode();
+        chip.process_opcode().unwrap();
         assert_eq!(chip.pc, previous_pc + 2);
     }
 
@@ -529,14 +1008,14 @@ mod tests {
         chip.v[2] = 0x11;
         chip.v[3] = 0x11;
         chip.opcode = 0x9230;
-        chip.process_opcode();
+        chip.process_opcode().unwrap();
         assert_eq!(chip.pc, previous_pc + 2);
 
         let previous_pc = chip.pc;
         chip.v[2] = 0xff;
         chip.v[3] = 0x11;
         chip.opcode = 0x9230;
-        chip.process_opcode();
+        chip.process_opcode().unwrap();
         assert_eq!(chip.pc, previous_pc + 4);
     }
 
@@ -548,7 +1027,7 @@ mod tests {
         // test skip equal
         chip.v[2] = 0x3;
         chip.opcode = 0x6240;
-        chip.process_opcode();
+        chip.process_opcode().unwrap();
         assert_eq!(chip.v[2], 0x40);
     }
 
@@ -561,7 +1040,7 @@ mod tests {
         chip.v[2] = 0x3;
         let previous_v2 = chip.v[2];
         chip.opcode = 0x7240;
-        chip.process_opcode();
+        chip.process_opcode().unwrap();
         assert_eq!(chip.v[2], previous_v2 + 0x40);
     }
 
@@ -578,7 +1057,7 @@ mod tests {
             chip.v[4] = rng.gen_range(0, 255);
             chip.v[2] = rng.gen_range(0, 255);
             chip.opcode = 0x8240;
-            chip.process_opcode();
+            chip.process_opcode().unwrap();
             assert_eq!(chip.v[2], chip.v[4]);
         }
 
@@ -588,7 +1067,7 @@ mod tests {
             chip.v[2] = rng.gen_range(0, 255);
             let previous_vx = chip.v[2];
             chip.opcode = 0x8241;
-            chip.process_opcode();
+            chip.process_opcode().unwrap();
             assert_eq!(chip.v[2], previous_vx | chip.v[4]);
         }
 
@@ -598,7 +1077,7 @@ mod tests {
             chip.v[2] = rng.gen_range(0, 255);
             let previous_vx = chip.v[2];
             chip.opcode = 0x8242;
-            chip.process_opcode();
+            chip.process_opcode().unwrap();
             assert_eq!(chip.v[2], previous_vx & chip.v[4]);
         }
 
@@ -608,7 +1087,7 @@ mod tests {
             chip.v[2] = rng.gen_range(0, 255);
             let previous_vx = chip.v[2];
             chip.opcode = 0x8243;
-            chip.process_opcode();
+            chip.process_opcode().unwrap();
             assert_eq!(chip.v[2], previous_vx ^ chip.v[4]);
         }
 
@@ -618,7 +1097,7 @@ mod tests {
             chip.v[2] = rng.gen_range(0, 255);
             let previous_vx = chip.v[2];
             chip.opcode = 0x8244;
-            chip.process_opcode();
+            chip.process_opcode().unwrap();
             let (res, carry) = previous_vx.overflowing_add(chip.v[4]);
             assert_eq!(chip.v[2], res);
             assert_eq!(
@@ -635,12 +1114,12 @@ mod tests {
             chip.v[4] = rng.gen_range(0, 255);
             chip.v[2] = rng.gen_range(0, 255);
             chip.opcode = 0x8245;
-            let vf = match chip.v[2] > chip.v[4] {
+            let vf = match chip.v[2] >= chip.v[4] {
                 true => 1,
                 false => 0,
             };
             let res = chip.v[2].wrapping_sub(chip.v[4]);
-            chip.process_opcode();
+            chip.process_opcode().unwrap();
             assert_eq!(chip.v[2], res);
             assert_eq!(chip.v[0xf], vf);
         }
@@ -650,14 +1129,507 @@ mod tests {
             chip.v[4] = rng.gen_range(0, 255);
             chip.v[2] = rng.gen_range(0, 255);
             chip.opcode = 0x8245;
-            let vf = match chip.v[2] > chip.v[4] {
+            let vf = match chip.v[2] >= chip.v[4] {
                 true => 1,
                 false => 0,
             };
             let res = chip.v[2].wrapping_sub(chip.v[4]);
-            chip.process_opcode();
+            chip.process_opcode().unwrap();
             assert_eq!(chip.v[2], res);
             assert_eq!(chip.v[0xf], vf);
         }
     }
+
+    #[test]
+    fn test_stack_errors() {
+        let mut chip = Chip8::new();
+
+        // returning with an empty stack
+        chip.opcode = 0x00ee;
+        assert_eq!(
+            chip.process_opcode(),
+            Err(Chip8Error::StackUnderflow { pc: 0x200 })
+        );
+
+        // calling a 17th subroutine
+        chip.sp = 16;
+        chip.pc = 0x300;
+        chip.opcode = 0x2400;
+        assert_eq!(
+            chip.process_opcode(),
+            Err(Chip8Error::StackOverflow { pc: 0x300 })
+        );
+
+        // or a 13th on the COSMAC VIP
+        chip.quirks.stack_depth = 12;
+        chip.sp = 12;
+        chip.pc = 0x300;
+        assert_eq!(
+            chip.process_opcode(),
+            Err(Chip8Error::StackOverflow { pc: 0x300 })
+        );
+        chip.sp = 11;
+        assert_eq!(chip.process_opcode(), Ok(()));
+        assert_eq!(chip.stack().len(), 12);
+    }
+
+    #[test]
+    fn test_memory_errors() {
+        let mut chip = Chip8::new();
+
+        // FX55 storing past the end of memory
+        chip.index = 0xffe;
+        chip.opcode = 0xf355;
+        assert_eq!(
+            chip.process_opcode(),
+            Err(Chip8Error::MemoryOutOfBounds {
+                pc: 0x200,
+                address: 0x1001
+            })
+        );
+
+        // DXYN reading a sprite past the end of memory
+        chip.pc = 0x200;
+        chip.opcode = 0xd005;
+        assert_eq!(
+            chip.process_opcode(),
+            Err(Chip8Error::MemoryOutOfBounds {
+                pc: 0x200,
+                address: 0x1002
+            })
+        );
+
+        // cycle leaves pc on the failing instruction
+        chip.load_instructions(&[0xf3, 0x55]).unwrap();
+        chip.pc = 0x200;
+        assert!(chip.cycle().is_err());
+        assert_eq!(chip.pc, 0x200);
+
+        // jumping past the end of memory fails on the next fetch
+        chip.pc = 0xfff;
+        assert_eq!(
+            chip.cycle(),
+            Err(Chip8Error::MemoryOutOfBounds {
+                pc: 0xfff,
+                address: 0x1000
+            })
+        );
+    }
+
+    #[test]
+    fn test_program_too_large() {
+        assert_eq!(
+            Chip8::load(vec![0; 4096]).err(),
+            Some(Chip8Error::ProgramTooLarge { size: 4096 })
+        );
+        assert!(Chip8::load(vec![0; 4096 - 0x200]).is_ok());
+    }
+
+    #[test]
+    fn test_quirks() {
+        let mut chip = Chip8::new();
+        chip.set_quirks(Quirks {
+            shift: false,
+            memory_increment_by_x: false,
+            memory_leave_i_unchanged: false,
+            wrap: false,
+            jump: true,
+            vblank: true,
+            logic: true,
+            stack_depth: 16,
+        });
+
+        // 8XY6 shifts vy into vx
+        chip.v[1] = 0xff;
+        chip.v[2] = 0x03;
+        chip.opcode = 0x8126;
+        chip.process_opcode().unwrap();
+        assert_eq!((chip.v[1], chip.v[0xf]), (0x01, 1));
+
+        // 8XY1 resets vf
+        chip.opcode = 0x8121;
+        chip.process_opcode().unwrap();
+        assert_eq!(chip.v[0xf], 0);
+
+        // FX55 leaves i after the last register written
+        chip.index = 0x300;
+        chip.opcode = 0xf255;
+        chip.process_opcode().unwrap();
+        assert_eq!(chip.index, 0x303);
+
+        // BXNN jumps to vx + nnn
+        chip.v[3] = 0x10;
+        chip.opcode = 0xb300;
+        chip.process_opcode().unwrap();
+        assert_eq!(chip.pc, 0x310);
+
+        // sprites are clipped at the right edge and the chip waits for vblank
+        chip.v[0] = 62;
+        chip.v[1] = 0;
+        chip.index = 0x300;
+        chip.memory[0x300] = 0xff;
+        chip.opcode = 0xd011;
+        chip.process_opcode().unwrap();
+        assert_eq!(chip.gfx.iter().filter(|&&pixel| pixel).count(), 2);
+        assert!(chip.waiting_for_vblank);
+        chip.vblank();
+        assert!(!chip.waiting_for_vblank);
+    }
+
+    #[test]
+    fn test_wait_key() {
+        // ld v2, k then add v3, 1
+        let program = vec![0xf2, 0x0a, 0x73, 0x01];
+        for &engine in &[Engine::Reference, Engine::Cached] {
+            let mut chip = Chip8::load(program.clone()).unwrap();
+            chip.set_engine(engine);
+            chip.cycle().unwrap();
+            assert_eq!(chip.pc, 0x200);
+
+            // a key that is held does not count until it is let go
+            chip.set_key(0xa, true);
+            chip.cycle().unwrap();
+            chip.set_key(0x3, true);
+            chip.cycle().unwrap();
+            assert_eq!(chip.pc, 0x200);
+            chip.set_key(0xa, false);
+            chip.cycle().unwrap();
+            assert_eq!((chip.pc, chip.v[2]), (0x202, 0xa));
+        }
+    }
+
+    #[test]
+    fn test_step() {
+        // drw v0, v0, 1 then add v1, 1 twice
+        let program = vec![0xd0, 0x01, 0x71, 0x01, 0x71, 0x01];
+        for &engine in &[Engine::Reference, Engine::Cached] {
+            let mut chip = Chip8::load(program.clone()).unwrap();
+            chip.set_engine(engine);
+            chip.set_quirks(Quirks {
+                vblank: true,
+                ..Quirks::default()
+            });
+
+            // a cycle after the draw waits for the frame
+            chip.cycle().unwrap();
+            chip.cycle().unwrap();
+            assert_eq!((chip.pc, chip.v[1]), (0x202, 0));
+
+            // stepping does not
+            chip.step().unwrap();
+            assert_eq!((chip.pc, chip.v[1]), (0x204, 1));
+            chip.step().unwrap();
+            assert_eq!((chip.pc, chip.v[1]), (0x206, 2));
+        }
+    }
+
+    // reference model for the 8XYN family, returns the new vx and, for the
+    // opcodes that set it, the flag that ends up in vf
+    fn reference_alu(n: u16, vx: u8, vy: u8) -> (u8, Option<u8>) {
+        match n {
+            0x0 => (vy, None),
+            0x1 => (vx | vy, None),
+            0x2 => (vx & vy, None),
+            0x3 => (vx ^ vy, None),
+            0x4 => {
+                let sum = vx as u16 + vy as u16;
+                (sum as u8, Some((sum > 0xff) as u8))
+            }
+            0x5 => (vx.wrapping_sub(vy), Some((vx >= vy) as u8)),
+            0x6 => (vx >> 1, Some(vx & 1)),
+            0x7 => (vy.wrapping_sub(vx), Some((vy >= vx) as u8)),
+            0xe => (vx << 1, Some(vx >> 7)),
+            _ => unreachable!(),
+        }
+    }
+
+    fn chip_with(v: [u8; 16], index: u16) -> Chip8 {
+        let mut chip = Chip8::new();
+        chip.v = v;
+        chip.index = index;
+        chip
+    }
+
+    #[test]
+    fn test_rpl_flags() {
+        let mut chip = Chip8::new();
+        chip.v[..4].copy_from_slice(&[1, 2, 3, 4]);
+
+        // FX75 stores v0..vx
+        chip.opcode = 0xf275;
+        chip.process_opcode().unwrap();
+        assert_eq!(chip.rpl_flags()[..4], [1, 2, 3, 0]);
+
+        // FX85 reads them back, into a fresh chip as if the rom was restarted
+        let mut restarted = Chip8::new();
+        restarted.set_rpl_flags(chip.rpl_flags());
+        restarted.opcode = 0xf185;
+        restarted.process_opcode().unwrap();
+        assert_eq!(restarted.v[..3], [1, 2, 0]);
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut chip = Chip8::load(vec![0x60, 0x2a, 0x22, 0x08, 0x00, 0xe0]).unwrap();
+        chip.set_quirks(Quirks {
+            vblank: true,
+            ..Quirks::default()
+        });
+        for _ in 0..2 {
+            chip.cycle().unwrap();
+        }
+        chip.gfx[0] = true;
+        chip.rpl_flags[0] = 7;
+        chip.reset();
+
+        assert_eq!((chip.v[0], chip.pc, chip.sp), (0, 0x200, 0));
+        assert!(!chip.gfx[0]);
+        assert_eq!(
+            chip.memory[0x200..0x206],
+            [0x60, 0x2a, 0x22, 0x08, 0x00, 0xe0]
+        );
+        assert!(chip.quirks().vblank);
+        assert_eq!(chip.rpl_flags()[0], 7);
+    }
+
+    #[test]
+    fn test_save_state() {
+        let mut chip = Chip8::load(vec![0x60, 0x2a, 0x22, 0x08, 0xa2, 0x10]).unwrap();
+        for _ in 0..2 {
+            chip.cycle().unwrap();
+        }
+        chip.gfx[100] = true;
+        let state = chip.save_state();
+
+        let mut restored = Chip8::new();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.save_state(), state);
+        assert_eq!((restored.v[0], restored.pc, restored.sp), (0x2a, 0x208, 1));
+        assert!(restored.gfx[100]);
+
+        assert_eq!(
+            restored.load_state(&state[1..]),
+            Err(Chip8Error::InvalidSaveState)
+        );
+        // a stack pointer past the stack
+        let mut corrupt = state.clone();
+        corrupt[26] = 17;
+        assert_eq!(
+            restored.load_state(&corrupt),
+            Err(Chip8Error::InvalidSaveState)
+        );
+        assert_eq!(restored.sp, 1);
+        // a pc whose instruction runs past the end of memory
+        let mut corrupt = state.clone();
+        corrupt[24..26].copy_from_slice(&[0x0f, 0xff]);
+        assert_eq!(
+            restored.load_state(&corrupt),
+            Err(Chip8Error::InvalidSaveState)
+        );
+        assert_eq!(restored.pc, 0x208);
+    }
+
+    // runs both engines side by side, checking they agree after every
+    // instruction. CXNN and FX07 depend on chance and the clock, their
+    // result is copied over from the reference.
+    fn lockstep(reference: &mut Chip8, cached: &mut Chip8, cycles: usize) {
+        cached.set_engine(Engine::Cached);
+        for _ in 0..cycles {
+            let expected = reference.cycle();
+            assert_eq!(cached.cycle(), expected);
+            let x = ((reference.opcode & 0x0f00) >> 8) as usize;
+            if reference.opcode & 0xf000 == 0xc000 || reference.opcode & 0xf0ff == 0xf007 {
+                cached.v[x] = reference.v[x];
+            }
+            cached.delay_set_time = reference.delay_set_time;
+            cached.sound_set_time = reference.sound_set_time;
+            let state = |chip: &Chip8| {
+                (
+                    chip.opcode,
+                    chip.v,
+                    chip.index,
+                    chip.pc,
+                    chip.sp,
+                    chip.stack,
+                    (chip.delay_timer, chip.sound_timer, chip.rpl_flags),
+                    (chip.settled, chip.waiting_for_vblank, chip.held_key),
+                )
+            };
+            assert_eq!(state(cached), state(reference));
+            assert!(cached.memory[..] == reference.memory[..]);
+            assert!(cached.gfx[..] == reference.gfx[..]);
+            if expected.is_err() {
+                return;
+            }
+            // the vblank quirk would hold both for good
+            reference.vblank();
+            cached.vblank();
+        }
+    }
+
+    #[test]
+    fn test_cached_engine() {
+        // writes over an instruction that has already been decoded
+        let program = vec![
+            0xa2, 0x0c, // 200: ld i, 20c
+            0x12, 0x0c, // 202: jp 20c
+            0x60, 0x70, // 204: ld v0, 0x70
+            0x61, 0x01, // 206: ld v1, 1
+            0xf1, 0x55, // 208: ld [i], v1, 20c becomes add v0, 1
+            0x12, 0x0c, // 20a: jp 20c
+            0x12, 0x04, // 20c: jp 204 the first time
+            0x12, 0x0e, // 20e: jp 20e
+        ];
+        let mut chip = Chip8::load(program.clone()).unwrap();
+        chip.set_engine(Engine::Cached);
+        for _ in 0..9 {
+            chip.cycle().unwrap();
+        }
+        assert_eq!((chip.v[0], chip.pc), (0x71, 0x20e));
+        // pokes are seen too, 20e becomes jp 200
+        chip.poke(0x20f, 0x00);
+        chip.cycle().unwrap();
+        assert_eq!(chip.pc, 0x200);
+
+        let mut reference = Chip8::load(program.clone()).unwrap();
+        let mut cached = Chip8::load(program).unwrap();
+        lockstep(&mut reference, &mut cached, 100);
+
+        // a return with a stack pointer past the stack fails the same way
+        let mut reference = Chip8::load(vec![0x00, 0xee]).unwrap();
+        reference.sp = 20;
+        let mut cached = Chip8::load(vec![0x00, 0xee]).unwrap();
+        cached.sp = 20;
+        lockstep(&mut reference, &mut cached, 1);
+        assert!(cached.cycle().is_err());
+
+        let digits = include_bytes!("../tests/roms/digits.ch8").to_vec();
+        let mut reference = Chip8::load(digits.clone()).unwrap();
+        let mut cached = Chip8::load(digits).unwrap();
+        lockstep(&mut reference, &mut cached, 500);
+
+        // batches stop at the draw that waits for the next frame
+        cached.set_quirks(Quirks {
+            vblank: true,
+            ..Quirks::default()
+        });
+        cached.reset();
+        let ran = cached.run(500).unwrap();
+        assert!(cached.waiting_for_vblank() && ran < 500);
+        assert_eq!(cached.run(500), Ok(0));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_cached_engine(
+            program in prop::collection::vec(any::<u8>(), 0..256),
+            keyboard in any::<[bool; 16]>(),
+            shift in any::<bool>(),
+            memory_increment_by_x in any::<bool>(),
+            memory_leave_i_unchanged in any::<bool>(),
+            wrap in any::<bool>(),
+            jump in any::<bool>(),
+            vblank in any::<bool>(),
+            logic in any::<bool>(),
+            stack_depth in 0u8..=16,
+        ) {
+            let quirks = Quirks {
+                shift,
+                memory_increment_by_x,
+                memory_leave_i_unchanged,
+                wrap,
+                jump,
+                vblank,
+                logic,
+                stack_depth,
+            };
+            let mut reference = Chip8::load(program.clone()).unwrap();
+            let mut cached = Chip8::load(program).unwrap();
+            for chip in [&mut reference, &mut cached] {
+                chip.set_quirks(quirks);
+                chip.keyboard = keyboard;
+            }
+            lockstep(&mut reference, &mut cached, 300);
+        }
+
+        #[test]
+        fn prop_8xyn(
+            v in any::<[u8; 16]>(),
+            x in 0usize..16,
+            y in 0usize..16,
+            n in prop::sample::select(vec![0x0u16, 0x1, 0x2, 0x3, 0x4, 0x5, 0x6, 0x7, 0xe]),
+        ) {
+            let mut chip = chip_with(v, 0);
+            chip.opcode = 0x8000 | (x as u16) << 8 | (y as u16) << 4 | n;
+            chip.process_opcode().unwrap();
+
+            // the flag is written last, so when x is f vf holds the flag
+            let mut expected = v;
+            let (result, flag) = reference_alu(n, v[x], v[y]);
+            expected[x] = result;
+            if let Some(flag) = flag {
+                expected[0xf] = flag;
+            }
+            prop_assert_eq!(chip.v, expected);
+        }
+
+        #[test]
+        fn prop_7xnn(v in any::<[u8; 16]>(), x in 0usize..16, nn in any::<u8>()) {
+            let mut chip = chip_with(v, 0);
+            chip.opcode = 0x7000 | (x as u16) << 8 | nn as u16;
+            chip.process_opcode().unwrap();
+
+            // 7XNN never touches the carry flag
+            let mut expected = v;
+            expected[x] = v[x].wrapping_add(nn);
+            prop_assert_eq!(chip.v, expected);
+        }
+
+        #[test]
+        fn prop_fx1e(v in any::<[u8; 16]>(), x in 0usize..16, index in 0u16..0x1000) {
+            let mut chip = chip_with(v, index);
+            chip.opcode = 0xf01e | (x as u16) << 8;
+            chip.process_opcode().unwrap();
+
+            prop_assert_eq!(chip.index, index + v[x] as u16);
+            prop_assert_eq!(chip.v, v);
+        }
+
+        #[test]
+        fn prop_fx33(v in any::<[u8; 16]>(), x in 0usize..16, index in 0x200u16..0xffd) {
+            let mut chip = chip_with(v, index);
+            chip.opcode = 0xf033 | (x as u16) << 8;
+            chip.process_opcode().unwrap();
+
+            let i = index as usize;
+            prop_assert_eq!(
+                &chip.memory[i..i + 3],
+                &[v[x] / 100, v[x] / 10 % 10, v[x] % 10][..]
+            );
+            prop_assert_eq!(chip.index, index);
+        }
+
+        #[test]
+        fn prop_fx55_fx65(v in any::<[u8; 16]>(), x in 0usize..16, index in 0x200u16..0xff0) {
+            let i = index as usize;
+            let mut chip = chip_with(v, index);
+            chip.opcode = 0xf055 | (x as u16) << 8;
+            chip.process_opcode().unwrap();
+
+            // only v0..=vx are stored and the bytes around them stay untouched
+            prop_assert_eq!(&chip.memory[i..=i + x], &v[..=x]);
+            prop_assert!(chip.memory[i + x + 1..].iter().all(|&b| b == 0));
+            prop_assert_eq!(chip.index, index);
+
+            // loading them back into cleared registers restores v0..=vx only
+            chip.v = [0; 16];
+            chip.opcode = 0xf065 | (x as u16) << 8;
+            chip.process_opcode().unwrap();
+
+            let mut expected = [0; 16];
+            expected[..=x].copy_from_slice(&v[..=x]);
+            prop_assert_eq!(chip.v, expected);
+            prop_assert_eq!(chip.index, index);
+        }
+    }
 }
@@ -0,0 +1,367 @@
+// the cached engine: each address is decoded once into an Op and kept until
+// memory under it is written. it behaves exactly like process_opcode, minus
+// the printing, which the tests check by running both side by side.
+use super::{Chip8, Chip8Error};
+use rand::Rng;
+use std::time::Instant;
+
+// an instruction with its operands pulled out, x and y are register numbers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Op {
+    // not decoded yet, or written over since
+    Undecoded,
+    Clear,
+    Return,
+    // 0NNN and 1NNN
+    Jump(u16),
+    Call(u16),
+    SkipEqual(u8, u8),
+    SkipNotEqual(u8, u8),
+    // 5XYN and 9XYN, n is ignored
+    SkipEqualRegister(u8, u8),
+    SkipNotEqualRegister(u8, u8),
+    Load(u8, u8),
+    Add(u8, u8),
+    Move(u8, u8),
+    Or(u8, u8),
+    And(u8, u8),
+    Xor(u8, u8),
+    AddRegister(u8, u8),
+    Sub(u8, u8),
+    ShiftRight(u8, u8),
+    SubReverse(u8, u8),
+    ShiftLeft(u8, u8),
+    LoadIndex(u16),
+    // BNNN, x is only used with the jump quirk
+    JumpOffset(u8, u16),
+    Random(u8, u8),
+    Draw(u8, u8, u8),
+    SkipKey(u8),
+    SkipNotKey(u8),
+    LoadDelay(u8),
+    WaitKey(u8),
+    SetDelay(u8),
+    SetSound(u8),
+    AddIndex(u8),
+    Font(u8),
+    Bcd(u8),
+    Store(u8),
+    Restore(u8),
+    SaveFlags(u8),
+    LoadFlags(u8),
+    // the unknown 8XYN, EXNN and FXNN, which do nothing
+    Nop,
+}
+
+pub(super) fn decode(opcode: u16) -> Op {
+    let x = ((opcode & 0x0f00) >> 8) as u8;
+    let y = ((opcode & 0x00f0) >> 4) as u8;
+    let n = (opcode & 0x000f) as u8;
+    let nn = (opcode & 0x00ff) as u8;
+    let nnn = opcode & 0x0fff;
+    match opcode {
+        0x00e0 => Op::Clear,
+        0x00ee => Op::Return,
+        0x0000..=0x1fff => Op::Jump(nnn),
+        0x2000..=0x2fff => Op::Call(nnn),
+        0x3000..=0x3fff => Op::SkipEqual(x, nn),
+        0x4000..=0x4fff => Op::SkipNotEqual(x, nn),
+        0x5000..=0x5fff => Op::SkipEqualRegister(x, y),
+        0x6000..=0x6fff => Op::Load(x, nn),
+        0x7000..=0x7fff => Op::Add(x, nn),
+        0x8000..=0x8fff => match n {
+            0x0 => Op::Move(x, y),
+            0x1 => Op::Or(x, y),
+            0x2 => Op::And(x, y),
+            0x3 => Op::Xor(x, y),
+            0x4 => Op::AddRegister(x, y),
+            0x5 => Op::Sub(x, y),
+            0x6 => Op::ShiftRight(x, y),
+            0x7 => Op::SubReverse(x, y),
+            0xe => Op::ShiftLeft(x, y),
+            _ => Op::Nop,
+        },
+        0x9000..=0x9fff => Op::SkipNotEqualRegister(x, y),
+        0xa000..=0xafff => Op::LoadIndex(nnn),
+        0xb000..=0xbfff => Op::JumpOffset(x, nnn),
+        0xc000..=0xcfff => Op::Random(x, nn),
+        0xd000..=0xdfff => Op::Draw(x, y, n),
+        0xe000..=0xefff => match nn {
+            0x9e => Op::SkipKey(x),
+            0xa1 => Op::SkipNotKey(x),
+            _ => Op::Nop,
+        },
+        0xf000..=0xffff => match nn {
+            0x07 => Op::LoadDelay(x),
+            0x0a => Op::WaitKey(x),
+            0x15 => Op::SetDelay(x),
+            0x18 => Op::SetSound(x),
+            0x1e => Op::AddIndex(x),
+            0x29 => Op::Font(x),
+            0x33 => Op::Bcd(x),
+            0x55 => Op::Store(x),
+            0x65 => Op::Restore(x),
+            0x75 => Op::SaveFlags(x),
+            0x85 => Op::LoadFlags(x),
+            _ => Op::Nop,
+        },
+    }
+}
+
+impl Chip8 {
+    // forgets the decoded instructions overlapping len bytes at address,
+    // including the one starting on the byte before
+    pub(super) fn invalidate(&mut self, address: usize, len: usize) {
+        let start = address.saturating_sub(1).min(self.decoded.len());
+        let end = (address + len).min(self.decoded.len());
+        for op in &mut self.decoded[start..end] {
+            *op = Op::Undecoded;
+        }
+        #[cfg(feature = "jit")]
+        if let Some(jit) = &mut self.jit {
+            jit.write(address, len);
+        }
+    }
+
+    // memory was loaded as a whole rather than written by the rom
+    pub(super) fn invalidate_all(&mut self) {
+        for op in self.decoded.iter_mut() {
+            *op = Op::Undecoded;
+        }
+        #[cfg(feature = "jit")]
+        if let Some(jit) = &mut self.jit {
+            jit.reset();
+        }
+    }
+
+    #[inline(always)]
+    pub(super) fn cycle_cached(&mut self) -> Result<(), Chip8Error> {
+        if self.waiting_for_vblank {
+            return Ok(());
+        }
+        let pc = self.pc;
+        let at = pc as usize;
+        if at + 2 > self.memory.len() {
+            return Err(Chip8Error::MemoryOutOfBounds {
+                pc,
+                address: at + 1,
+            });
+        }
+        self.opcode = u16::from_be_bytes([self.memory[at], self.memory[at + 1]]);
+        let mut op = self.decoded[at];
+        if op == Op::Undecoded {
+            op = decode(self.opcode);
+            self.decoded[at] = op;
+        }
+        self.pc += 2;
+        let result = self.execute(op, pc);
+        if result.is_err() {
+            self.pc = pc;
+        }
+        result
+    }
+
+    pub(super) fn run_cached(&mut self, cycles: u32) -> Result<u32, Chip8Error> {
+        for ran in 0..cycles {
+            if self.waiting_for_vblank {
+                return Ok(ran);
+            }
+            self.cycle_cached()?;
+        }
+        Ok(cycles)
+    }
+
+    // runs op with pc already moved past it
+    #[inline(always)]
+    fn execute(&mut self, op: Op, pc: u16) -> Result<(), Chip8Error> {
+        let v = &mut self.v;
+        match op {
+            Op::Undecoded => unreachable!("ran an instruction that was not decoded"),
+            Op::Clear => {
+                self.gfx = [false; 64 * 32];
+                self.settled = false;
+            }
+            Op::Return => {
+                if self.sp == 0 {
+                    return Err(Chip8Error::StackUnderflow { pc });
+                }
+                // a stack pointer past the stack fails like in process_opcode
+                self.pc = *self
+                    .stack
+                    .get(self.sp as usize - 1)
+                    .ok_or(Chip8Error::StackOverflow { pc })?;
+                self.sp -= 1;
+            }
+            Op::Jump(nnn) => self.pc = nnn,
+            Op::Call(nnn) => {
+                if self.sp as usize >= self.stack_depth() {
+                    return Err(Chip8Error::StackOverflow { pc });
+                }
+                self.stack[self.sp as usize] = self.pc;
+                self.sp += 1;
+                self.pc = nnn;
+            }
+            Op::SkipEqual(x, nn) => {
+                if v[x as usize] == nn {
+                    self.pc += 2;
+                }
+            }
+            Op::SkipNotEqual(x, nn) => {
+                if v[x as usize] != nn {
+                    self.pc += 2;
+                }
+            }
+            Op::SkipEqualRegister(x, y) => {
+                if v[x as usize] == v[y as usize] {
+                    self.pc += 2;
+                }
+            }
+            Op::SkipNotEqualRegister(x, y) => {
+                if v[x as usize] != v[y as usize] {
+                    self.pc += 2;
+                }
+            }
+            Op::Load(x, nn) => v[x as usize] = nn,
+            Op::Add(x, nn) => v[x as usize] = v[x as usize].wrapping_add(nn),
+            Op::Move(x, y) => v[x as usize] = v[y as usize],
+            Op::Or(x, y) => {
+                v[x as usize] |= v[y as usize];
+                if self.quirks.logic {
+                    v[0xf] = 0;
+                }
+            }
+            Op::And(x, y) => {
+                v[x as usize] &= v[y as usize];
+                if self.quirks.logic {
+                    v[0xf] = 0;
+                }
+            }
+            Op::Xor(x, y) => {
+                v[x as usize] ^= v[y as usize];
+                if self.quirks.logic {
+                    v[0xf] = 0;
+                }
+            }
+            // as in process_opcode vf is written last, it holds the flag when x is f
+            Op::AddRegister(x, y) => {
+                let (result, carry) = v[x as usize].overflowing_add(v[y as usize]);
+                v[x as usize] = result;
+                v[0xf] = carry as u8;
+            }
+            Op::Sub(x, y) => {
+                let no_borrow = v[x as usize] >= v[y as usize];
+                v[x as usize] = v[x as usize].wrapping_sub(v[y as usize]);
+                v[0xf] = no_borrow as u8;
+            }
+            Op::ShiftRight(x, y) => {
+                if !self.quirks.shift {
+                    v[x as usize] = v[y as usize];
+                }
+                let shifted_out = v[x as usize] & 0x01;
+                v[x as usize] >>= 1;
+                v[0xf] = shifted_out;
+            }
+            Op::SubReverse(x, y) => {
+                let no_borrow = v[y as usize] >= v[x as usize];
+                v[x as usize] = v[y as usize].wrapping_sub(v[x as usize]);
+                v[0xf] = no_borrow as u8;
+            }
+            Op::ShiftLeft(x, y) => {
+                if !self.quirks.shift {
+                    v[x as usize] = v[y as usize];
+                }
+                let shifted_out = v[x as usize] >> 7;
+                v[x as usize] <<= 1;
+                v[0xf] = shifted_out;
+            }
+            Op::LoadIndex(nnn) => self.index = nnn,
+            Op::JumpOffset(x, nnn) => {
+                let register = if self.quirks.jump { x as usize } else { 0 };
+                self.pc = v[register] as u16 + nnn;
+            }
+            Op::Random(x, nn) => v[x as usize] = rand::thread_rng().gen::<u8>() & nn,
+            Op::Draw(x, y, n) => self.draw(pc, x as usize, y as usize, n as usize)?,
+            Op::SkipKey(x) => {
+                if self.keyboard[(v[x as usize] & 0xf) as usize] {
+                    self.pc += 2;
+                }
+            }
+            Op::SkipNotKey(x) => {
+                if !self.keyboard[(v[x as usize] & 0xf) as usize] {
+                    self.pc += 2;
+                }
+            }
+            Op::LoadDelay(x) => self.v[x as usize] = self.get_delay_timer(),
+            Op::WaitKey(x) => match self.wait_key() {
+                Some(key) => self.v[x as usize] = key,
+                None => self.pc = pc,
+            },
+            Op::SetDelay(x) => {
+                self.delay_timer = v[x as usize];
+                self.delay_set_time = Some(Instant::now());
+            }
+            Op::SetSound(x) => {
+                self.sound_timer = v[x as usize];
+                self.sound_set_time = Some(Instant::now());
+            }
+            Op::AddIndex(x) => self.index = self.index.wrapping_add(v[x as usize] as u16),
+            Op::Font(x) => self.index = v[x as usize] as u16 * 5,
+            Op::Bcd(x) => {
+                let value = v[x as usize];
+                let i = self.index as usize;
+                self.write_memory(pc, i, 3)?.copy_from_slice(&[
+                    value / 100,
+                    value / 10 % 10,
+                    value % 10,
+                ]);
+            }
+            Op::Store(x) => {
+                let x = x as usize;
+                let i = self.index as usize;
+                let v = self.v;
+                self.write_memory(pc, i, x + 1)?.copy_from_slice(&v[..=x]);
+                self.increment_index_after_memory(x);
+            }
+            Op::Restore(x) => {
+                let x = x as usize;
+                let i = self.index as usize;
+                let mut v = self.v;
+                v[..=x].copy_from_slice(self.read_memory(pc, i, x + 1)?);
+                self.v = v;
+                self.increment_index_after_memory(x);
+            }
+            Op::SaveFlags(x) => self.rpl_flags[..=x as usize].copy_from_slice(&v[..=x as usize]),
+            Op::LoadFlags(x) => v[..=x as usize].copy_from_slice(&self.rpl_flags[..=x as usize]),
+            Op::Nop => {}
+        }
+        Ok(())
+    }
+
+    // DXYN a row at a time, the same pixels and flag as process_opcode
+    fn draw(&mut self, pc: u16, x: usize, y: usize, n: usize) -> Result<(), Chip8Error> {
+        let mut sprite = [0u8; 15];
+        sprite[..n].copy_from_slice(self.read_memory(pc, self.index as usize, n)?);
+        let (x, y) = (self.v[x] as usize % 64, self.v[y] as usize % 32);
+        let mut collision = false;
+        for (yy, &row) in sprite[..n].iter().enumerate() {
+            let gy = y + yy;
+            if row == 0 || (!self.quirks.wrap && gy >= 32) {
+                continue;
+            }
+            let line = &mut self.gfx[gy % 32 * 64..][..64];
+            for xx in 0..8 {
+                let gx = x + xx;
+                if row & (0x80 >> xx) == 0 || (!self.quirks.wrap && gx >= 64) {
+                    continue;
+                }
+                let pixel = &mut line[gx % 64];
+                collision |= *pixel;
+                *pixel = !*pixel;
+            }
+        }
+        self.v[0xf] = collision as u8;
+        self.settled = !collision;
+        self.waiting_for_vblank = self.quirks.vblank;
+        Ok(())
+    }
+}
@@ -0,0 +1,709 @@
+// the dynamic recompiler behind the jit feature. a run of simple
+// instructions is translated into x86-64 code the first time it is reached,
+// up to and including the jump or skip that ends it, and called directly
+// from then on. anything else, BNNN included, and code in bytes the rom has
+// written since it was loaded, goes through the cached interpreter.
+#[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+compile_error!("the jit feature only supports x86-64 linux");
+
+use super::cached::{decode, Op};
+use super::{Chip8, Chip8Error, Engine, Quirks};
+use std::mem::offset_of;
+
+// instructions in a block at most, also bounds how far back from a write
+// the blocks it may have changed start
+const MAX_BLOCK: usize = 64;
+const CODE_SIZE: usize = 1 << 20;
+
+// a compiled block, called with the chip in rdi and in esi how many times
+// to run it, which is only more than once for the blocks that loop
+type Native = unsafe extern "sysv64" fn(*mut Chip8, u32);
+
+#[derive(Clone, Copy)]
+enum Slot {
+    Unknown,
+    // nothing can be compiled here, the interpreter runs it
+    Interpret,
+    // len instructions, covering the bytes up to end. loops when it ends
+    // in a jump back to its start.
+    Native {
+        code: Native,
+        len: u32,
+        end: usize,
+        loops: bool,
+    },
+}
+
+pub(super) struct Jit {
+    // None when no executable memory could be had, everything is interpreted
+    code: Option<CodeBuffer>,
+    // by the address a block starts at
+    slots: Vec<Slot>,
+    // bytes written since the rom was loaded
+    written: Vec<bool>,
+    // what the lockstep engine runs each block on to check it
+    shadow: Option<Box<Chip8>>,
+}
+
+impl Jit {
+    pub(super) fn new() -> Jit {
+        Jit {
+            code: CodeBuffer::new(),
+            slots: vec![Slot::Unknown; 4096],
+            written: vec![false; 4096],
+            shadow: None,
+        }
+    }
+
+    // forgets every block, for when the quirks they were compiled with change
+    pub(super) fn flush(&mut self) {
+        for slot in self.slots.iter_mut() {
+            *slot = Slot::Unknown;
+        }
+        if let Some(code) = &mut self.code {
+            code.clear();
+        }
+    }
+
+    // memory was loaded as a whole, nothing counts as written any more
+    pub(super) fn reset(&mut self) {
+        self.flush();
+        for byte in self.written.iter_mut() {
+            *byte = false;
+        }
+    }
+
+    // drops the blocks over len bytes at address, which are interpreted from now on
+    pub(super) fn write(&mut self, address: usize, len: usize) {
+        let end = (address + len).min(self.written.len());
+        let start = address.min(end);
+        for byte in &mut self.written[start..end] {
+            *byte = true;
+        }
+        let first = start.saturating_sub(MAX_BLOCK * 2);
+        for (at, slot) in self.slots[first..end].iter_mut().enumerate() {
+            let covered = match *slot {
+                Slot::Unknown => false,
+                Slot::Interpret => first + at + 2 > start,
+                Slot::Native { end, .. } => end > start,
+            };
+            if covered {
+                *slot = Slot::Unknown;
+            }
+        }
+    }
+
+    fn slot(&mut self, chip: &Chip8, pc: usize) -> Slot {
+        if pc + 2 > self.slots.len() {
+            return Slot::Interpret;
+        }
+        if let Slot::Unknown = self.slots[pc] {
+            self.slots[pc] = self.compile(chip, pc);
+        }
+        self.slots[pc]
+    }
+
+    fn compile(&mut self, chip: &Chip8, start: usize) -> Slot {
+        let mut asm = Assembler::default();
+        let mut address = start;
+        let mut len = 0;
+        let mut branched = false;
+        let mut loops = false;
+        let mut last = 0;
+        while len < MAX_BLOCK
+            && address + 2 <= chip.memory.len()
+            && !self.written[address]
+            && !self.written[address + 1]
+        {
+            let opcode = u16::from_be_bytes([chip.memory[address], chip.memory[address + 1]]);
+            let next = address as u16 + 2;
+            let op = decode(opcode);
+            if op == Op::Jump(start as u16) {
+                asm.repeat();
+                loops = true;
+            }
+            match asm.instruction(op, opcode, next, chip.quirks) {
+                Some(ends) => branched = ends,
+                None => break,
+            }
+            last = opcode;
+            len += 1;
+            address += 2;
+            if branched {
+                break;
+            }
+        }
+        if len == 0 {
+            return Slot::Interpret;
+        }
+        if !branched {
+            asm.store_word(OPCODE, last);
+            asm.store_word(PC, address as u16);
+            asm.emit(&[0xc3]);
+        }
+        let code = match &mut self.code {
+            Some(code) => code,
+            None => return Slot::Interpret,
+        };
+        let native = match code.add(&asm.code) {
+            Some(native) => native,
+            // full, start over with only this block
+            None => {
+                self.flush();
+                match self.code.as_mut().and_then(|code| code.add(&asm.code)) {
+                    Some(native) => native,
+                    None => return Slot::Interpret,
+                }
+            }
+        };
+        Slot::Native {
+            code: native,
+            len: len as u32,
+            end: address,
+            loops,
+        }
+    }
+}
+
+impl Chip8 {
+    pub(super) fn run_jit(&mut self, cycles: u32) -> Result<u32, Chip8Error> {
+        let mut ran = 0;
+        while ran < cycles && !self.waiting_for_vblank {
+            ran += self.step_jit(cycles - ran)?;
+        }
+        Ok(ran)
+    }
+
+    // runs the block at pc if it fits in budget, otherwise interprets one
+    // instruction. returns how many instructions ran.
+    fn step_jit(&mut self, budget: u32) -> Result<u32, Chip8Error> {
+        let pc = self.pc;
+        let slot = match &self.jit {
+            Some(jit) if (pc as usize) < jit.slots.len() => jit.slots[pc as usize],
+            _ => Slot::Unknown,
+        };
+        let slot = match slot {
+            Slot::Unknown => {
+                let mut jit = self.jit.take().unwrap_or_else(|| Box::new(Jit::new()));
+                let slot = jit.slot(self, pc as usize);
+                self.jit = Some(jit);
+                slot
+            }
+            slot => slot,
+        };
+        match slot {
+            Slot::Native {
+                code, len, loops, ..
+            } if len <= budget => {
+                let times = if loops { budget / len } else { 1 };
+                if self.engine == Engine::Lockstep {
+                    return self.run_checked(code, len * times, times, pc);
+                }
+                // safety: blocks only touch the fields of the chip they were
+                // compiled for, at offsets taken from this struct
+                unsafe { code(self, times) };
+                Ok(len * times)
+            }
+            _ => {
+                self.cycle_cached()?;
+                Ok(1)
+            }
+        }
+    }
+
+    // the block, then the same instructions on the interpreter from the same
+    // machine. the chip is left as the block had it either way.
+    fn run_checked(
+        &mut self,
+        code: Native,
+        len: u32,
+        times: u32,
+        pc: u16,
+    ) -> Result<u32, Chip8Error> {
+        let mut shadow = self
+            .jit
+            .as_mut()
+            .and_then(|jit| jit.shadow.take())
+            .unwrap_or_default();
+        shadow.copy_machine(self);
+        // safety: see step_jit
+        unsafe { code(self, times) };
+        let same = shadow.run_cached(len) == Ok(len) && shadow.same_machine(self);
+        if let Some(jit) = &mut self.jit {
+            jit.shadow = Some(shadow);
+        }
+        match same {
+            true => Ok(len),
+            false => Err(Chip8Error::Diverged { pc }),
+        }
+    }
+
+    fn copy_machine(&mut self, other: &Chip8) {
+        self.opcode = other.opcode;
+        self.v = other.v;
+        self.index = other.index;
+        self.pc = other.pc;
+        self.sp = other.sp;
+        self.delay_timer = other.delay_timer;
+        self.sound_timer = other.sound_timer;
+        self.delay_set_time = other.delay_set_time;
+        self.sound_set_time = other.sound_set_time;
+        self.stack = other.stack;
+        self.memory = other.memory;
+        self.gfx = other.gfx;
+        self.settled = other.settled;
+        self.keyboard = other.keyboard;
+        self.held_key = other.held_key;
+        self.rpl_flags = other.rpl_flags;
+        self.quirks = other.quirks;
+        self.waiting_for_vblank = other.waiting_for_vblank;
+        self.invalidate_all();
+    }
+
+    fn same_machine(&self, other: &Chip8) -> bool {
+        self.opcode == other.opcode
+            && self.v == other.v
+            && self.index == other.index
+            && self.pc == other.pc
+            && self.sp == other.sp
+            && self.stack == other.stack
+            && (self.delay_timer, self.sound_timer) == (other.delay_timer, other.sound_timer)
+            && self.memory[..] == other.memory[..]
+            && self.gfx[..] == other.gfx[..]
+            && self.settled == other.settled
+            && self.waiting_for_vblank == other.waiting_for_vblank
+            && self.held_key == other.held_key
+            && self.rpl_flags == other.rpl_flags
+    }
+}
+
+// where the fields blocks use are, from the chip in rdi
+const OPCODE: usize = offset_of!(Chip8, opcode);
+const PC: usize = offset_of!(Chip8, pc);
+const INDEX: usize = offset_of!(Chip8, index);
+const KEYBOARD: usize = offset_of!(Chip8, keyboard);
+const RPL_FLAGS: usize = offset_of!(Chip8, rpl_flags);
+
+fn v(x: u8) -> usize {
+    offset_of!(Chip8, v) + x as usize
+}
+
+// jcc rel8
+const JE: u8 = 0x74;
+const JNE: u8 = 0x75;
+
+// al op [field]
+const OR: u8 = 0x0a;
+const AND: u8 = 0x22;
+const XOR: u8 = 0x32;
+const ADD: u8 = 0x02;
+const SUB: u8 = 0x2a;
+const CMP: u8 = 0x3a;
+
+const SETC_CL: [u8; 3] = [0x0f, 0x92, 0xc1];
+const SETNC_CL: [u8; 3] = [0x0f, 0x93, 0xc1];
+const MOV_CL_AL: [u8; 2] = [0x88, 0xc1];
+
+#[derive(Default)]
+struct Assembler {
+    code: Vec<u8>,
+}
+
+impl Assembler {
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    // an instruction with a [rdi + offset] operand, reg is al/ax/eax (0) or cl (1)
+    fn field(&mut self, opcode: &[u8], reg: u8, offset: usize) {
+        self.emit(opcode);
+        self.code.push(0x87 | reg << 3);
+        self.emit(&(offset as i32).to_le_bytes());
+    }
+
+    fn load_al(&mut self, offset: usize) {
+        self.field(&[0x8a], 0, offset);
+    }
+
+    fn store_al(&mut self, offset: usize) {
+        self.field(&[0x88], 0, offset);
+    }
+
+    fn store_cl(&mut self, offset: usize) {
+        self.field(&[0x88], 1, offset);
+    }
+
+    fn alu(&mut self, op: u8, offset: usize) {
+        self.field(&[op], 0, offset);
+    }
+
+    fn store_byte(&mut self, offset: usize, value: u8) {
+        self.field(&[0xc6], 0, offset);
+        self.code.push(value);
+    }
+
+    fn add_byte(&mut self, offset: usize, value: u8) {
+        self.field(&[0x80], 0, offset);
+        self.code.push(value);
+    }
+
+    // 9 bytes, skips jump over it
+    fn store_word(&mut self, offset: usize, value: u16) {
+        self.field(&[0x66, 0xc7], 0, offset);
+        self.emit(&value.to_le_bytes());
+    }
+
+    // movzx eax, byte [field]
+    fn load_eax(&mut self, offset: usize) {
+        self.field(&[0x0f, 0xb6], 0, offset);
+    }
+
+    // dec esi then jnz back to the start of the block
+    fn repeat(&mut self) {
+        self.emit(&[0xff, 0xce, 0x0f, 0x85]);
+        let back = -(self.code.len() as i32 + 4);
+        self.emit(&back.to_le_bytes());
+    }
+
+    // the flags are set, pc goes to next or past it and the block returns
+    fn skip(&mut self, next: u16, unless: u8) {
+        self.store_word(PC, next);
+        self.emit(&[unless, 9]);
+        self.store_word(PC, next + 2);
+        self.emit(&[0xc3]);
+    }
+
+    // vx = vx op vy, with the flag of op in vf
+    fn arithmetic(&mut self, x: u8, y: u8, op: u8, flag: [u8; 3]) {
+        self.load_al(v(x));
+        self.alu(op, v(y));
+        self.emit(&flag);
+        self.store_al(v(x));
+        self.store_cl(v(0xf));
+    }
+
+    // vx = shifted vx (or vy), with the bit shifted out in vf
+    fn shift(&mut self, x: u8, y: u8, quirks: Quirks, shift: [u8; 2], shifted_out: [u8; 3]) {
+        self.load_al(v(if quirks.shift { x } else { y }));
+        self.emit(&MOV_CL_AL);
+        self.emit(&shifted_out);
+        self.emit(&shift);
+        self.store_al(v(x));
+        self.store_cl(v(0xf));
+    }
+
+    fn logic(&mut self, x: u8, y: u8, op: u8, quirks: Quirks) {
+        self.load_al(v(x));
+        self.alu(op, v(y));
+        self.store_al(v(x));
+        if quirks.logic {
+            self.store_byte(v(0xf), 0);
+        }
+    }
+
+    // the code for op, which is at next - 2. Some(true) when it ended the
+    // block, None when it can't be compiled and nothing was emitted.
+    fn instruction(&mut self, op: Op, opcode: u16, next: u16, quirks: Quirks) -> Option<bool> {
+        match op {
+            Op::Load(x, nn) => self.store_byte(v(x), nn),
+            Op::Add(x, nn) => self.add_byte(v(x), nn),
+            Op::Move(x, y) => {
+                self.load_al(v(y));
+                self.store_al(v(x));
+            }
+            Op::Or(x, y) => self.logic(x, y, OR, quirks),
+            Op::And(x, y) => self.logic(x, y, AND, quirks),
+            Op::Xor(x, y) => self.logic(x, y, XOR, quirks),
+            Op::AddRegister(x, y) => self.arithmetic(x, y, ADD, SETC_CL),
+            Op::Sub(x, y) => self.arithmetic(x, y, SUB, SETNC_CL),
+            Op::SubReverse(x, y) => {
+                self.load_al(v(y));
+                self.alu(SUB, v(x));
+                self.emit(&SETNC_CL);
+                self.store_al(v(x));
+                self.store_cl(v(0xf));
+            }
+            // shr/shl al, 1 with and cl, 1 or shr cl, 7
+            Op::ShiftRight(x, y) => self.shift(x, y, quirks, [0xd0, 0xe8], [0x80, 0xe1, 0x01]),
+            Op::ShiftLeft(x, y) => self.shift(x, y, quirks, [0xd0, 0xe0], [0xc0, 0xe9, 0x07]),
+            Op::LoadIndex(nnn) => self.store_word(INDEX, nnn),
+            Op::AddIndex(x) => {
+                self.load_eax(v(x));
+                // add [index], ax
+                self.field(&[0x66, 0x01], 0, INDEX);
+            }
+            Op::Font(x) => {
+                self.load_eax(v(x));
+                // imul eax, eax, 5 then mov [index], ax
+                self.emit(&[0x6b, 0xc0, 0x05]);
+                self.field(&[0x66, 0x89], 0, INDEX);
+            }
+            Op::SaveFlags(x) => {
+                for i in 0..=x {
+                    self.load_al(v(i));
+                    self.store_al(RPL_FLAGS + i as usize);
+                }
+            }
+            Op::LoadFlags(x) => {
+                for i in 0..=x {
+                    self.load_al(RPL_FLAGS + i as usize);
+                    self.store_al(v(i));
+                }
+            }
+            Op::Nop => {}
+            Op::Jump(nnn) => {
+                self.store_word(OPCODE, opcode);
+                self.store_word(PC, nnn);
+                self.emit(&[0xc3]);
+                return Some(true);
+            }
+            Op::SkipEqual(x, nn) | Op::SkipNotEqual(x, nn) => {
+                self.store_word(OPCODE, opcode);
+                self.load_al(v(x));
+                // cmp al, nn
+                self.emit(&[0x3c, nn]);
+                let unless = if let Op::SkipEqual(..) = op { JNE } else { JE };
+                self.skip(next, unless);
+                return Some(true);
+            }
+            Op::SkipEqualRegister(x, y) | Op::SkipNotEqualRegister(x, y) => {
+                self.store_word(OPCODE, opcode);
+                self.load_al(v(x));
+                self.alu(CMP, v(y));
+                let unless = if let Op::SkipEqualRegister(..) = op {
+                    JNE
+                } else {
+                    JE
+                };
+                self.skip(next, unless);
+                return Some(true);
+            }
+            Op::SkipKey(x) | Op::SkipNotKey(x) => {
+                self.store_word(OPCODE, opcode);
+                self.load_eax(v(x));
+                // and eax, 0xf then cmp byte [rdi + rax + keyboard], 0
+                self.emit(&[0x83, 0xe0, 0x0f, 0x80, 0xbc, 0x07]);
+                self.emit(&(KEYBOARD as i32).to_le_bytes());
+                self.code.push(0);
+                let unless = if let Op::SkipKey(..) = op { JE } else { JNE };
+                self.skip(next, unless);
+                return Some(true);
+            }
+            _ => return None,
+        }
+        Some(false)
+    }
+}
+
+// a mapping blocks are copied into, only writable while that happens
+struct CodeBuffer {
+    memory: *mut u8,
+    used: usize,
+}
+
+// the mapping is owned like a Vec would be
+unsafe impl Send for CodeBuffer {}
+
+impl CodeBuffer {
+    fn new() -> Option<CodeBuffer> {
+        // safety: a fresh anonymous mapping, checked before it is used
+        let memory = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                CODE_SIZE,
+                libc::PROT_READ | libc::PROT_EXEC,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if memory == libc::MAP_FAILED {
+            return None;
+        }
+        Some(CodeBuffer {
+            memory: memory as *mut u8,
+            used: 0,
+        })
+    }
+
+    fn clear(&mut self) {
+        self.used = 0;
+    }
+
+    // copies code in, None when it does not fit
+    fn add(&mut self, code: &[u8]) -> Option<Native> {
+        if self.used + code.len() > CODE_SIZE {
+            return None;
+        }
+        let protect = |protection| {
+            // safety: the whole mapping made in new
+            unsafe { libc::mprotect(self.memory as *mut libc::c_void, CODE_SIZE, protection) == 0 }
+        };
+        if !protect(libc::PROT_READ | libc::PROT_WRITE) {
+            return None;
+        }
+        // safety: in bounds of the mapping, checked above
+        let start = unsafe { self.memory.add(self.used) };
+        unsafe { std::ptr::copy_nonoverlapping(code.as_ptr(), start, code.len()) };
+        if !protect(libc::PROT_READ | libc::PROT_EXEC) {
+            return None;
+        }
+        self.used += code.len();
+        // safety: complete x86-64 code for a Native, ending in ret
+        Some(unsafe { std::mem::transmute::<*mut u8, Native>(start) })
+    }
+}
+
+impl Drop for CodeBuffer {
+    fn drop(&mut self) {
+        // safety: the mapping made in new, no blocks outlive the jit
+        unsafe { libc::munmap(self.memory as *mut libc::c_void, CODE_SIZE) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    // runs the jit a block or an instruction at a time and the reference
+    // interpreter for as many instructions, checking they agree each time.
+    // CXNN and FX07 are interpreted, their result is copied over as in the
+    // lockstep test of the cached engine.
+    fn lockstep(reference: &mut Chip8, jit: &mut Chip8, cycles: u32) {
+        jit.set_engine(Engine::Jit);
+        let mut ran = 0;
+        while ran < cycles {
+            let step = jit.step_jit(cycles - ran);
+            match step {
+                Ok(len) => {
+                    for _ in 0..len {
+                        assert_eq!(reference.cycle(), Ok(()));
+                    }
+                    ran += len;
+                }
+                Err(err) => {
+                    assert_eq!(reference.cycle(), Err(err));
+                    return;
+                }
+            }
+            let x = ((jit.opcode & 0x0f00) >> 8) as usize;
+            if jit.opcode & 0xf000 == 0xc000 || jit.opcode & 0xf0ff == 0xf007 {
+                jit.v[x] = reference.v[x];
+            }
+            jit.delay_set_time = reference.delay_set_time;
+            jit.sound_set_time = reference.sound_set_time;
+            assert_eq!(
+                (jit.pc, jit.v, jit.index, jit.opcode),
+                (reference.pc, reference.v, reference.index, reference.opcode)
+            );
+            assert!(jit.same_machine(reference));
+            reference.vblank();
+            jit.vblank();
+        }
+    }
+
+    fn native(chip: &Chip8, pc: usize) -> Option<u32> {
+        match chip.jit.as_ref()?.slots[pc] {
+            Slot::Native { len, .. } => Some(len),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_jit() {
+        let program = vec![
+            0x60, 0x00, // 200: ld v0, 0
+            0x61, 0x03, // 202: ld v1, 3
+            0x80, 0x14, // 204: add v0, v1
+            0x81, 0x0e, // 206: shl v1
+            0x30, 0x2d, // 208: se v0, 45
+            0x12, 0x04, // 20a: jp 204
+            0xa2, 0x00, // 20c: ld i, 200
+            0xf1, 0x55, // 20e: ld [i], v1, over 200
+            0x12, 0x00, // 210: jp 200
+        ];
+        let mut chip = Chip8::load(program.clone()).unwrap();
+        chip.set_engine(Engine::Jit);
+        assert_eq!(chip.run(6), Ok(6));
+        // 200 to the skip at 208, then 20a
+        assert_eq!(
+            (native(&chip, 0x200), native(&chip, 0x20a)),
+            (Some(5), Some(1))
+        );
+        assert_eq!((chip.v[0], chip.v[1], chip.pc), (3, 6, 0x204));
+
+        // the store is interpreted and drops the block it wrote over,
+        // which is interpreted from then on
+        let mut reference = Chip8::load(program.clone()).unwrap();
+        let mut jit = Chip8::load(program).unwrap();
+        lockstep(&mut reference, &mut jit, 40);
+        assert!(jit.jit.as_ref().unwrap().written[0x200]);
+        assert_eq!(native(&jit, 0x200), None);
+        assert_eq!(native(&jit, 0x204), Some(3));
+
+        // a loop runs as many times as fit, the rest is interpreted
+        let mut chip = Chip8::load(vec![0x70, 0x01, 0x12, 0x00]).unwrap();
+        chip.set_engine(Engine::Jit);
+        assert_eq!(chip.run(999), Ok(999));
+        assert_eq!((chip.v[0], chip.pc, chip.opcode), (244, 0x202, 0x7001));
+        assert!(matches!(
+            chip.jit.as_ref().unwrap().slots[0x200],
+            Slot::Native { loops: true, .. }
+        ));
+
+        let digits = include_bytes!("../../tests/roms/digits.ch8").to_vec();
+        let mut reference = Chip8::load(digits.clone()).unwrap();
+        let mut jit = Chip8::load(digits.clone()).unwrap();
+        lockstep(&mut reference, &mut jit, 500);
+
+        // the lockstep engine finds nothing wrong with the jit, but does
+        // with a block from elsewhere put in at 200
+        let mut chip = Chip8::load(digits).unwrap();
+        chip.set_engine(Engine::Lockstep);
+        assert_eq!(chip.run(500), Ok(500));
+        let slots = &mut chip.jit.as_mut().unwrap().slots;
+        let elsewhere = slots[0x202..]
+            .iter()
+            .find_map(|slot| match *slot {
+                Slot::Native { code, len: 1, .. } => Some(code),
+                _ => None,
+            })
+            .unwrap();
+        slots[0x200] = Slot::Native {
+            code: elsewhere,
+            len: 1,
+            end: 0x202,
+            loops: false,
+        };
+        chip.set_pc(0x200);
+        assert_eq!(chip.run(1), Err(Chip8Error::Diverged { pc: 0x200 }));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_jit(
+            program in prop::collection::vec(any::<u8>(), 0..256),
+            keyboard in any::<[bool; 16]>(),
+            shift in any::<bool>(),
+            logic in any::<bool>(),
+            jump in any::<bool>(),
+            vblank in any::<bool>(),
+        ) {
+            let quirks = Quirks {
+                shift,
+                logic,
+                jump,
+                vblank,
+                ..Quirks::default()
+            };
+            let mut reference = Chip8::load(program.clone()).unwrap();
+            let mut jit = Chip8::load(program).unwrap();
+            for chip in [&mut reference, &mut jit] {
+                chip.set_quirks(quirks);
+                chip.keyboard = keyboard;
+            }
+            lockstep(&mut reference, &mut jit, 300);
+        }
+    }
+}
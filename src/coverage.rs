@@ -0,0 +1,228 @@
+// which bytes of memory a run used: run as instructions, read as sprite data
+// by DXYN or written by FX33/FX55. written out as a text map of the rom, or
+// as lcov tracefile records when a line map ties addresses to source lines.
+use crate::chip8::Chip8;
+use crate::disasm::opcode_at;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+
+const EXECUTED: u8 = 1;
+const SPRITE: u8 = 2;
+const WRITTEN: u8 = 4;
+
+// bytes per row of the text map
+const ROW: usize = 32;
+const PROGRAM_START: usize = 0x200;
+
+pub struct Coverage {
+    flags: Vec<u8>,
+    // runs of the instruction at each address
+    hits: Vec<u64>,
+    program_len: usize,
+}
+
+impl Coverage {
+    pub fn new(program_len: usize) -> Coverage {
+        Coverage {
+            flags: vec![0; 4096],
+            hits: vec![0; 4096],
+            program_len,
+        }
+    }
+
+    // called before each cycle with the instruction about to run
+    pub fn record(&mut self, chip: &Chip8) {
+        if chip.waiting_for_vblank() {
+            return;
+        }
+        let pc = chip.pc() as usize & 0xfff;
+        let opcode = opcode_at(chip.memory(), pc as u16);
+        self.hits[pc] += 1;
+        self.mark(pc, 2, EXECUTED);
+        let index = chip.index() as usize;
+        let x = (opcode as usize >> 8) & 0xf;
+        match opcode & 0xf000 {
+            0xd000 => self.mark(index, opcode as usize & 0xf, SPRITE),
+            0xf000 if opcode & 0xff == 0x33 => self.mark(index, 3, WRITTEN),
+            0xf000 if opcode & 0xff == 0x55 => self.mark(index, x + 1, WRITTEN),
+            _ => {}
+        }
+    }
+
+    fn mark(&mut self, address: usize, len: usize, flag: u8) {
+        for offset in 0..len {
+            self.flags[(address + offset) & 0xfff] |= flag;
+        }
+    }
+
+    pub fn executed(&self, address: u16) -> bool {
+        self.flags[address as usize & 0xfff] & EXECUTED != 0
+    }
+
+    pub fn sprite(&self, address: u16) -> bool {
+        self.flags[address as usize & 0xfff] & SPRITE != 0
+    }
+
+    pub fn written(&self, address: u16) -> bool {
+        self.flags[address as usize & 0xfff] & WRITTEN != 0
+    }
+
+    // one character per byte of the program: x run, s sprite data, w written,
+    // * more than one of those and . untouched
+    pub fn text(&self) -> String {
+        let program = &self.flags[PROGRAM_START..(PROGRAM_START + self.program_len).min(4096)];
+        let count = |flag: u8| program.iter().filter(|&&flags| flags & flag != 0).count();
+        let share = |count: usize| 100.0 * count as f64 / program.len().max(1) as f64;
+        let mut text = String::new();
+        let _ = writeln!(text, "{} program bytes", program.len());
+        for (name, flag) in [
+            ("run", EXECUTED),
+            ("sprite data", SPRITE),
+            ("written", WRITTEN),
+        ] {
+            let _ = writeln!(
+                text,
+                "  {:<11} {:>5}  {:5.1}%",
+                name,
+                count(flag),
+                share(count(flag))
+            );
+        }
+        let untouched = program.iter().filter(|&&flags| flags == 0).count();
+        let _ = writeln!(
+            text,
+            "  {:<11} {:>5}  {:5.1}%\n",
+            "untouched",
+            untouched,
+            share(untouched)
+        );
+        for (row, bytes) in program.chunks(ROW).enumerate() {
+            let map: String = bytes
+                .iter()
+                .map(|&flags| match flags {
+                    0 => '.',
+                    EXECUTED => 'x',
+                    SPRITE => 's',
+                    WRITTEN => 'w',
+                    _ => '*',
+                })
+                .collect();
+            let _ = writeln!(text, "{:03x}: {}", PROGRAM_START + row * ROW, map);
+        }
+        text
+    }
+
+    // lcov tracefile records, one per source file, with the runs of each
+    // instruction line in the line map
+    pub fn lcov(&self, lines: &LineMap) -> String {
+        let mut files: BTreeMap<&str, Vec<(u32, u64)>> = BTreeMap::new();
+        for (&address, (file, line)) in &lines.lines {
+            files
+                .entry(file)
+                .or_default()
+                .push((*line, self.hits[address as usize]));
+        }
+        let mut lcov = String::new();
+        for (file, mut hits) in files {
+            hits.sort_unstable();
+            let _ = writeln!(lcov, "TN:\nSF:{}", file);
+            for (line, count) in &hits {
+                let _ = writeln!(lcov, "DA:{},{}", line, count);
+            }
+            let hit = hits.iter().filter(|(_, count)| *count > 0).count();
+            let _ = writeln!(lcov, "LH:{}\nLF:{}\nend_of_record", hit, hits.len());
+        }
+        lcov
+    }
+}
+
+// the source line each instruction was assembled from. one instruction per
+// line as "ADDRESS FILE:LINE", with the address in hex, e.g.
+// "0x2a6 game.8o:41". blank lines and lines starting with # are skipped.
+#[derive(Debug, Default, PartialEq)]
+pub struct LineMap {
+    lines: BTreeMap<u16, (String, u32)>,
+}
+
+impl LineMap {
+    pub fn parse(text: &str) -> Result<LineMap, String> {
+        let mut lines = BTreeMap::new();
+        for (number, entry) in text.lines().enumerate() {
+            let entry = entry.trim();
+            if entry.is_empty() || entry.starts_with('#') {
+                continue;
+            }
+            let invalid = || format!("line {}: expected ADDRESS FILE:LINE", number + 1);
+            let (address, source) = entry.split_once(char::is_whitespace).ok_or_else(invalid)?;
+            let (file, line) = source.trim().rsplit_once(':').ok_or_else(invalid)?;
+            let address = u16::from_str_radix(address.trim_start_matches("0x"), 16)
+                .ok()
+                .filter(|&address| address < 0x1000)
+                .ok_or_else(invalid)?;
+            let line = line.parse().map_err(|_| invalid())?;
+            lines.insert(address, (file.to_string(), line));
+        }
+        Ok(LineMap { lines })
+    }
+
+    // the file and line of the instruction at address
+    pub fn lookup(&self, address: u16) -> Option<(&str, u32)> {
+        self.lines
+            .get(&address)
+            .map(|(file, line)| (file.as_str(), *line))
+    }
+
+    // the first instruction on the line or the closest line after it with
+    // one, as (address, line). the file matches when one path ends with the
+    // other, so an editor's absolute paths find the relative ones in the map.
+    pub fn find(&self, file: &str, line: u32) -> Option<(u16, u32)> {
+        self.lines
+            .iter()
+            .filter(|(_, (name, found))| *found >= line && same_file(name, file))
+            .min_by_key(|(&address, (_, found))| (*found, address))
+            .map(|(&address, (_, found))| (address, *found))
+    }
+}
+
+fn same_file(a: &str, b: &str) -> bool {
+    let (a, b) = (Path::new(a), Path::new(b));
+    a.ends_with(b) || b.ends_with(a)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coverage() {
+        // 200: ld i 20a, 202: drw v0 v0 2, 204: ld b v0, 206: jp 206, 208: unused,
+        // 20a: the sprite, also written over by ld b
+        let program = vec![
+            0xa2, 0x0a, 0xd0, 0x02, 0xf0, 0x33, 0x12, 0x06, 0x00, 0x00, 0xf0, 0xf0, 0x00,
+        ];
+        let mut chip = Chip8::load(program.clone()).unwrap();
+        let mut coverage = Coverage::new(program.len());
+        for _ in 0..5 {
+            coverage.record(&chip);
+            chip.cycle().unwrap();
+        }
+
+        assert!(coverage.executed(0x200) && coverage.executed(0x207));
+        assert!(!coverage.executed(0x208));
+        assert!(coverage.sprite(0x20a) && coverage.sprite(0x20b));
+        assert!(coverage.written(0x20c) && !coverage.sprite(0x20c));
+        assert!(coverage.text().ends_with("200: xxxxxxxx..**w\n"));
+
+        let lines =
+            LineMap::parse("# game\n0x200 game.8o:3\n0x206 game.8o:7\n0x208 game.8o:8\n").unwrap();
+        assert_eq!(lines.lookup(0x206), Some(("game.8o", 7)));
+        assert_eq!(lines.find("/home/me/rom/game.8o", 5), Some((0x206, 7)));
+        assert_eq!(lines.find("game.8o", 9), None);
+        assert_eq!(
+            coverage.lcov(&lines),
+            "TN:\nSF:game.8o\nDA:3,1\nDA:7,2\nDA:8,0\nLH:2\nLF:3\nend_of_record\n"
+        );
+        assert!(LineMap::parse("0x200 game.8o").is_err());
+    }
+}
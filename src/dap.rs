@@ -0,0 +1,1026 @@
+// a debug adapter protocol server, so editors can launch a rom, set
+// breakpoints on the lines of its assembler source and step through it. the
+// editor connects over tcp, like vscode's debugServer setting. the frontend
+// polls it once per frame and only runs the chip while the editor lets it.
+//
+// there is one thread. the frames of its stack trace are the instruction at
+// pc and the calls on the chip8 stack. the variables are the registers, the
+// stack and memory in rows of 16 bytes.
+use crate::breakpoints::Breakpoints;
+use crate::chip8::Chip8;
+use crate::coverage::LineMap;
+use crate::disasm::{disassemble_with, opcode_at};
+use crate::symbols::Symbols;
+use crate::watch::Expr;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const THREAD: u64 = 1;
+const REGISTERS: u64 = 1;
+const STACK: u64 = 2;
+const MEMORY: u64 = 3;
+
+pub struct DapServer {
+    listener: TcpListener,
+    client: Option<Client>,
+    lines: LineMap,
+    // where the files named in the line map are
+    source_dir: PathBuf,
+}
+
+// what the editor is debugging, None before a rom is loaded
+pub struct Target<'a> {
+    pub chip: &'a mut Chip8,
+    pub breakpoints: &'a mut Breakpoints,
+    pub symbols: &'a Symbols,
+}
+
+// a launch request for the frontend to load a rom. it answers with
+// DapServer::launched once the rom is loaded or failed to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Launch {
+    pub program: String,
+    pub symbols: Option<String>,
+    pub line_map: Option<String>,
+}
+
+struct Client {
+    stream: TcpStream,
+    // bytes received that don't make up a whole message yet
+    input: Vec<u8>,
+    seq: u64,
+    // continuing until a breakpoint, an error or a pause
+    running: bool,
+    // where a step over or out stops, to tell it apart from a breakpoint
+    stepping: Option<u16>,
+    stop_on_entry: bool,
+    // the launch request waiting for the frontend, and the launch to hand it
+    pending: Option<u64>,
+    launch: Option<Launch>,
+    // breakpoints set by the editor, kept to be replaced the next time it
+    // sets them. source breakpoints are kept by file.
+    source_breakpoints: BTreeMap<String, Vec<u16>>,
+    function_breakpoints: Vec<u16>,
+    instruction_breakpoints: Vec<u16>,
+    disconnected: bool,
+    // events sent after the response to the request being handled
+    events: Vec<(&'static str, Value)>,
+}
+
+impl DapServer {
+    // listens without blocking, e.g. on "127.0.0.1:4711"
+    pub fn bind<A: ToSocketAddrs>(address: A) -> io::Result<DapServer> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(DapServer {
+            listener,
+            client: None,
+            lines: LineMap::default(),
+            source_dir: PathBuf::new(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    // the source lines breakpoints are set on, with the directory the files
+    // it names are in
+    pub fn set_line_map(&mut self, lines: LineMap, source_dir: &Path) {
+        self.lines = lines;
+        self.source_dir = source_dir.to_path_buf();
+    }
+
+    pub fn connected(&self) -> bool {
+        self.client.is_some()
+    }
+
+    // an editor is attached and has the chip stopped
+    pub fn halted(&self) -> bool {
+        self.client.as_ref().is_some_and(|client| !client.running)
+    }
+
+    // takes a new connection and answers the requests that came in. returns
+    // a launch for the frontend to load. on an error the connection is
+    // dropped and the rom runs on by itself.
+    pub fn poll(&mut self, mut target: Option<Target>) -> io::Result<Option<Launch>> {
+        if self.client.is_none() {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(true)?;
+                    stream.set_nodelay(true)?;
+                    self.client = Some(Client::new(stream));
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(err) => return Err(err),
+            }
+        }
+        let client = self.client.as_mut().expect("connected above");
+        let result = client.poll(&mut target, &self.lines, &self.source_dir);
+        if result.is_err() || client.disconnected {
+            // the rom runs on without the editor's breakpoints
+            if let Some(target) = target {
+                client.clear_breakpoints(target.breakpoints);
+                target.breakpoints.run_until(None);
+            }
+            self.client = None;
+        }
+        result
+    }
+
+    // answers the launch request once the frontend has loaded the rom
+    pub fn launched(&mut self, result: Result<(), String>) -> io::Result<()> {
+        let client = match &mut self.client {
+            Some(client) => client,
+            None => return Ok(()),
+        };
+        let seq = match client.pending.take() {
+            Some(seq) => seq,
+            None => return Ok(()),
+        };
+        let failed = result.is_err();
+        let result = client
+            .respond(seq, "launch", result.map(|()| Value::Null))
+            .and_then(|()| match failed {
+                true => Ok(()),
+                false => client.event("initialized", Value::Null),
+            });
+        if result.is_err() {
+            self.client = None;
+        }
+        result
+    }
+
+    // tells a running editor that the chip stopped at a breakpoint or at the
+    // end of a step over or out
+    pub fn stop(&mut self, chip: &Chip8, breakpoints: &mut Breakpoints) -> io::Result<()> {
+        let reason = match &mut self.client {
+            Some(client) if client.running => match client.stepping.take() {
+                Some(address) if address == chip.pc() => "step",
+                _ => "breakpoint",
+            },
+            _ => return Ok(()),
+        };
+        breakpoints.run_until(None);
+        self.stopped(json!({ "reason": reason }))
+    }
+
+    // tells a running editor the rom failed and can't go on
+    pub fn fail(&mut self, error: &str) -> io::Result<()> {
+        match &self.client {
+            Some(client) if client.running => self.stopped(json!({
+                "reason": "exception",
+                "description": error,
+                "text": error,
+            })),
+            _ => Ok(()),
+        }
+    }
+
+    fn stopped(&mut self, body: Value) -> io::Result<()> {
+        let client = self.client.as_mut().expect("checked by the callers");
+        client.running = false;
+        client.stepping = None;
+        let result = client.event("stopped", stopped_body(body));
+        if result.is_err() {
+            self.client = None;
+        }
+        result
+    }
+}
+
+impl Client {
+    fn new(stream: TcpStream) -> Client {
+        Client {
+            stream,
+            input: Vec::new(),
+            seq: 0,
+            running: false,
+            stepping: None,
+            stop_on_entry: false,
+            pending: None,
+            launch: None,
+            source_breakpoints: BTreeMap::new(),
+            function_breakpoints: Vec::new(),
+            instruction_breakpoints: Vec::new(),
+            disconnected: false,
+            events: Vec::new(),
+        }
+    }
+
+    fn poll(
+        &mut self,
+        target: &mut Option<Target>,
+        lines: &LineMap,
+        source_dir: &Path,
+    ) -> io::Result<Option<Launch>> {
+        let mut buffer = [0; 4096];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "the editor closed the connection",
+                    ))
+                }
+                Ok(len) => self.input.extend_from_slice(&buffer[..len]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+        while let Some(message) = self.take()? {
+            let seq = message["seq"].as_u64().unwrap_or(0);
+            let command = message["command"].as_str().unwrap_or("").to_string();
+            let arguments = &message["arguments"];
+            let context = Context { lines, source_dir };
+            match self.handle(&command, arguments, target, &context) {
+                // answered by DapServer::launched
+                Ok(None) => self.pending = Some(seq),
+                Ok(Some(body)) => self.respond(seq, &command, Ok(body))?,
+                Err(message) => self.respond(seq, &command, Err(message))?,
+            }
+            for (event, body) in std::mem::take(&mut self.events) {
+                self.event(event, body)?;
+            }
+        }
+        Ok(self.launch.take())
+    }
+
+    // the next whole message in the input, each is a json object after a
+    // Content-Length header
+    fn take(&mut self) -> io::Result<Option<Value>> {
+        let end = match self
+            .input
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+        {
+            Some(end) => end,
+            None => return Ok(None),
+        };
+        let header = String::from_utf8_lossy(&self.input[..end]).into_owned();
+        let len = header
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+            .and_then(|(_, len)| len.trim().parse::<usize>().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+        if self.input.len() < end + 4 + len {
+            return Ok(None);
+        }
+        let message: Vec<u8> = self.input.drain(..end + 4 + len).skip(end + 4).collect();
+        serde_json::from_slice(&message)
+            .map(Some)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    // the body of the response, None for a launch the frontend answers
+    fn handle(
+        &mut self,
+        command: &str,
+        arguments: &Value,
+        target: &mut Option<Target>,
+        context: &Context,
+    ) -> Result<Option<Value>, String> {
+        let body = match command {
+            "initialize" => json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsFunctionBreakpoints": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsSetVariable": true,
+                "supportsReadMemoryRequest": true,
+                "supportsDisassembleRequest": true,
+                "supportsEvaluateForHovers": true,
+            }),
+            "launch" => {
+                let program = arguments["program"]
+                    .as_str()
+                    .ok_or("launch expects a program")?;
+                let path = |name: &str| arguments[name].as_str().map(str::to_string);
+                self.launch = Some(Launch {
+                    program: program.to_string(),
+                    symbols: path("symbols"),
+                    line_map: path("lineMap"),
+                });
+                self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+                return Ok(None);
+            }
+            "attach" => {
+                self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+                self.events.push(("initialized", Value::Null));
+                Value::Null
+            }
+            "configurationDone" => {
+                match self.stop_on_entry {
+                    true => self.stop_after("entry"),
+                    false => self.running = true,
+                }
+                Value::Null
+            }
+            "threads" => json!({ "threads": [{ "id": THREAD, "name": "chip8" }] }),
+            "disconnect" | "terminate" => {
+                self.running = true;
+                self.disconnected = true;
+                Value::Null
+            }
+            "pause" => {
+                if self.running {
+                    self.stop_after("pause");
+                }
+                Value::Null
+            }
+            _ => {
+                let target = target.as_mut().ok_or("no rom is loaded")?;
+                self.handle_target(command, arguments, target, context)?
+            }
+        };
+        Ok(Some(body))
+    }
+
+    fn handle_target(
+        &mut self,
+        command: &str,
+        arguments: &Value,
+        target: &mut Target,
+        context: &Context,
+    ) -> Result<Value, String> {
+        let chip = &mut *target.chip;
+        let symbols = target.symbols;
+        let body = match command {
+            "setBreakpoints" => {
+                let path = arguments["source"]["path"]
+                    .as_str()
+                    .ok_or("setBreakpoints expects a source path")?;
+                let requested: Vec<u64> = arguments["breakpoints"]
+                    .as_array()
+                    .map(|breakpoints| {
+                        breakpoints
+                            .iter()
+                            .filter_map(|breakpoint| breakpoint["line"].as_u64())
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let mut set = Vec::new();
+                let mut replies = Vec::new();
+                for line in requested {
+                    match context.lines.find(path, line as u32) {
+                        Some((address, line)) => {
+                            set.push(address);
+                            replies.push(json!({
+                                "verified": true,
+                                "line": line,
+                                "instructionReference": format!("{:#05x}", address),
+                            }));
+                        }
+                        None => replies.push(json!({
+                            "verified": false,
+                            "line": line,
+                            "message": "no instruction on or after this line",
+                        })),
+                    }
+                }
+                self.source_breakpoints.insert(path.to_string(), set);
+                self.sync_breakpoints(target.breakpoints);
+                json!({ "breakpoints": replies })
+            }
+            "setFunctionBreakpoints" | "setInstructionBreakpoints" => {
+                let (previous, field) = match command {
+                    "setFunctionBreakpoints" => (&mut self.function_breakpoints, "name"),
+                    _ => (&mut self.instruction_breakpoints, "instructionReference"),
+                };
+                previous.clear();
+                let mut replies = Vec::new();
+                for breakpoint in arguments["breakpoints"].as_array().into_iter().flatten() {
+                    let offset = breakpoint["offset"].as_i64().unwrap_or(0);
+                    let resolved = symbols
+                        .resolve(breakpoint[field].as_str().unwrap_or(""))
+                        .map(|address| (address as i64).wrapping_add(offset) as u16 & 0xfff);
+                    match resolved {
+                        Ok(address) => {
+                            previous.push(address);
+                            replies.push(json!({
+                                "verified": true,
+                                "instructionReference": format!("{:#05x}", address),
+                            }));
+                        }
+                        Err(err) => replies.push(json!({ "verified": false, "message": err })),
+                    }
+                }
+                self.sync_breakpoints(target.breakpoints);
+                json!({ "breakpoints": replies })
+            }
+            "continue" => {
+                self.running = true;
+                json!({ "allThreadsContinued": true })
+            }
+            "next" if opcode_at(chip.memory(), chip.pc()) & 0xf000 == 0x2000 => {
+                self.run_until(chip.pc() + 2, target.breakpoints);
+                Value::Null
+            }
+            "stepOut" if !chip.stack().is_empty() => {
+                let address = *chip.stack().last().expect("checked above");
+                self.run_until(address, target.breakpoints);
+                Value::Null
+            }
+            // one instruction, stepping out of the outermost routine too
+            "stepIn" | "next" | "stepOut" => {
+                match chip.step() {
+                    Ok(()) => self.stop_after("step"),
+                    Err(err) => self.events.push((
+                        "stopped",
+                        stopped_body(json!({
+                            "reason": "exception",
+                            "description": err.to_string(),
+                            "text": err.to_string(),
+                        })),
+                    )),
+                }
+                self.running = false;
+                Value::Null
+            }
+            "stackTrace" => {
+                let mut addresses = vec![chip.pc()];
+                // the calls, from the innermost out
+                addresses.extend(
+                    chip.stack()
+                        .iter()
+                        .rev()
+                        .map(|&r| r.wrapping_sub(2) & 0xfff),
+                );
+                let frames: Vec<Value> = addresses
+                    .iter()
+                    .enumerate()
+                    .map(|(id, &address)| frame(id, address, chip, symbols, context))
+                    .collect();
+                json!({ "stackFrames": frames, "totalFrames": frames.len() })
+            }
+            "scopes" => json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS, "expensive": false },
+                { "name": "Stack", "variablesReference": STACK, "expensive": false },
+                { "name": "Memory", "variablesReference": MEMORY, "expensive": true },
+            ] }),
+            "variables" => {
+                let variables = match arguments["variablesReference"].as_u64() {
+                    Some(REGISTERS) => registers(chip),
+                    Some(STACK) => stack(chip, symbols),
+                    Some(MEMORY) => memory(chip),
+                    _ => Vec::new(),
+                };
+                json!({ "variables": variables })
+            }
+            "setVariable" => {
+                let name = arguments["name"].as_str().unwrap_or("");
+                let value = arguments["value"].as_str().unwrap_or("");
+                let value = symbols.resolve(value)?;
+                match Expr::parse(name) {
+                    Ok(Expr::Register(x)) if value <= 0xff => chip.set_register(x, value as u8),
+                    Ok(Expr::Index) => chip.set_index(value),
+                    Ok(Expr::Pc) => chip.set_pc(value),
+                    _ => return Err(format!("{} can't be set to {}", name, value)),
+                }
+                let value = Expr::parse(name)?.evaluate(chip);
+                json!({ "value": format!("{:#x}", value) })
+            }
+            "evaluate" => {
+                let expression = arguments["expression"].as_str().unwrap_or("");
+                match Expr::parse(expression) {
+                    Ok(expr) => {
+                        let value = expr.evaluate(chip);
+                        json!({
+                            "result": format!("{:#x} ({})", value, value),
+                            "variablesReference": 0,
+                        })
+                    }
+                    // labels evaluate to their address
+                    Err(err) => {
+                        let address = symbols.resolve(expression).map_err(|_| err)?;
+                        json!({
+                            "result": format!("{:#05x}", address),
+                            "memoryReference": format!("{:#05x}", address),
+                            "variablesReference": 0,
+                        })
+                    }
+                }
+            }
+            "readMemory" => {
+                let address = memory_reference(arguments, symbols)?;
+                // nothing past memory can be read, the editor picks the count
+                let count = arguments["count"].as_u64().unwrap_or(0).min(4096) as usize;
+                let readable = match address {
+                    0..=4095 => {
+                        let start = address as usize;
+                        &chip.memory()[start..start.saturating_add(count).min(4096)]
+                    }
+                    _ => &[],
+                };
+                json!({
+                    "address": format!("{:#05x}", address),
+                    "data": base64(readable),
+                    "unreadableBytes": count - readable.len(),
+                })
+            }
+            "disassemble" => {
+                let address = memory_reference(arguments, symbols)?;
+                let offset = arguments["instructionOffset"].as_i64().unwrap_or(0);
+                // all of memory is 2048 instructions. the addresses wrap
+                // around it, which wrapping arithmetic keeps for any offset.
+                let count = arguments["instructionCount"]
+                    .as_u64()
+                    .unwrap_or(0)
+                    .min(2048) as i64;
+                let instructions: Vec<Value> = (0..count)
+                    .map(|n| offset.wrapping_add(n).wrapping_mul(2))
+                    .map(|n| address.wrapping_add(n).rem_euclid(4096) as u16)
+                    .map(|address| instruction(address, chip, symbols, context))
+                    .collect();
+                json!({ "instructions": instructions })
+            }
+            _ => return Err(format!("{} is not supported", command)),
+        };
+        Ok(body)
+    }
+
+    // runs on until the rom gets to address, for stepping over a call or
+    // out of a subroutine
+    fn run_until(&mut self, address: u16, breakpoints: &mut Breakpoints) {
+        breakpoints.run_until(Some(address));
+        self.stepping = Some(address & 0xfff);
+        self.running = true;
+    }
+
+    // stops the chip and tells the editor once the request is answered
+    fn stop_after(&mut self, reason: &str) {
+        self.running = false;
+        self.events
+            .push(("stopped", stopped_body(json!({ "reason": reason }))));
+    }
+
+    // hands the editor's breakpoints to the shared ones, replacing what it
+    // set before without touching breakpoints set elsewhere
+    fn sync_breakpoints(&self, breakpoints: &mut Breakpoints) {
+        let set = self.source_breakpoints.values().flatten();
+        let set = set
+            .chain(&self.function_breakpoints)
+            .chain(&self.instruction_breakpoints);
+        breakpoints.set_editor(set.copied());
+    }
+
+    fn clear_breakpoints(&self, breakpoints: &mut Breakpoints) {
+        breakpoints.set_editor(std::iter::empty());
+    }
+
+    fn respond(
+        &mut self,
+        request: u64,
+        command: &str,
+        body: Result<Value, String>,
+    ) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request,
+            "command": command,
+            "success": body.is_ok(),
+        });
+        match body {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = Value::String(message),
+        }
+        self.send(response)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        let mut message = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+        self.send(message)
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        let content = message.to_string();
+        let framed = format!("Content-Length: {}\r\n\r\n{}", content.len(), content);
+        let mut bytes = framed.as_bytes();
+        // the stream doesn't block, so a full send buffer is waited out here
+        while !bytes.is_empty() {
+            match self.stream.write(bytes) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(len) => bytes = &bytes[len..],
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    std::thread::sleep(Duration::from_millis(1))
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+// what the requests about the rom need besides the target
+struct Context<'a> {
+    lines: &'a LineMap,
+    source_dir: &'a Path,
+}
+
+impl Context<'_> {
+    // the source and line of the instruction at address for a stack frame
+    // or disassembled instruction
+    fn locate(&self, address: u16, into: &mut Value) {
+        if let Some((file, line)) = self.lines.lookup(address) {
+            let name = Path::new(file)
+                .file_name()
+                .map_or(file.into(), |name| name.to_string_lossy());
+            into["source"] = json!({
+                "name": name,
+                "path": self.source_dir.join(file).to_string_lossy(),
+            });
+            into["line"] = json!(line);
+        }
+    }
+}
+
+fn stopped_body(mut body: Value) -> Value {
+    body["threadId"] = json!(THREAD);
+    body["allThreadsStopped"] = json!(true);
+    body
+}
+
+fn frame(id: usize, address: u16, chip: &Chip8, symbols: &Symbols, context: &Context) -> Value {
+    let opcode = opcode_at(chip.memory(), address);
+    let mut frame = json!({
+        "id": id,
+        "name": format!("{}: {}", symbols.describe(address), disassemble_with(opcode, symbols)),
+        "line": 0,
+        "column": 0,
+        "instructionPointerReference": format!("{:#05x}", address),
+    });
+    context.locate(address, &mut frame);
+    if frame["source"].is_object() {
+        frame["column"] = json!(1);
+    }
+    frame
+}
+
+fn instruction(address: u16, chip: &Chip8, symbols: &Symbols, context: &Context) -> Value {
+    let opcode = opcode_at(chip.memory(), address);
+    let mut instruction = json!({
+        "address": format!("{:#05x}", address),
+        "instructionBytes": format!("{:04x}", opcode),
+        "instruction": disassemble_with(opcode, symbols),
+    });
+    if let Some(label) = symbols.label(address) {
+        instruction["symbol"] = json!(label);
+    }
+    context.locate(address, &mut instruction);
+    instruction
+}
+
+fn registers(chip: &Chip8) -> Vec<Value> {
+    let mut variables: Vec<Value> = chip
+        .registers()
+        .iter()
+        .enumerate()
+        .map(|(x, value)| variable(&format!("v{:x}", x), format!("{:#04x}", value)))
+        .collect();
+    let mut index = variable("i", format!("{:#05x}", chip.index()));
+    index["memoryReference"] = json!(format!("{:#05x}", chip.index()));
+    variables.push(index);
+    variables.push(variable("pc", format!("{:#05x}", chip.pc())));
+    variables.push(variable("sp", chip.sp().to_string()));
+    variables.push(variable("dt", chip.get_delay_timer().to_string()));
+    variables.push(variable("st", chip.get_sound_timer().to_string()));
+    variables
+}
+
+// the return addresses, the outermost call first
+fn stack(chip: &Chip8, symbols: &Symbols) -> Vec<Value> {
+    chip.stack()
+        .iter()
+        .enumerate()
+        .map(|(n, &address)| {
+            let value = format!("{:#05x} {}", address, symbols.describe(address));
+            variable(&n.to_string(), value)
+        })
+        .collect()
+}
+
+fn memory(chip: &Chip8) -> Vec<Value> {
+    chip.memory()
+        .chunks(16)
+        .enumerate()
+        .map(|(row, bytes)| {
+            let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let mut variable = variable(&format!("{:#05x}", row * 16), hex.join(" "));
+            variable["memoryReference"] = json!(format!("{:#05x}", row * 16));
+            variable
+        })
+        .collect()
+}
+
+fn variable(name: &str, value: String) -> Value {
+    json!({ "name": name, "value": value, "variablesReference": 0 })
+}
+
+// the memoryReference of a request, an address or a label, plus its offset
+fn memory_reference(arguments: &Value, symbols: &Symbols) -> Result<i64, String> {
+    let reference = arguments["memoryReference"]
+        .as_str()
+        .ok_or("expected a memoryReference")?;
+    let address = symbols.resolve(reference)?;
+    let offset = arguments["offset"].as_i64().unwrap_or(0);
+    Ok((address as i64).saturating_add(offset))
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let word = chunk.iter().enumerate().fold(0u32, |word, (n, &byte)| {
+            word | (byte as u32) << (16 - 8 * n)
+        });
+        for n in 0..4 {
+            match n <= chunk.len() {
+                true => encoded.push(ALPHABET[(word >> (18 - 6 * n) & 0x3f) as usize] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Quirks;
+
+    // sends a request as an editor would and polls the server until the
+    // messages it expects have come back
+    fn exchange(
+        server: &mut DapServer,
+        editor: &mut TcpStream,
+        target: &mut (Chip8, Breakpoints, Symbols),
+        request: Value,
+        expected: usize,
+    ) -> Vec<Value> {
+        let content = request.to_string();
+        write!(
+            editor,
+            "Content-Length: {}\r\n\r\n{}",
+            content.len(),
+            content
+        )
+        .unwrap();
+        let mut received = Vec::new();
+        let mut messages = Vec::new();
+        for _ in 0..200 {
+            let (chip, breakpoints, symbols) = target;
+            server
+                .poll(Some(Target {
+                    chip,
+                    breakpoints,
+                    symbols,
+                }))
+                .unwrap();
+            let mut buffer = [0; 65536];
+            if let Ok(len) = editor.read(&mut buffer) {
+                received.extend_from_slice(&buffer[..len]);
+            }
+            while let Some(end) = received.windows(4).position(|w| w == b"\r\n\r\n") {
+                let header = String::from_utf8_lossy(&received[..end]).into_owned();
+                let len: usize = header["Content-Length: ".len()..].parse().unwrap();
+                if received.len() < end + 4 + len {
+                    break;
+                }
+                let body: Vec<u8> = received.drain(..end + 4 + len).skip(end + 4).collect();
+                messages.push(serde_json::from_slice(&body).unwrap());
+            }
+            if messages.len() >= expected {
+                break;
+            }
+        }
+        messages
+    }
+
+    #[test]
+    fn test_dap() {
+        let mut server = DapServer::bind("127.0.0.1:0").unwrap();
+        server.set_line_map(
+            LineMap::parse("0x200 game.8o:1\n0x202 game.8o:2\n0x206 game.8o:5\n").unwrap(),
+            Path::new("/roms"),
+        );
+        let mut editor = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+        editor
+            .set_read_timeout(Some(Duration::from_millis(5)))
+            .unwrap();
+        // 200: ld v3, 0x2a, 202: call 206, 204: jp 204, 206: ret
+        let chip = Chip8::load(vec![0x63, 0x2a, 0x22, 0x06, 0x12, 0x04, 0x00, 0xee]).unwrap();
+        let mut symbols = Symbols::default();
+        symbols.insert("routine", 0x206);
+        // also set from the command line, it has to outlive the editor's
+        let mut breakpoints = Breakpoints::default();
+        breakpoints.insert(0x206);
+        let mut target = (chip, breakpoints, symbols);
+        let mut send = |server: &mut DapServer,
+                        target: &mut (Chip8, Breakpoints, Symbols),
+                        command: &str,
+                        arguments: Value,
+                        expected| {
+            let request = json!({
+                "seq": 1, "type": "request", "command": command, "arguments": arguments,
+            });
+            exchange(server, &mut editor, target, request, expected)
+        };
+
+        let replies = send(&mut server, &mut target, "initialize", json!({}), 1);
+        assert_eq!(replies[0]["body"]["supportsConfigurationDoneRequest"], true);
+        let replies = send(
+            &mut server,
+            &mut target,
+            "attach",
+            json!({ "stopOnEntry": true }),
+            2,
+        );
+        assert_eq!(replies[1]["event"], "initialized");
+        assert!(server.halted());
+
+        let replies = send(
+            &mut server,
+            &mut target,
+            "setBreakpoints",
+            json!({ "source": { "path": "/roms/game.8o" }, "breakpoints": [{ "line": 4 }, { "line": 9 }] }),
+            1,
+        );
+        let breakpoints = &replies[0]["body"]["breakpoints"];
+        assert_eq!(breakpoints[0]["line"], 5);
+        assert_eq!(breakpoints[1]["verified"], false);
+
+        let replies = send(&mut server, &mut target, "configurationDone", json!({}), 2);
+        assert_eq!(replies[1]["body"]["reason"], "entry");
+
+        // one instruction, then over the call
+        let replies = send(
+            &mut server,
+            &mut target,
+            "stepIn",
+            json!({ "threadId": 1 }),
+            2,
+        );
+        assert_eq!(replies[1]["body"]["reason"], "step");
+        send(
+            &mut server,
+            &mut target,
+            "next",
+            json!({ "threadId": 1 }),
+            1,
+        );
+        assert!(!server.halted());
+        let (chip, breakpoints, _) = &mut target;
+        // the frontend runs into the breakpoint in the routine first
+        chip.cycle().unwrap();
+        assert!(breakpoints.hit(chip.pc()));
+        server.stop(chip, breakpoints).unwrap();
+        assert!(server.halted());
+
+        // the stopped event was sent before the request
+        let replies = send(
+            &mut server,
+            &mut target,
+            "stackTrace",
+            json!({ "threadId": 1 }),
+            2,
+        );
+        assert_eq!(replies[0]["body"]["reason"], "breakpoint");
+        let frames = &replies[1]["body"]["stackFrames"];
+        assert_eq!(frames[0]["name"], "routine: ret");
+        assert_eq!(frames[0]["line"], 5);
+        assert_eq!(frames[0]["source"]["path"], "/roms/game.8o");
+        assert_eq!(frames[1]["instructionPointerReference"], "0x202");
+
+        let replies = send(
+            &mut server,
+            &mut target,
+            "variables",
+            json!({ "variablesReference": 1 }),
+            1,
+        );
+        assert_eq!(replies[0]["body"]["variables"][3]["value"], "0x2a");
+        let replies = send(
+            &mut server,
+            &mut target,
+            "setVariable",
+            json!({ "variablesReference": 1, "name": "v3", "value": "7" }),
+            1,
+        );
+        assert_eq!(replies[0]["body"]["value"], "0x7");
+        let replies = send(
+            &mut server,
+            &mut target,
+            "evaluate",
+            json!({ "expression": "v3" }),
+            1,
+        );
+        assert_eq!(replies[0]["body"]["result"], "0x7 (7)");
+        let replies = send(
+            &mut server,
+            &mut target,
+            "readMemory",
+            json!({ "memoryReference": "0x200", "count": 4 }),
+            1,
+        );
+        assert_eq!(
+            replies[0]["body"]["data"],
+            base64(&[0x63, 0x2a, 0x22, 0x06])
+        );
+        let replies = send(
+            &mut server,
+            &mut target,
+            "disassemble",
+            json!({ "memoryReference": "routine", "instructionCount": 1 }),
+            1,
+        );
+        assert_eq!(replies[0]["body"]["instructions"][0]["instruction"], "ret");
+
+        // counts and offsets from the editor are not trusted
+        let replies = send(
+            &mut server,
+            &mut target,
+            "readMemory",
+            json!({ "memoryReference": "0xffe", "count": u64::MAX }),
+            1,
+        );
+        assert_eq!(replies[0]["body"]["unreadableBytes"], 4094);
+        let replies = send(
+            &mut server,
+            &mut target,
+            "disassemble",
+            json!({
+                "memoryReference": "0x200",
+                "instructionOffset": i64::MAX,
+                "instructionCount": u64::MAX,
+            }),
+            1,
+        );
+        let instructions = replies[0]["body"]["instructions"].as_array().unwrap();
+        assert_eq!(instructions.len(), 2048);
+
+        // out of the routine, stopping back after the call
+        send(
+            &mut server,
+            &mut target,
+            "stepOut",
+            json!({ "threadId": 1 }),
+            1,
+        );
+        let (chip, breakpoints, _) = &mut target;
+        while !breakpoints.hit(chip.pc()) {
+            chip.cycle().unwrap();
+        }
+        assert_eq!(chip.pc(), 0x204);
+        server.stop(chip, breakpoints).unwrap();
+        let replies = send(&mut server, &mut target, "threads", json!({}), 2);
+        assert_eq!(replies[0]["body"]["reason"], "step");
+
+        // stepping goes on after a draw that waits for vblank,
+        // 204: drw v0, v0, 1, 206: add v3, 1
+        let (chip, _, _) = &mut target;
+        chip.set_quirks(Quirks {
+            vblank: true,
+            ..Quirks::default()
+        });
+        for (offset, &byte) in [0xd0, 0x01, 0x73, 0x01].iter().enumerate() {
+            chip.poke(0x204 + offset as u16, byte);
+        }
+        for _ in 0..2 {
+            let replies = send(
+                &mut server,
+                &mut target,
+                "stepIn",
+                json!({ "threadId": 1 }),
+                2,
+            );
+            assert_eq!(replies[1]["body"]["reason"], "step");
+        }
+        assert_eq!((target.0.pc(), target.0.registers()[3]), (0x208, 8));
+        let replies = send(
+            &mut server,
+            &mut target,
+            "stepBack",
+            json!({ "threadId": 1 }),
+            1,
+        );
+        assert_eq!(replies[0]["success"], false);
+
+        let replies = send(&mut server, &mut target, "launch", json!({}), 1);
+        assert_eq!(replies[0]["success"], false);
+        send(&mut server, &mut target, "disconnect", json!({}), 1);
+        assert!(!server.connected());
+        assert_eq!(target.1.addresses().collect::<Vec<_>>(), [0x206]);
+
+        assert_eq!(base64(b"chip8"), "Y2hpcDg=");
+    }
+}
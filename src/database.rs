@@ -0,0 +1,262 @@
+// rom metadata looked up by the SHA-1 of the rom, read from json in the
+// format of the community chip-8-database (platforms.json and programs.json)
+use crate::chip8::Quirks;
+use crate::palette::Palette;
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+use std::collections::{BTreeMap, HashMap};
+
+const BUNDLED_PLATFORMS: &str = include_str!("../data/platforms.json");
+const BUNDLED_PROGRAMS: &str = include_str!("../data/programs.json");
+
+// what the database knows about a rom, anything it does not know is None
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomInfo {
+    pub title: String,
+    pub platform: Option<String>,
+    pub quirks: Option<Quirks>,
+    // instructions per frame
+    pub tickrate: Option<u32>,
+    pub palette: Option<Palette>,
+    // what the game uses each key for, e.g. "up" -> 0x5
+    pub keys: BTreeMap<String, u8>,
+}
+
+// quirks in the database may be partial, missing ones keep their current value
+#[derive(Deserialize, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+struct QuirkSet {
+    shift: Option<bool>,
+    memory_increment_by_x: Option<bool>,
+    memory_leave_i_unchanged: Option<bool>,
+    wrap: Option<bool>,
+    jump: Option<bool>,
+    vblank: Option<bool>,
+    logic: Option<bool>,
+    stack_depth: Option<u8>,
+}
+
+impl QuirkSet {
+    fn apply(&self, quirks: &mut Quirks) {
+        let fields = [
+            (self.shift, &mut quirks.shift),
+            (
+                self.memory_increment_by_x,
+                &mut quirks.memory_increment_by_x,
+            ),
+            (
+                self.memory_leave_i_unchanged,
+                &mut quirks.memory_leave_i_unchanged,
+            ),
+            (self.wrap, &mut quirks.wrap),
+            (self.jump, &mut quirks.jump),
+            (self.vblank, &mut quirks.vblank),
+            (self.logic, &mut quirks.logic),
+        ];
+        for (value, quirk) in fields {
+            if let Some(value) = value {
+                *quirk = value;
+            }
+        }
+        if let Some(depth) = self.stack_depth {
+            quirks.stack_depth = depth;
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Platform {
+    id: String,
+    default_tickrate: Option<u32>,
+    #[serde(default)]
+    quirks: QuirkSet,
+}
+
+#[derive(Deserialize)]
+struct Program {
+    title: String,
+    #[serde(default)]
+    roms: HashMap<String, Rom>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Rom {
+    #[serde(default)]
+    platforms: Vec<String>,
+    #[serde(default)]
+    quirky_platforms: HashMap<String, QuirkSet>,
+    tickrate: Option<u32>,
+    colors: Option<Colors>,
+    #[serde(default)]
+    keys: BTreeMap<String, u8>,
+}
+
+#[derive(Deserialize)]
+struct Colors {
+    #[serde(default)]
+    pixels: Vec<String>,
+}
+
+pub struct Database {
+    platforms: HashMap<String, Platform>,
+    // sha1 -> (program title, rom)
+    roms: HashMap<String, (String, Rom)>,
+}
+
+impl Platform {
+    // the chip-8-database has no stack depth, the COSMAC VIP interpreters
+    // had room for 12 calls and the later ones for 16
+    fn quirks(&self) -> Quirks {
+        let mut quirks = Quirks::default();
+        if matches!(self.id.as_str(), "originalChip8" | "hybridVIP") {
+            quirks.stack_depth = 12;
+        }
+        self.quirks.apply(&mut quirks);
+        quirks
+    }
+}
+
+impl Database {
+    pub fn empty() -> Database {
+        Database {
+            platforms: HashMap::new(),
+            roms: HashMap::new(),
+        }
+    }
+
+    // the database compiled into the binary, see data/README.md
+    pub fn bundled() -> Database {
+        let mut database = Database::empty();
+        database
+            .add_platforms(BUNDLED_PLATFORMS)
+            .expect("bundled platforms.json is invalid");
+        database
+            .add_programs(BUNDLED_PROGRAMS)
+            .expect("bundled programs.json is invalid");
+        database
+    }
+
+    // adds the platforms of a platforms.json, replacing ones with the same id
+    pub fn add_platforms(&mut self, json: &str) -> Result<(), String> {
+        let platforms: Vec<Platform> = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        for platform in platforms {
+            self.platforms.insert(platform.id.clone(), platform);
+        }
+        Ok(())
+    }
+
+    // adds the roms of a programs.json, replacing ones with the same hash
+    pub fn add_programs(&mut self, json: &str) -> Result<(), String> {
+        let programs: Vec<Program> = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        for program in programs {
+            for (hash, rom) in program.roms {
+                self.roms
+                    .insert(hash.to_lowercase(), (program.title.clone(), rom));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<RomInfo> {
+        let (title, rom) = self.roms.get(&rom_hash(rom))?;
+        // the first listed platform is the one the rom is meant for
+        let platform = rom.platforms.first();
+        let known_platform = platform.and_then(|id| self.platforms.get(id));
+
+        let quirks = known_platform.map(|known| {
+            let mut quirks = known.quirks();
+            if let Some(quirky) = rom.quirky_platforms.get(&known.id) {
+                quirky.apply(&mut quirks);
+            }
+            quirks
+        });
+        let palette = rom
+            .colors
+            .as_ref()
+            .and_then(|colors| match &colors.pixels[..] {
+                [background, foreground, ..] => {
+                    Palette::parse(&format!("{},{}", background, foreground))
+                        .ok()
+                        .map(|palette| Palette {
+                            name: title.clone(),
+                            ..palette
+                        })
+                }
+                _ => None,
+            });
+
+        Some(RomInfo {
+            title: title.clone(),
+            platform: platform.cloned(),
+            quirks,
+            tickrate: rom
+                .tickrate
+                .or_else(|| known_platform.and_then(|known| known.default_tickrate)),
+            palette,
+            keys: rom.keys.clone(),
+        })
+    }
+
+    // quirks and tickrate of a platform by its id, e.g. "originalChip8"
+    pub fn platform(&self, id: &str) -> Option<(Quirks, Option<u32>)> {
+        self.platforms
+            .get(id)
+            .map(|platform| (platform.quirks(), platform.default_tickrate))
+    }
+
+    pub fn platform_ids(&self) -> Vec<&str> {
+        let mut ids: Vec<&str> = self.platforms.keys().map(|id| id.as_str()).collect();
+        ids.sort_unstable();
+        ids
+    }
+}
+
+// lowercase hex SHA-1, the key roms are stored under
+pub fn rom_hash(rom: &[u8]) -> String {
+    format!("{:x}", Sha1::digest(rom))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let mut database = Database::bundled();
+        database
+            .add_programs(
+                r##"[{
+                    "title": "Quirky",
+                    "roms": {
+                        "A9993E364706816ABA3E25717850C26C9CD0D89D": {
+                            "platforms": ["originalChip8"],
+                            "quirkyPlatforms": { "originalChip8": { "shift": true } },
+                            "colors": { "pixels": ["#000000", "#ff0000"] },
+                            "keys": { "up": 5 }
+                        }
+                    }
+                }]"##,
+            )
+            .unwrap();
+
+        // sha1("abc")
+        let info = database.lookup(b"abc").unwrap();
+        assert_eq!(info.title, "Quirky");
+        assert_eq!(info.platform.as_deref(), Some("originalChip8"));
+        assert_eq!(info.tickrate, Some(15));
+        assert_eq!(info.palette.unwrap().foreground, [0xff, 0, 0]);
+        assert_eq!(info.keys.get("up"), Some(&5));
+
+        // the platform's quirks with the rom's overrides on top
+        let (mut expected, _) = database.platform("originalChip8").unwrap();
+        assert!(!expected.shift);
+        expected.shift = true;
+        assert_eq!(info.quirks, Some(expected));
+        assert_eq!(expected.stack_depth, 12);
+        assert_eq!(database.platform("superchip").unwrap().0.stack_depth, 16);
+
+        assert_eq!(database.lookup(b"not in the database"), None);
+    }
+}
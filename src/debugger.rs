@@ -0,0 +1,317 @@
+use crate::overlay::{Mark, Panel, Style};
+use crate::Settings;
+use chip8::disasm::{disassemble_with, opcode_at};
+use chip8::Chip8;
+use sdl2::keyboard::{Keycode, Mod};
+
+// bytes per row of the hex dump
+const ROW: usize = 8;
+// frames a changed byte keeps flashing
+const FLASH_FRAMES: u8 = 30;
+
+// what the debugger asks the frontend to do
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DebugAction {
+    Close,
+    HardReset,
+    ToggleBreakpoint(u16),
+}
+
+// the cell being edited
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Cursor {
+    Register(usize),
+    Index,
+    Pc,
+    Memory(usize),
+}
+
+// a live view of the machine: pc and registers, the call stack, the watches
+// and all 4 KiB of memory as a hex dump with pc and i highlighted and changed
+// bytes flashing. bytes and registers can be edited while it is paused.
+pub struct Debugger {
+    running: bool,
+    // the error that stopped the rom, shown until it runs again
+    fault: Option<String>,
+    cursor: Cursor,
+    // hex digits typed into the cursor so far
+    typed: String,
+    // memory as of the previous frame and the frames each byte has left to flash
+    previous: [u8; 4096],
+    flash: [u8; 4096],
+}
+
+impl Debugger {
+    // opens paused with the cursor on the instruction at pc
+    pub fn new(chip: &Chip8) -> Debugger {
+        Debugger {
+            running: false,
+            fault: None,
+            cursor: Cursor::Memory(chip.pc() as usize),
+            typed: String::new(),
+            previous: *chip.memory(),
+            flash: [0; 4096],
+        }
+    }
+
+    // the emulator keeps running under the debugger until it is paused
+    pub fn running(&self) -> bool {
+        self.running
+    }
+
+    // a breakpoint was reached
+    pub fn stop(&mut self, chip: &Chip8) {
+        self.running = false;
+        self.cursor = Cursor::Memory(chip.pc() as usize);
+    }
+
+    // the rom failed at pc, e.g. with a stack overflow
+    pub fn fault(&mut self, chip: &Chip8, error: String) {
+        self.stop(chip);
+        self.fault = Some(error);
+    }
+
+    // called once per frame to find the bytes that changed
+    pub fn update(&mut self, chip: &Chip8) {
+        for (address, (&now, before)) in chip
+            .memory()
+            .iter()
+            .zip(self.previous.iter_mut())
+            .enumerate()
+        {
+            if now != *before {
+                self.flash[address] = FLASH_FRAMES;
+                *before = now;
+            } else {
+                self.flash[address] = self.flash[address].saturating_sub(1);
+            }
+        }
+    }
+
+    pub fn key_down(
+        &mut self,
+        keycode: Keycode,
+        keymod: Mod,
+        chip: &mut Chip8,
+    ) -> Option<DebugAction> {
+        if let Some(digit) = hex_digit(keycode) {
+            if !self.running {
+                self.type_digit(digit, chip);
+            }
+            return None;
+        }
+        self.typed.clear();
+        match keycode {
+            Keycode::Escape | Keycode::F2 => return Some(DebugAction::Close),
+            Keycode::Space => {
+                self.running = !self.running;
+                self.fault = None;
+            }
+            Keycode::N if !self.running => {
+                self.fault = chip.step().err().map(|err| err.to_string());
+            }
+            Keycode::R if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) => {
+                return Some(DebugAction::HardReset)
+            }
+            Keycode::R => chip.reset(),
+            Keycode::F9 => {
+                let address = match self.cursor {
+                    Cursor::Memory(address) => address as u16,
+                    _ => chip.pc(),
+                };
+                return Some(DebugAction::ToggleBreakpoint(address));
+            }
+            Keycode::P => self.cursor = Cursor::Memory(chip.pc() as usize),
+            Keycode::I => self.cursor = Cursor::Memory(chip.index() as usize & 0xfff),
+            Keycode::Tab => {
+                self.cursor = match self.cursor {
+                    Cursor::Memory(_) => Cursor::Register(0),
+                    _ => Cursor::Memory(chip.pc() as usize),
+                }
+            }
+            Keycode::Left => self.cursor = self.step(-1),
+            Keycode::Right => self.cursor = self.step(1),
+            Keycode::Up => self.cursor = self.step(-(ROW as isize)),
+            Keycode::Down => self.cursor = self.step(ROW as isize),
+            Keycode::PageUp => self.cursor = self.step(-16 * ROW as isize),
+            Keycode::PageDown => self.cursor = self.step(16 * ROW as isize),
+            _ => {}
+        }
+        None
+    }
+
+    pub fn panel(&self, chip: &Chip8, settings: &Settings) -> Panel {
+        let symbols = &settings.symbols;
+        let registers = chip.registers();
+        let hex = |bytes: &[u8]| {
+            bytes
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let mut items = vec![
+            format!(
+                "pc {:03x}  i {:03x}  sp {:x}  dt {:02x}  st {:02x}",
+                chip.pc(),
+                chip.index(),
+                chip.sp(),
+                chip.get_delay_timer(),
+                chip.get_sound_timer()
+            ),
+            format!("v0-7 {}", hex(&registers[..8])),
+            format!("v8-f {}", hex(&registers[8..])),
+            format!(
+                "{}: {}",
+                symbols.describe(chip.pc()),
+                disassemble_with(opcode_at(chip.memory(), chip.pc()), symbols)
+            ),
+        ];
+        // the calls being run, innermost first, as the address each returns
+        // to and where it was called from
+        items.push(format!("stack {}/{}", chip.sp(), chip.stack_depth()));
+        items.extend(chip.stack().iter().rev().map(|&address| {
+            let call = address.wrapping_sub(2) & 0xfff;
+            format!("  {:03x} from {}", address, symbols.describe(call))
+        }));
+        let breakpoints: Vec<String> = settings
+            .breakpoints
+            .addresses()
+            .map(|address| symbols.describe(address))
+            .collect();
+        if !breakpoints.is_empty() {
+            items.push(format!("break {}", breakpoints.join(" ")));
+        }
+        items.extend(settings.watches.iter().map(|watch| watch.describe(chip)));
+        let dump = items.len() + 1;
+        items.push(String::new());
+        for (row, bytes) in chip.memory().chunks(ROW).enumerate() {
+            items.push(format!("{:03x}: {}", row * ROW, hex(bytes)));
+        }
+
+        let byte_mark = |address: usize, style: Style| Mark {
+            item: dump + address / ROW,
+            column: 5 + 3 * (address % ROW),
+            len: 2,
+            style,
+        };
+        let mut marks: Vec<Mark> = self
+            .flash
+            .iter()
+            .enumerate()
+            .filter(|(_, &frames)| frames > 0)
+            .map(|(address, &frames)| {
+                byte_mark(address, Style::Flash(frames as f32 / FLASH_FRAMES as f32))
+            })
+            .collect();
+        // breakpoints in red, under pc and the cursor
+        for address in settings.breakpoints.addresses() {
+            marks.push(byte_mark(address as usize, Style::Flash(0.5)));
+        }
+        // the instruction at pc is two bytes
+        let pc = chip.pc() as usize & 0xfff;
+        marks.push(byte_mark(pc, Style::Strong));
+        marks.push(byte_mark((pc + 1) & 0xfff, Style::Strong));
+        marks.push(byte_mark(chip.index() as usize & 0xfff, Style::Underline));
+        let (item, column, len) = match self.cursor {
+            Cursor::Pc => (0, 3, 3),
+            Cursor::Index => (0, 10, 3),
+            Cursor::Register(x) => (1 + x / 8, 5 + 3 * (x % 8), 2),
+            Cursor::Memory(address) => (dump + address / ROW, 5 + 3 * (address % ROW), 2),
+        };
+        marks.push(Mark {
+            item,
+            column,
+            len,
+            style: Style::Cursor,
+        });
+
+        let footer = match (self.running, self.typed.is_empty()) {
+            (true, _) => "space: pause  escape: close".to_string(),
+            (false, true) => "space: run  n: step  0-f: edit  f9: break".to_string(),
+            (false, false) => format!("typed {}", self.typed),
+        };
+        Panel {
+            title: format!(
+                "debugger - {} - {}",
+                settings.title,
+                match (&self.fault, self.running) {
+                    (Some(fault), _) => fault,
+                    (None, true) => "running",
+                    (None, false) => "paused",
+                }
+            ),
+            items,
+            selected: item,
+            footer,
+            marks,
+        }
+    }
+
+    // digits are collected until the cell is full, then written and the cursor moves on
+    fn type_digit(&mut self, digit: char, chip: &mut Chip8) {
+        self.typed.push(digit);
+        let width = match self.cursor {
+            Cursor::Pc | Cursor::Index => 3,
+            _ => 2,
+        };
+        if self.typed.len() < width {
+            return;
+        }
+        let value = u16::from_str_radix(&self.typed, 16).expect("typed only hex digits");
+        self.typed.clear();
+        match self.cursor {
+            Cursor::Register(x) => chip.set_register(x, value as u8),
+            Cursor::Index => chip.set_index(value),
+            Cursor::Pc => chip.set_pc(value),
+            Cursor::Memory(address) => chip.poke(address as u16, value as u8),
+        }
+        // the edit is not a change made by the rom
+        self.previous = *chip.memory();
+        self.cursor = self.step(1);
+    }
+
+    // moves through memory, or through v0-vf, i and pc
+    fn step(&self, delta: isize) -> Cursor {
+        match self.cursor {
+            Cursor::Memory(address) => {
+                Cursor::Memory((address as isize + delta).rem_euclid(4096) as usize)
+            }
+            register => {
+                let index = match register {
+                    Cursor::Register(x) => x as isize,
+                    Cursor::Index => 16,
+                    _ => 17,
+                };
+                match (index + delta.clamp(-8, 8)).rem_euclid(18) {
+                    16 => Cursor::Index,
+                    17 => Cursor::Pc,
+                    x => Cursor::Register(x as usize),
+                }
+            }
+        }
+    }
+}
+
+fn hex_digit(keycode: Keycode) -> Option<char> {
+    let digit = match keycode {
+        Keycode::Num0 | Keycode::Kp0 => '0',
+        Keycode::Num1 | Keycode::Kp1 => '1',
+        Keycode::Num2 | Keycode::Kp2 => '2',
+        Keycode::Num3 | Keycode::Kp3 => '3',
+        Keycode::Num4 | Keycode::Kp4 => '4',
+        Keycode::Num5 | Keycode::Kp5 => '5',
+        Keycode::Num6 | Keycode::Kp6 => '6',
+        Keycode::Num7 | Keycode::Kp7 => '7',
+        Keycode::Num8 | Keycode::Kp8 => '8',
+        Keycode::Num9 | Keycode::Kp9 => '9',
+        Keycode::A => 'a',
+        Keycode::B => 'b',
+        Keycode::C => 'c',
+        Keycode::D => 'd',
+        Keycode::E => 'e',
+        Keycode::F => 'f',
+        _ => return None,
+    };
+    Some(digit)
+}
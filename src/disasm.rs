@@ -0,0 +1,159 @@
+// turns opcodes back into assembly, used by the profiler and the debugger.
+// mnemonics follow Cowgod's technical reference in lower case.
+use crate::symbols::Symbols;
+
+// the opcode with its operands spelled out, e.g. "ld v3, 0x2a"
+pub fn disassemble(opcode: u16) -> String {
+    let x = (opcode >> 8) & 0xf;
+    let y = (opcode >> 4) & 0xf;
+    let n = opcode & 0xf;
+    let nn = opcode & 0xff;
+    let nnn = opcode & 0xfff;
+    match pattern(opcode) {
+        "00e0" => "cls".to_string(),
+        "00ee" => "ret".to_string(),
+        "0nnn" => format!("sys {:#05x}", nnn),
+        "1nnn" => format!("jp {:#05x}", nnn),
+        "2nnn" => format!("call {:#05x}", nnn),
+        "3xnn" => format!("se v{:x}, {:#04x}", x, nn),
+        "4xnn" => format!("sne v{:x}, {:#04x}", x, nn),
+        "5xy0" => format!("se v{:x}, v{:x}", x, y),
+        "6xnn" => format!("ld v{:x}, {:#04x}", x, nn),
+        "7xnn" => format!("add v{:x}, {:#04x}", x, nn),
+        "8xy0" => format!("ld v{:x}, v{:x}", x, y),
+        "8xy1" => format!("or v{:x}, v{:x}", x, y),
+        "8xy2" => format!("and v{:x}, v{:x}", x, y),
+        "8xy3" => format!("xor v{:x}, v{:x}", x, y),
+        "8xy4" => format!("add v{:x}, v{:x}", x, y),
+        "8xy5" => format!("sub v{:x}, v{:x}", x, y),
+        "8xy6" => format!("shr v{:x}, v{:x}", x, y),
+        "8xy7" => format!("subn v{:x}, v{:x}", x, y),
+        "8xye" => format!("shl v{:x}, v{:x}", x, y),
+        "9xy0" => format!("sne v{:x}, v{:x}", x, y),
+        "annn" => format!("ld i, {:#05x}", nnn),
+        "bnnn" => format!("jp v0, {:#05x}", nnn),
+        "cxnn" => format!("rnd v{:x}, {:#04x}", x, nn),
+        "dxyn" => format!("drw v{:x}, v{:x}, {}", x, y, n),
+        "ex9e" => format!("skp v{:x}", x),
+        "exa1" => format!("sknp v{:x}", x),
+        "fx07" => format!("ld v{:x}, dt", x),
+        "fx0a" => format!("ld v{:x}, k", x),
+        "fx15" => format!("ld dt, v{:x}", x),
+        "fx18" => format!("ld st, v{:x}", x),
+        "fx1e" => format!("add i, v{:x}", x),
+        "fx29" => format!("ld f, v{:x}", x),
+        "fx33" => format!("ld b, v{:x}", x),
+        "fx55" => format!("ld [i], v{:x}", x),
+        "fx65" => format!("ld v{:x}, [i]", x),
+        "fx75" => format!("ld r, v{:x}", x),
+        "fx85" => format!("ld v{:x}, r", x),
+        _ => format!("dw {:#06x}", opcode),
+    }
+}
+
+// the same with addresses shown as labels, e.g. "call draw_player"
+pub fn disassemble_with(opcode: u16, symbols: &Symbols) -> String {
+    let nnn = opcode & 0xfff;
+    match pattern(opcode) {
+        "0nnn" | "1nnn" | "2nnn" | "annn" | "bnnn" if !symbols.is_empty() => {
+            disassemble(opcode).replace(&format!("{:#05x}", nnn), &symbols.describe(nnn))
+        }
+        _ => disassemble(opcode),
+    }
+}
+
+// the class of the opcode as it is usually written, e.g. "8xy4" for any
+// addition of two registers. "data" for words that are not instructions.
+// the interpreter ignores the low nibble of 5XY0 and 9XY0, so does this.
+pub fn pattern(opcode: u16) -> &'static str {
+    match opcode & 0xf000 {
+        0x0000 => match opcode {
+            0x00e0 => "00e0",
+            0x00ee => "00ee",
+            _ => "0nnn",
+        },
+        0x1000 => "1nnn",
+        0x2000 => "2nnn",
+        0x3000 => "3xnn",
+        0x4000 => "4xnn",
+        0x5000 => "5xy0",
+        0x6000 => "6xnn",
+        0x7000 => "7xnn",
+        0x8000 => match opcode & 0xf {
+            0x0 => "8xy0",
+            0x1 => "8xy1",
+            0x2 => "8xy2",
+            0x3 => "8xy3",
+            0x4 => "8xy4",
+            0x5 => "8xy5",
+            0x6 => "8xy6",
+            0x7 => "8xy7",
+            0xe => "8xye",
+            _ => "data",
+        },
+        0x9000 => "9xy0",
+        0xa000 => "annn",
+        0xb000 => "bnnn",
+        0xc000 => "cxnn",
+        0xd000 => "dxyn",
+        0xe000 => match opcode & 0xff {
+            0x9e => "ex9e",
+            0xa1 => "exa1",
+            _ => "data",
+        },
+        0xf000 => match opcode & 0xff {
+            0x07 => "fx07",
+            0x0a => "fx0a",
+            0x15 => "fx15",
+            0x18 => "fx18",
+            0x1e => "fx1e",
+            0x29 => "fx29",
+            0x33 => "fx33",
+            0x55 => "fx55",
+            0x65 => "fx65",
+            0x75 => "fx75",
+            0x85 => "fx85",
+            _ => "data",
+        },
+        _ => "data",
+    }
+}
+
+// the big endian opcode at address, wrapping around the end of memory
+pub fn opcode_at(memory: &[u8; 4096], address: u16) -> u16 {
+    let address = address as usize & 0xfff;
+    (memory[address] as u16) << 8 | memory[(address + 1) & 0xfff] as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disassemble() {
+        assert_eq!(disassemble(0x00e0), "cls");
+        assert_eq!(disassemble(0x2345), "call 0x345");
+        assert_eq!(disassemble(0x632a), "ld v3, 0x2a");
+        assert_eq!(disassemble(0x8ab4), "add va, vb");
+        assert_eq!(disassemble(0xd125), "drw v1, v2, 5");
+        assert_eq!(disassemble(0xf365), "ld v3, [i]");
+        assert_eq!(disassemble(0x5121), "se v1, v2");
+        assert_eq!(disassemble(0x9ab3), "sne va, vb");
+        assert_eq!(disassemble(0xf0ff), "dw 0xf0ff");
+
+        let mut symbols = Symbols::default();
+        symbols.insert("draw_player", 0x2a2);
+        assert_eq!(disassemble_with(0x22a6, &symbols), "call draw_player+4");
+        assert_eq!(disassemble_with(0xa2a2, &symbols), "ld i, draw_player");
+        assert_eq!(disassemble_with(0x632a, &symbols), "ld v3, 0x2a");
+
+        assert_eq!(pattern(0x8ab4), "8xy4");
+        assert_eq!(pattern(0x0123), "0nnn");
+        assert_eq!(pattern(0xe19f), "data");
+
+        let mut memory = [0; 4096];
+        memory[0xfff] = 0x12;
+        memory[0] = 0x34;
+        assert_eq!(opcode_at(&memory, 0xfff), 0x1234);
+    }
+}
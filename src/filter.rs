@@ -0,0 +1,205 @@
+// post processing of the framebuffer to hide the flicker caused by games
+// erasing (xor drawing) and redrawing their sprites every frame.
+// the result is an intensity per pixel, 0.0 is background and 1.0 foreground.
+use std::collections::VecDeque;
+
+const PIXELS: usize = 64 * 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum FilterMode {
+    // show every frame as is
+    #[default]
+    None,
+    // average of the last frames
+    Blend {
+        frames: usize,
+    },
+    // pixels that turn off fade out, multiplied by factor every frame
+    Decay {
+        factor: f32,
+    },
+    // only show frames where the last sprite drawn did not erase anything
+    Settled,
+}
+
+impl FilterMode {
+    // "none", "blend[:frames]", "decay[:factor]" or "settled"
+    pub fn parse(s: &str) -> Result<FilterMode, String> {
+        let mut parts = s.splitn(2, ':');
+        let name = parts.next().unwrap_or_default();
+        let arg = parts.next();
+        let invalid = || {
+            format!(
+                "invalid filter {}, expected none, blend[:frames], decay[:factor] or settled",
+                s
+            )
+        };
+        match (name, arg) {
+            ("none", None) => Ok(FilterMode::None),
+            ("settled", None) => Ok(FilterMode::Settled),
+            ("blend", None) => Ok(FilterMode::Blend { frames: 2 }),
+            ("blend", Some(frames)) => match frames.parse() {
+                Ok(frames) if frames > 0 => Ok(FilterMode::Blend { frames }),
+                _ => Err(invalid()),
+            },
+            ("decay", None) => Ok(FilterMode::Decay { factor: 0.5 }),
+            ("decay", Some(factor)) => match factor.parse() {
+                Ok(factor) if (0.0..1.0).contains(&factor) => Ok(FilterMode::Decay { factor }),
+                _ => Err(invalid()),
+            },
+            _ => Err(invalid()),
+        }
+    }
+
+    // the string parse turns back into this mode
+    pub fn spec(&self) -> String {
+        match self {
+            FilterMode::None => "none".to_string(),
+            FilterMode::Blend { frames } => format!("blend:{}", frames),
+            FilterMode::Decay { factor } => format!("decay:{}", factor),
+            FilterMode::Settled => "settled".to_string(),
+        }
+    }
+
+    // the next or previous mode for the settings menu, blend and decay with
+    // the defaults parse gives them
+    pub fn cycle(self, forward: bool) -> FilterMode {
+        let modes = [
+            FilterMode::None,
+            FilterMode::Blend { frames: 2 },
+            FilterMode::Decay { factor: 0.5 },
+            FilterMode::Settled,
+        ];
+        let index = match self {
+            FilterMode::None => 0,
+            FilterMode::Blend { .. } => 1,
+            FilterMode::Decay { .. } => 2,
+            FilterMode::Settled => 3,
+        };
+        let count = modes.len();
+        match forward {
+            true => modes[(index + 1) % count],
+            false => modes[(index + count - 1) % count],
+        }
+    }
+}
+
+pub struct Filter {
+    mode: FilterMode,
+    // previous raw frames, newest last, only kept for blending
+    history: VecDeque<[bool; PIXELS]>,
+    // the last output
+    intensity: [f32; PIXELS],
+}
+
+impl Filter {
+    pub fn new(mode: FilterMode) -> Filter {
+        Filter {
+            mode,
+            history: VecDeque::new(),
+            intensity: [0.0; PIXELS],
+        }
+    }
+
+    pub fn mode(&self) -> FilterMode {
+        self.mode
+    }
+
+    // settled is Chip8::display_settled for the frame, only used by FilterMode::Settled
+    pub fn apply(&mut self, gfx: &[bool; PIXELS], settled: bool) -> &[f32; PIXELS] {
+        match self.mode {
+            FilterMode::None => {
+                for (out, &pixel) in self.intensity.iter_mut().zip(gfx.iter()) {
+                    *out = on(pixel);
+                }
+            }
+            FilterMode::Blend { frames } => {
+                self.history.push_back(*gfx);
+                while self.history.len() > frames {
+                    self.history.pop_front();
+                }
+                let weight = 1.0 / self.history.len() as f32;
+                self.intensity = [0.0; PIXELS];
+                for frame in self.history.iter() {
+                    for (out, &pixel) in self.intensity.iter_mut().zip(frame.iter()) {
+                        *out += on(pixel) * weight;
+                    }
+                }
+            }
+            FilterMode::Decay { factor } => {
+                for (out, &pixel) in self.intensity.iter_mut().zip(gfx.iter()) {
+                    *out = on(pixel).max(*out * factor);
+                }
+            }
+            FilterMode::Settled => {
+                if settled {
+                    for (out, &pixel) in self.intensity.iter_mut().zip(gfx.iter()) {
+                        *out = on(pixel);
+                    }
+                }
+            }
+        }
+        &self.intensity
+    }
+}
+
+fn on(pixel: bool) -> f32 {
+    if pixel {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_modes() {
+        let mut lit = [false; PIXELS];
+        lit[0] = true;
+        let dark = [false; PIXELS];
+
+        let mut blend = Filter::new(FilterMode::Blend { frames: 2 });
+        assert_eq!(blend.apply(&lit, true)[0], 1.0);
+        assert_eq!(blend.apply(&dark, true)[0], 0.5);
+        assert_eq!(blend.apply(&dark, true)[0], 0.0);
+
+        let mut decay = Filter::new(FilterMode::Decay { factor: 0.5 });
+        assert_eq!(decay.apply(&lit, true)[0], 1.0);
+        assert_eq!(decay.apply(&dark, true)[0], 0.5);
+        assert_eq!(decay.apply(&dark, true)[0], 0.25);
+        assert_eq!(decay.apply(&lit, true)[0], 1.0);
+
+        // an unsettled frame keeps showing the last settled one
+        let mut settled = Filter::new(FilterMode::Settled);
+        assert_eq!(settled.apply(&lit, true)[0], 1.0);
+        assert_eq!(settled.apply(&dark, false)[0], 1.0);
+        assert_eq!(settled.apply(&dark, true)[0], 0.0);
+    }
+
+    #[test]
+    fn test_parse_filter_mode() {
+        assert_eq!(FilterMode::parse("none"), Ok(FilterMode::None));
+        assert_eq!(
+            FilterMode::parse("blend:3"),
+            Ok(FilterMode::Blend { frames: 3 })
+        );
+        assert_eq!(
+            FilterMode::parse("decay:0.25"),
+            Ok(FilterMode::Decay { factor: 0.25 })
+        );
+        assert!(FilterMode::parse("blend:0").is_err());
+        assert!(FilterMode::parse("decay:2").is_err());
+        assert!(FilterMode::parse("settled:1").is_err());
+
+        let mut mode = FilterMode::None;
+        for _ in 0..4 {
+            mode = mode.cycle(true);
+            assert_eq!(FilterMode::parse(&mode.spec()), Ok(mode));
+        }
+        assert_eq!(mode, FilterMode::None);
+        assert_eq!(mode.cycle(false), FilterMode::Settled);
+    }
+}
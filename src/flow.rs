@@ -0,0 +1,284 @@
+// static control flow analysis of a rom. starting at 0x200 it follows jumps,
+// calls, skips and returns without running anything, splits the reached
+// instructions into basic blocks and notes what it can't follow: BNNN
+// computed jumps and code that may be written over by FX33/FX55.
+use crate::disasm::{self, disassemble, opcode_at};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+const PROGRAM_START: u16 = 0x200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EdgeKind {
+    // on to the next instruction
+    Next,
+    Jump,
+    // into a subroutine, the block also goes on after the call as it returns
+    Call,
+    // the instruction after the skipped one
+    Skip,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub start: u16,
+    // one past the last instruction
+    pub end: u16,
+    pub edges: Vec<(u16, EdgeKind)>,
+}
+
+pub struct Flow {
+    memory: [u8; 4096],
+    program_end: u16,
+    blocks: BTreeMap<u16, Block>,
+    subroutines: BTreeSet<u16>,
+    computed_jumps: Vec<u16>,
+    self_modifying: Vec<(u16, u16)>,
+}
+
+impl Flow {
+    pub fn analyze(program: &[u8]) -> Flow {
+        let mut memory = [0; 4096];
+        let len = program.len().min(4096 - PROGRAM_START as usize);
+        memory[PROGRAM_START as usize..PROGRAM_START as usize + len]
+            .copy_from_slice(&program[..len]);
+        let program_end = PROGRAM_START + len as u16;
+
+        // every instruction that can be reached and the ones that start a block
+        let mut reached = BTreeSet::new();
+        let mut leaders = BTreeSet::from([PROGRAM_START]);
+        let mut subroutines = BTreeSet::new();
+        let mut computed_jumps = Vec::new();
+        let mut work = vec![PROGRAM_START];
+        while let Some(address) = work.pop() {
+            if address > 0xffe || !reached.insert(address) {
+                continue;
+            }
+            let opcode = opcode_at(&memory, address);
+            let (edges, ends_block) = edges(address, opcode);
+            if opcode & 0xf000 == 0xb000 {
+                computed_jumps.push(address);
+            }
+            if opcode & 0xf000 == 0x2000 {
+                subroutines.insert(opcode & 0xfff);
+            }
+            for (target, kind) in edges {
+                if ends_block || kind != EdgeKind::Next {
+                    leaders.insert(target);
+                }
+                work.push(target);
+            }
+        }
+
+        // a block runs from a leader up to an instruction that goes elsewhere
+        // or the next leader
+        let mut blocks = BTreeMap::new();
+        for &start in leaders.intersection(&reached) {
+            let mut address = start;
+            loop {
+                let (edges, ends_block) = edges(address, opcode_at(&memory, address));
+                let next = address + 2;
+                if ends_block || next > 0xffe || leaders.contains(&next) {
+                    let block = Block {
+                        start,
+                        end: next,
+                        edges,
+                    };
+                    blocks.insert(start, block);
+                    break;
+                }
+                address = next;
+            }
+        }
+
+        // I pointed into reached code by ANNN, with FX33 or FX55 somewhere
+        // that could write through it
+        let writes = reached.iter().any(|&address| {
+            matches!(
+                disasm::pattern(opcode_at(&memory, address)),
+                "fx33" | "fx55"
+            )
+        });
+        let self_modifying = reached
+            .iter()
+            .filter(|_| writes)
+            .map(|&address| (address, opcode_at(&memory, address)))
+            .filter(|(_, opcode)| opcode & 0xf000 == 0xa000)
+            .map(|(address, opcode)| (address, opcode & 0xfff))
+            .filter(|&(_, target)| {
+                reached.contains(&target) || reached.contains(&target.wrapping_sub(1))
+            })
+            .collect();
+
+        Flow {
+            memory,
+            program_end,
+            blocks,
+            subroutines,
+            computed_jumps,
+            self_modifying,
+        }
+    }
+
+    pub fn blocks(&self) -> &BTreeMap<u16, Block> {
+        &self.blocks
+    }
+
+    // the targets of 2NNN calls
+    pub fn subroutines(&self) -> &BTreeSet<u16> {
+        &self.subroutines
+    }
+
+    // BNNN instructions, where the analysis stops as the target is only known
+    // when the rom runs
+    pub fn computed_jumps(&self) -> &[u16] {
+        &self.computed_jumps
+    }
+
+    // ANNN instructions pointing I at code, with the address they load. with
+    // FX33 or FX55 in the rom the code may be written over.
+    pub fn self_modifying(&self) -> &[(u16, u16)] {
+        &self.self_modifying
+    }
+
+    // ranges of program bytes no reached instruction covers, usually sprites
+    // and other data, as (start, end) with end exclusive
+    pub fn unreachable(&self) -> Vec<(u16, u16)> {
+        let mut covered = vec![false; 4096];
+        for block in self.blocks.values() {
+            for address in block.start..block.end {
+                covered[address as usize] = true;
+            }
+        }
+        let mut ranges: Vec<(u16, u16)> = Vec::new();
+        for address in PROGRAM_START..self.program_end {
+            if covered[address as usize] {
+                continue;
+            }
+            match ranges.last_mut() {
+                Some((_, end)) if *end == address => *end += 1,
+                _ => ranges.push((address, address + 1)),
+            }
+        }
+        ranges
+    }
+
+    // the graph for Graphviz, a node per block listing its instructions.
+    // subroutine entries are drawn bold and calls dashed.
+    pub fn dot(&self) -> String {
+        let mut dot = String::new();
+        let _ = writeln!(dot, "digraph rom {{");
+        let _ = writeln!(dot, "    node [shape=box fontname=monospace];");
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            for address in (block.start..block.end).step_by(2) {
+                let opcode = opcode_at(&self.memory, address);
+                let _ = write!(label, "{:03x}: {}\\l", address, disassemble(opcode));
+            }
+            let style = if self.subroutines.contains(&block.start) {
+                " style=bold"
+            } else {
+                ""
+            };
+            let _ = writeln!(
+                dot,
+                "    b{:03x} [label=\"{}\"{}];",
+                block.start, label, style
+            );
+            for (target, kind) in &block.edges {
+                let attributes = match kind {
+                    EdgeKind::Next | EdgeKind::Jump => "",
+                    EdgeKind::Call => " [style=dashed]",
+                    EdgeKind::Skip => " [label=skip]",
+                };
+                let _ = writeln!(
+                    dot,
+                    "    b{:03x} -> b{:03x}{};",
+                    block.start, target, attributes
+                );
+            }
+        }
+        for address in &self.computed_jumps {
+            let block = self
+                .blocks
+                .range(..=address)
+                .next_back()
+                .map_or(*address, |(&start, _)| start);
+            let _ = writeln!(
+                dot,
+                "    c{:03x} [label=\"v0 + nnn\" shape=ellipse style=dotted];",
+                address
+            );
+            let _ = writeln!(
+                dot,
+                "    b{:03x} -> c{:03x} [style=dotted];",
+                block, address
+            );
+        }
+        let _ = writeln!(dot, "}}");
+        dot
+    }
+}
+
+// where the instruction at address can go next and whether it ends a block
+fn edges(address: u16, opcode: u16) -> (Vec<(u16, EdgeKind)>, bool) {
+    let next = address + 2;
+    let nnn = opcode & 0xfff;
+    match disasm::pattern(opcode) {
+        "00ee" => (Vec::new(), true),
+        // this interpreter jumps to machine code routines as if they were chip8
+        "0nnn" | "1nnn" => (vec![(nnn, EdgeKind::Jump)], true),
+        "2nnn" => (vec![(nnn, EdgeKind::Call), (next, EdgeKind::Next)], true),
+        "3xnn" | "4xnn" | "5xy0" | "9xy0" | "ex9e" | "exa1" => (
+            vec![(next, EdgeKind::Next), (next + 2, EdgeKind::Skip)],
+            true,
+        ),
+        "bnnn" => (Vec::new(), true),
+        _ => (vec![(next, EdgeKind::Next)], false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flow() {
+        let program = [
+            0x22, 0x0c, // 200: call 20c
+            0x30, 0x01, // 202: se v0, 1
+            0x12, 0x02, // 204: jp 202
+            0xa2, 0x0e, // 206: ld i, 20e
+            0xf0, 0x33, // 208: ld b, v0
+            0xb2, 0x00, // 20a: jp v0, 200
+            0x60, 0x01, // 20c: ld v0, 1
+            0x00, 0xee, // 20e: ret
+            0xff, 0x81, // 210: sprite data
+        ];
+        let flow = Flow::analyze(&program);
+
+        let starts: Vec<u16> = flow.blocks().keys().copied().collect();
+        assert_eq!(starts, [0x200, 0x202, 0x204, 0x206, 0x20c]);
+        assert_eq!(
+            flow.blocks()[&0x200].edges,
+            [(0x20c, EdgeKind::Call), (0x202, EdgeKind::Next)]
+        );
+        assert_eq!(
+            flow.blocks()[&0x202].edges,
+            [(0x204, EdgeKind::Next), (0x206, EdgeKind::Skip)]
+        );
+        assert_eq!(flow.blocks()[&0x206].end, 0x20c);
+        assert_eq!(flow.blocks()[&0x20c].end, 0x210);
+        assert_eq!(flow.subroutines().iter().collect::<Vec<_>>(), [&0x20c]);
+        assert_eq!(flow.computed_jumps(), [0x20a]);
+        assert_eq!(flow.self_modifying(), [(0x206, 0x20e)]);
+        assert_eq!(flow.unreachable(), [(0x210, 0x212)]);
+
+        let dot = flow.dot();
+        assert!(dot.starts_with("digraph rom {"));
+        assert!(dot.contains("b200 -> b20c [style=dashed];"));
+        assert!(dot.contains("b202 -> b206 [label=skip];"));
+        assert!(dot.contains("b206 -> c20a [style=dotted];"));
+        assert!(dot.contains("b20c [label=\"20c: ld v0, 0x01\\l20e: ret\\l\" style=bold];"));
+    }
+}
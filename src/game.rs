@@ -1,55 +1,201 @@
+use crate::options::{Options, Scaling};
+use crate::overlay::{self, Panel};
+use chip8::palette::Palette;
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
 use sdl2::render::WindowCanvas;
+use sdl2::video::FullscreenType;
 use sdl2::EventPump;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 pub struct Game {
     canvas: WindowCanvas,
     pub event_pump: EventPump,
+    // palettes that can be cycled through at runtime and the one in use
+    palettes: Vec<Palette>,
+    palette: usize,
+    scaling: Scaling,
+    grid: bool,
+    // shown in the window title along with the fps
+    title: String,
+    frames: u32,
+    fps_since: Instant,
 }
 
 impl Game {
-    pub fn initialize() -> Game {
+    pub fn initialize(options: &Options, palette: &Palette, scale: u32, title: &str) -> Game {
+        let mut palettes = Palette::builtin();
+        let palette = select_palette(&mut palettes, palette);
+
         // initializing graphics
         let sdl_context = sdl2::init().unwrap();
         let video_subsystem = sdl_context.video().unwrap();
         println!("initialized sdl");
         let window = video_subsystem
-            .window("rust-sdl2 demo", 640, 320)
+            .window(&format!("chip8 - {}", title), 64 * scale, 32 * scale)
             .position_centered()
+            .resizable()
             .build()
             .unwrap();
         let mut canvas = window.into_canvas().build().unwrap();
-        canvas.set_draw_color(Color::RGB(0, 255, 255));
+        canvas.set_draw_color(rgb(palettes[palette].background));
         canvas.clear();
         canvas.present();
         let event_pump = sdl_context.event_pump().unwrap();
         Game {
-            canvas: canvas,
-            event_pump: event_pump,
+            canvas,
+            event_pump,
+            palettes,
+            palette,
+            scaling: options.scaling,
+            grid: options.grid,
+            title: title.to_string(),
+            frames: 0,
+            fps_since: Instant::now(),
         }
     }
 
-    pub fn get_events(&mut self) -> sdl2::event::EventPollIterator {
+    pub fn get_events(&mut self) -> sdl2::event::EventPollIterator<'_> {
         self.event_pump.poll_iter()
     }
 
+    pub fn cycle_palette(&mut self, forward: bool) {
+        let count = self.palettes.len();
+        self.palette = match forward {
+            true => (self.palette + 1) % count,
+            false => (self.palette + count - 1) % count,
+        };
+        println!("palette: {}", self.palettes[self.palette].name);
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palettes[self.palette]
+    }
+
+    // resizes the window to scale window pixels per chip8 pixel
+    #[allow(unused_must_use)]
+    pub fn set_scale(&mut self, scale: u32) {
+        self.canvas.window_mut().set_size(64 * scale, 32 * scale);
+    }
+
+    // switches the window over to a newly loaded rom
     #[allow(unused_must_use)]
-    pub fn draw(&mut self, gfx: &[bool; 64 * 32]) {
-        self.canvas.set_draw_color(Color::RGB(0, 255, 255));
+    pub fn set_rom(&mut self, title: &str, palette: &Palette, scale: u32) {
+        self.title = title.to_string();
+        self.canvas
+            .window_mut()
+            .set_title(&format!("chip8 - {}", title));
+        self.palette = select_palette(&mut self.palettes, palette);
+        self.set_scale(scale);
+    }
+
+    #[allow(unused_must_use)]
+    pub fn toggle_fullscreen(&mut self) {
+        let window = self.canvas.window_mut();
+        match window.fullscreen_state() {
+            FullscreenType::Off => window.set_fullscreen(FullscreenType::Desktop),
+            _ => window.set_fullscreen(FullscreenType::Off),
+        };
+    }
+
+    // updates the title with the frames drawn during the last second
+    #[allow(unused_must_use)]
+    fn count_frame(&mut self) {
+        self.frames += 1;
+        let elapsed = self.fps_since.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            let fps = self.frames as f32 / elapsed.as_secs_f32();
+            let title = format!("chip8 - {} - {:.0} fps", self.title, fps);
+            self.canvas.window_mut().set_title(&title);
+            self.frames = 0;
+            self.fps_since = Instant::now();
+        }
+    }
+
+    // intensity is the filtered framebuffer of width x height pixels,
+    // 0.0 is background and 1.0 foreground. the hud lines and the panel are
+    // drawn on top.
+    #[allow(unused_must_use)]
+    pub fn draw(
+        &mut self,
+        intensity: &[f32],
+        width: usize,
+        height: usize,
+        panel: Option<&Panel>,
+        hud: &[String],
+    ) {
+        let palette = &self.palettes[self.palette];
+        let (window_width, window_height) = self.canvas.output_size().unwrap_or((1, 1));
+
+        // the screen is centered in the window with black bars around it
+        let mut scale =
+            (window_width as f32 / width as f32).min(window_height as f32 / height as f32);
+        if self.scaling == Scaling::Integer {
+            scale = scale.floor().max(1.0);
+        }
+        let left = (window_width as f32 - width as f32 * scale) / 2.0;
+        let top = (window_height as f32 - height as f32 * scale) / 2.0;
+        // pixel edges are rounded from the unscaled position so fit scaling leaves no seams
+        let edge = |offset: f32, i: usize| (offset + i as f32 * scale).round() as i32;
+
+        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
         self.canvas.clear();
-        // The rest of the game loop goes here...
-        self.canvas.set_draw_color(Color::RGB(255, 255, 255));
-        for x in 0..=63 {
-            for y in 0..=31 {
-                if gfx[64 * y + x] {
+        self.canvas.set_draw_color(rgb(palette.background));
+        self.canvas.fill_rect(Rect::new(
+            edge(left, 0),
+            edge(top, 0),
+            (edge(left, width) - edge(left, 0)) as u32,
+            (edge(top, height) - edge(top, 0)) as u32,
+        ));
+        // the grid gap is only drawn when there is room left for the pixel itself
+        let gap = if self.grid && scale > 2.0 { 1 } else { 0 };
+        for y in 0..height {
+            for x in 0..width {
+                let amount = intensity[width * y + x];
+                if amount > 0.0 {
                     self.canvas
-                        .fill_rect(Rect::new(x as i32 * 10, y as i32 * 10, 10, 10));
+                        .set_draw_color(mix(palette.background, palette.foreground, amount));
+                    self.canvas.fill_rect(Rect::new(
+                        edge(left, x),
+                        edge(top, y),
+                        (edge(left, x + 1) - edge(left, x) - gap).max(1) as u32,
+                        (edge(top, y + 1) - edge(top, y) - gap).max(1) as u32,
+                    ));
                 }
             }
         }
+        if !hud.is_empty() {
+            overlay::draw_hud(&mut self.canvas, hud, palette);
+        }
+        if let Some(panel) = panel {
+            overlay::draw(&mut self.canvas, panel, palette);
+        }
         self.canvas.present();
+        self.count_frame();
         ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
     }
 }
+
+// index of the palette in palettes, a user defined palette is added so it is
+// cycled through along with the builtin ones
+fn select_palette(palettes: &mut Vec<Palette>, palette: &Palette) -> usize {
+    match palettes.iter().position(|p| p == palette) {
+        Some(i) => i,
+        None => {
+            palettes.push(palette.clone());
+            palettes.len() - 1
+        }
+    }
+}
+
+pub fn rgb(colour: [u8; 3]) -> Color {
+    Color::RGB(colour[0], colour[1], colour[2])
+}
+
+// linear blend from background (amount 0.0) to foreground (amount 1.0)
+fn mix(background: [u8; 3], foreground: [u8; 3], amount: f32) -> Color {
+    let channel = |i: usize| {
+        (background[i] as f32 + (foreground[i] as f32 - background[i] as f32) * amount) as u8
+    };
+    Color::RGB(channel(0), channel(1), channel(2))
+}
@@ -0,0 +1,565 @@
+// a gdb remote serial protocol stub, so gdb and other debugger frontends can
+// attach to a running rom over tcp. the frontend polls it once per frame and
+// only runs the chip while the attached debugger has it continuing.
+//
+// the registers are v0-vf, i, pc, sp, dt and st, described to the debugger
+// in a target.xml. i and pc are two bytes, little endian like gdb expects
+// from a target without a description of its own.
+use crate::breakpoints::Breakpoints;
+use crate::chip8::Chip8;
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+// stop signals reported to the debugger
+pub const SIGINT: u8 = 2;
+pub const SIGTRAP: u8 = 5;
+pub const SIGSEGV: u8 = 11;
+
+const REGISTERS: [&str; 21] = [
+    "v0", "v1", "v2", "v3", "v4", "v5", "v6", "v7", "v8", "v9", "va", "vb", "vc", "vd", "ve", "vf",
+    "i", "pc", "sp", "dt", "st",
+];
+const I: usize = 16;
+const PC: usize = 17;
+
+pub struct GdbServer {
+    listener: TcpListener,
+    client: Option<Client>,
+}
+
+struct Client {
+    stream: TcpStream,
+    // bytes received that don't make up a whole packet yet
+    input: Vec<u8>,
+    no_ack: bool,
+    // continuing until a breakpoint, an error or an interrupt
+    running: bool,
+    detached: bool,
+}
+
+enum Incoming {
+    Packet(String),
+    // ctrl-c in the debugger
+    Interrupt,
+    // a packet with a bad checksum, the debugger sends it again
+    Corrupt,
+}
+
+impl GdbServer {
+    // listens without blocking, e.g. on "127.0.0.1:1234"
+    pub fn bind<A: ToSocketAddrs>(address: A) -> io::Result<GdbServer> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(GdbServer {
+            listener,
+            client: None,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn connected(&self) -> bool {
+        self.client.is_some()
+    }
+
+    // a debugger is attached and has the chip stopped
+    pub fn halted(&self) -> bool {
+        self.client.as_ref().is_some_and(|client| !client.running)
+    }
+
+    // takes a new connection and answers the packets that came in. a new
+    // debugger finds the chip stopped. on an error the connection is dropped
+    // and the rom runs on by itself.
+    pub fn poll(&mut self, chip: &mut Chip8, breakpoints: &mut Breakpoints) -> io::Result<()> {
+        if self.client.is_none() {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(true)?;
+                    stream.set_nodelay(true)?;
+                    self.client = Some(Client {
+                        stream,
+                        input: Vec::new(),
+                        no_ack: false,
+                        running: false,
+                        detached: false,
+                    });
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+        let client = self.client.as_mut().expect("connected above");
+        let result = client.poll(chip, breakpoints);
+        if result.is_err() || client.detached {
+            self.client = None;
+        }
+        result
+    }
+
+    // tells a continuing debugger that the chip stopped, SIGTRAP for a
+    // breakpoint and SIGSEGV for an error
+    pub fn stop(&mut self, signal: u8) -> io::Result<()> {
+        match &mut self.client {
+            Some(client) if client.running => {
+                client.running = false;
+                let result = client.send(&format!("S{:02x}", signal));
+                if result.is_err() {
+                    self.client = None;
+                }
+                result
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Client {
+    fn poll(&mut self, chip: &mut Chip8, breakpoints: &mut Breakpoints) -> io::Result<()> {
+        let mut buffer = [0; 4096];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "the debugger closed the connection",
+                    ))
+                }
+                Ok(len) => self.input.extend_from_slice(&buffer[..len]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+        while let Some(incoming) = self.take() {
+            match incoming {
+                Incoming::Corrupt => self.stream_write(b"-")?,
+                Incoming::Interrupt if self.running => {
+                    self.running = false;
+                    self.send(&format!("S{:02x}", SIGINT))?;
+                }
+                Incoming::Interrupt => {}
+                Incoming::Packet(packet) => {
+                    if !self.no_ack {
+                        self.stream_write(b"+")?;
+                    }
+                    if let Some(reply) = self.handle(&packet, chip, breakpoints) {
+                        self.send(&reply)?;
+                    }
+                    if packet == "QStartNoAckMode" {
+                        self.no_ack = true;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // the next whole packet or interrupt in the input, acks from the
+    // debugger are skipped
+    fn take(&mut self) -> Option<Incoming> {
+        loop {
+            match *self.input.first()? {
+                0x03 => {
+                    self.input.remove(0);
+                    return Some(Incoming::Interrupt);
+                }
+                b'$' => break,
+                _ => {
+                    self.input.remove(0);
+                }
+            }
+        }
+        let end = self.input.iter().position(|&byte| byte == b'#')?;
+        if self.input.len() < end + 3 {
+            return None;
+        }
+        let packet: Vec<u8> = self.input.drain(..end + 3).collect();
+        let data = &packet[1..end];
+        let checksum = std::str::from_utf8(&packet[end + 1..])
+            .ok()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match checksum == Some(checksum_of(data)) || self.no_ack {
+            true => Some(Incoming::Packet(String::from_utf8_lossy(data).into_owned())),
+            false => Some(Incoming::Corrupt),
+        }
+    }
+
+    // the reply to a packet, None for a continue which is answered when the
+    // chip stops
+    fn handle(
+        &mut self,
+        packet: &str,
+        chip: &mut Chip8,
+        breakpoints: &mut Breakpoints,
+    ) -> Option<String> {
+        if let Some(address) = packet.strip_prefix('c') {
+            if let Ok(address) = u16::from_str_radix(address, 16) {
+                chip.set_pc(address);
+            }
+            self.running = true;
+            return None;
+        }
+        let reply = self.reply(packet, chip, breakpoints);
+        Some(reply.unwrap_or_else(|| "E01".to_string()))
+    }
+
+    // None for a packet that can't be parsed
+    fn reply(
+        &mut self,
+        packet: &str,
+        chip: &mut Chip8,
+        breakpoints: &mut Breakpoints,
+    ) -> Option<String> {
+        let split = packet.len().min(1);
+        if !packet.is_char_boundary(split) {
+            return None;
+        }
+        let (command, args) = packet.split_at(split);
+        let reply = match command {
+            "?" => {
+                self.running = false;
+                format!("S{:02x}", SIGTRAP)
+            }
+            "g" => (0..REGISTERS.len())
+                .map(|n| encode_register(chip, n))
+                .collect(),
+            "G" => {
+                let bytes = decode_hex(args)?;
+                let mut offset = 0;
+                for n in 0..REGISTERS.len() {
+                    let size = register_size(n);
+                    if let Some(value) = bytes.get(offset..offset + size) {
+                        // sp and the timers can only be read
+                        let _ = write_register(chip, n, value);
+                    }
+                    offset += size;
+                }
+                "OK".to_string()
+            }
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(n) if n < REGISTERS.len() => encode_register(chip, n),
+                _ => "E01".to_string(),
+            },
+            "P" => {
+                let (n, value) = args.split_once('=')?;
+                let n = usize::from_str_radix(n, 16).ok()?;
+                let value = decode_hex(value)?;
+                match n < REGISTERS.len() && write_register(chip, n, &value) {
+                    true => "OK".to_string(),
+                    false => "E01".to_string(),
+                }
+            }
+            "m" => match memory_range(args) {
+                Some((address, len)) => chip.memory()[address..address + len]
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect(),
+                None => "E01".to_string(),
+            },
+            "M" => {
+                let (range, data) = args.split_once(':')?;
+                let bytes = decode_hex(data)?;
+                match memory_range(range) {
+                    Some((address, len)) if len == bytes.len() => {
+                        for (offset, byte) in bytes.into_iter().enumerate() {
+                            chip.poke((address + offset) as u16, byte);
+                        }
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "s" => {
+                if let Ok(address) = u16::from_str_radix(args, 16) {
+                    chip.set_pc(address);
+                }
+                let signal = match chip.step() {
+                    Ok(()) => SIGTRAP,
+                    Err(_) => SIGSEGV,
+                };
+                format!("S{:02x}", signal)
+            }
+            // software and hardware breakpoints are the same here
+            "Z" | "z" => {
+                let mut fields = args.split(',');
+                let kind = fields.next();
+                let address = fields.next().and_then(|a| u16::from_str_radix(a, 16).ok());
+                match (kind, address) {
+                    (Some("0") | Some("1"), Some(address)) => {
+                        match command {
+                            "Z" => breakpoints.insert(address),
+                            _ => breakpoints.remove(address),
+                        }
+                        "OK".to_string()
+                    }
+                    _ => String::new(),
+                }
+            }
+            "H" => "OK".to_string(),
+            "D" | "k" => {
+                self.running = true;
+                self.detached = true;
+                "OK".to_string()
+            }
+            _ => query(packet),
+        };
+        Some(reply)
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.stream_write(packet.as_bytes())
+    }
+
+    // the stream doesn't block, so a full send buffer is waited out here
+    fn stream_write(&mut self, mut bytes: &[u8]) -> io::Result<()> {
+        while !bytes.is_empty() {
+            match self.stream.write(bytes) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(len) => bytes = &bytes[len..],
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    std::thread::sleep(Duration::from_millis(1))
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+// the general queries, an empty reply tells the debugger one isn't supported
+fn query(packet: &str) -> String {
+    if packet.starts_with("qSupported") {
+        return "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+".to_string();
+    }
+    if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+        let xml = target_xml();
+        let (offset, len) = range.split_once(',').unwrap_or(("0", "0"));
+        let offset = usize::from_str_radix(offset, 16)
+            .unwrap_or(0)
+            .min(xml.len());
+        let len = usize::from_str_radix(len, 16).unwrap_or(0);
+        let chunk = &xml[offset..(offset + len).min(xml.len())];
+        let more = if offset + chunk.len() < xml.len() {
+            'm'
+        } else {
+            'l'
+        };
+        return format!("{}{}", more, chunk);
+    }
+    match packet {
+        "QStartNoAckMode" => "OK".to_string(),
+        "qAttached" => "1".to_string(),
+        "qfThreadInfo" => "m1".to_string(),
+        "qsThreadInfo" => "l".to_string(),
+        "qC" => "QC1".to_string(),
+        _ => String::new(),
+    }
+}
+
+fn target_xml() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n\
+         <target version=\"1.0\">\n  <feature name=\"org.chip8.core\">\n",
+    );
+    for (n, name) in REGISTERS.iter().enumerate() {
+        let kind = match n {
+            PC => "code_ptr",
+            I => "data_ptr",
+            _ => "uint8",
+        };
+        let _ = writeln!(
+            xml,
+            "    <reg name=\"{}\" bitsize=\"{}\" type=\"{}\"/>",
+            name,
+            register_size(n) * 8,
+            kind
+        );
+    }
+    xml.push_str("  </feature>\n</target>\n");
+    xml
+}
+
+fn register_size(n: usize) -> usize {
+    match n {
+        I | PC => 2,
+        _ => 1,
+    }
+}
+
+fn encode_register(chip: &Chip8, n: usize) -> String {
+    match n {
+        0..=15 => format!("{:02x}", chip.registers()[n]),
+        I => hex_le(chip.index()),
+        PC => hex_le(chip.pc()),
+        18 => format!("{:02x}", chip.sp()),
+        19 => format!("{:02x}", chip.get_delay_timer()),
+        _ => format!("{:02x}", chip.get_sound_timer()),
+    }
+}
+
+// false for sp and the timers, which can't be written
+fn write_register(chip: &mut Chip8, n: usize, value: &[u8]) -> bool {
+    let word = || value[0] as u16 | (*value.get(1).unwrap_or(&0) as u16) << 8;
+    match n {
+        0..=15 => chip.set_register(n, value[0]),
+        I => chip.set_index(word()),
+        PC => chip.set_pc(word()),
+        _ => return false,
+    }
+    true
+}
+
+fn hex_le(value: u16) -> String {
+    format!("{:02x}{:02x}", value & 0xff, value >> 8)
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || hex.is_empty() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// "ADDRESS,LENGTH" in hex, cut short at the end of memory
+fn memory_range(range: &str) -> Option<(usize, usize)> {
+    let (address, len) = range.split_once(',')?;
+    let address = usize::from_str_radix(address, 16).ok()?;
+    let len = usize::from_str_radix(len, 16).ok()?;
+    match address < 4096 {
+        true => Some((address, len.min(4096 - address))),
+        false => None,
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Quirks;
+
+    // sends a packet as gdb would and polls the server until the reply comes
+    fn exchange(
+        server: &mut GdbServer,
+        gdb: &mut TcpStream,
+        chip: &mut Chip8,
+        breakpoints: &mut Breakpoints,
+        packet: &str,
+    ) -> Option<String> {
+        let framed = format!("${}#{:02x}", packet, checksum_of(packet.as_bytes()));
+        gdb.write_all(framed.as_bytes()).unwrap();
+        receive(server, gdb, chip, breakpoints)
+    }
+
+    fn receive(
+        server: &mut GdbServer,
+        gdb: &mut TcpStream,
+        chip: &mut Chip8,
+        breakpoints: &mut Breakpoints,
+    ) -> Option<String> {
+        let mut received = Vec::new();
+        for _ in 0..200 {
+            server.poll(chip, breakpoints).unwrap();
+            let mut buffer = [0; 4096];
+            if let Ok(len) = gdb.read(&mut buffer) {
+                received.extend_from_slice(&buffer[..len]);
+            }
+            let text = String::from_utf8_lossy(&received).into_owned();
+            if let (Some(start), Some(end)) = (text.find('$'), text.find('#')) {
+                if text.len() >= end + 3 {
+                    return Some(text[start + 1..end].to_string());
+                }
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn test_gdb() {
+        let mut server = GdbServer::bind("127.0.0.1:0").unwrap();
+        let mut gdb = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+        gdb.set_read_timeout(Some(Duration::from_millis(5)))
+            .unwrap();
+        // 200: ld v3, 0x2a, 202: jp 202
+        let mut chip = Chip8::load(vec![0x63, 0x2a, 0x12, 0x02]).unwrap();
+        let mut breakpoints = Breakpoints::default();
+        let mut send = |server: &mut GdbServer, chip: &mut Chip8, packet: &str| {
+            exchange(server, &mut gdb, chip, &mut breakpoints, packet)
+        };
+
+        assert_eq!(send(&mut server, &mut chip, "?").as_deref(), Some("S05"));
+        assert!(server.halted());
+        assert_eq!(
+            send(&mut server, &mut chip, "m200,4").as_deref(),
+            Some("632a1202")
+        );
+        assert_eq!(send(&mut server, &mut chip, "s").as_deref(), Some("S05"));
+        assert_eq!(chip.pc(), 0x202);
+        let registers = send(&mut server, &mut chip, "g").unwrap();
+        assert_eq!(&registers[6..8], "2a");
+        assert_eq!(&registers[32..40], "00000202");
+        assert_eq!(send(&mut server, &mut chip, "P3=07").as_deref(), Some("OK"));
+        assert_eq!(chip.registers()[3], 7);
+        assert_eq!(
+            send(&mut server, &mut chip, "P12=00").as_deref(),
+            Some("E01")
+        );
+        assert_eq!(
+            send(&mut server, &mut chip, "M300,2:abcd").as_deref(),
+            Some("OK")
+        );
+        assert_eq!(chip.memory()[0x301], 0xcd);
+        assert!(send(
+            &mut server,
+            &mut chip,
+            "qXfer:features:read:target.xml:0,fff"
+        )
+        .unwrap()
+        .starts_with("l<?xml"));
+        assert_eq!(
+            send(&mut server, &mut chip, "Z0,202,2").as_deref(),
+            Some("OK")
+        );
+        // continue has no reply until the chip stops
+        gdb.write_all(b"$c#63").unwrap();
+        while server.halted() {
+            server.poll(&mut chip, &mut breakpoints).unwrap();
+        }
+
+        // the frontend runs the chip into the breakpoint
+        assert!(breakpoints.hit(chip.pc()));
+        server.stop(SIGTRAP).unwrap();
+        assert!(server.halted());
+        assert_eq!(
+            receive(&mut server, &mut gdb, &mut chip, &mut breakpoints).as_deref(),
+            Some("S05")
+        );
+
+        // stepping goes on after a draw that waits for vblank,
+        // 204: drw v0, v0, 1, 206: add v3, 1
+        chip.set_quirks(Quirks {
+            vblank: true,
+            ..Quirks::default()
+        });
+        let mut send = |server: &mut GdbServer, chip: &mut Chip8, packet: &str| {
+            exchange(server, &mut gdb, chip, &mut breakpoints, packet)
+        };
+        assert_eq!(
+            send(&mut server, &mut chip, "M204,4:d0017301").as_deref(),
+            Some("OK")
+        );
+        assert_eq!(send(&mut server, &mut chip, "s204").as_deref(), Some("S05"));
+        assert!(chip.waiting_for_vblank());
+        assert_eq!(send(&mut server, &mut chip, "s").as_deref(), Some("S05"));
+        assert_eq!((chip.pc(), chip.registers()[3]), (0x208, 8));
+    }
+}
@@ -0,0 +1,75 @@
+use sdl2::keyboard::Keycode;
+use std::collections::BTreeMap;
+
+// the usual layout, the 4x4 block from 1 to V mapped onto the COSMAC VIP keypad
+//   1 2 3 4      1 2 3 C
+//   Q W E R  ->  4 5 6 D
+//   A S D F      7 8 9 E
+//   Z X C V      A 0 B F
+const DEFAULT: [Keycode; 16] = [
+    Keycode::X,
+    Keycode::Num1,
+    Keycode::Num2,
+    Keycode::Num3,
+    Keycode::Q,
+    Keycode::W,
+    Keycode::E,
+    Keycode::A,
+    Keycode::S,
+    Keycode::D,
+    Keycode::Z,
+    Keycode::C,
+    Keycode::Num4,
+    Keycode::R,
+    Keycode::F,
+    Keycode::V,
+];
+
+// the keyboard key bound to each of the 16 chip8 keys
+pub struct Keymap {
+    keys: [Keycode; 16],
+}
+
+impl Keymap {
+    // the default layout with the bindings of a rom's settings on top, see RomSettings::keymap
+    pub fn new(bindings: &BTreeMap<String, String>) -> Keymap {
+        let mut keymap = Keymap { keys: DEFAULT };
+        for (chip_key, name) in bindings {
+            match (u8::from_str_radix(chip_key, 16), Keycode::from_name(name)) {
+                (Ok(chip_key), Some(keycode)) if chip_key < 16 => {
+                    keymap.bind(chip_key as usize, keycode)
+                }
+                _ => println!("ignoring key binding {} = {}", chip_key, name),
+            }
+        }
+        keymap
+    }
+
+    pub fn chip_key(&self, keycode: Keycode) -> Option<usize> {
+        self.keys.iter().position(|&key| key == keycode)
+    }
+
+    pub fn key(&self, chip_key: usize) -> Keycode {
+        self.keys[chip_key]
+    }
+
+    // a keyboard key drives a single chip8 key, so it is swapped with the
+    // chip8 key it was bound to before
+    pub fn bind(&mut self, chip_key: usize, keycode: Keycode) {
+        if let Some(previous) = self.chip_key(keycode) {
+            self.keys[previous] = self.keys[chip_key];
+        }
+        self.keys[chip_key] = keycode;
+    }
+
+    // the bindings that differ from the default layout, as stored in RomSettings::keymap
+    pub fn bindings(&self) -> BTreeMap<String, String> {
+        self.keys
+            .iter()
+            .zip(DEFAULT.iter())
+            .enumerate()
+            .filter(|(_, (key, default))| key != default)
+            .map(|(chip_key, (key, _))| (format!("{:X}", chip_key), key.name()))
+            .collect()
+    }
+}
@@ -0,0 +1,19 @@
+pub mod breakpoints;
+pub mod capture;
+pub mod cheat;
+pub mod chip8;
+pub mod coverage;
+pub mod dap;
+pub mod database;
+pub mod disasm;
+pub mod filter;
+pub mod flow;
+pub mod gdb;
+pub mod palette;
+pub mod profile;
+pub mod selfmod;
+pub mod settings;
+pub mod symbols;
+pub mod watch;
+
+pub use crate::chip8::Chip8;
@@ -1,46 +1,1367 @@
-mod chip8;
+use chip8::breakpoints::Breakpoints;
+use chip8::capture::{self, Recorder};
+use chip8::cheat::{Cheat, Search};
+use chip8::chip8::Chip8Error;
+use chip8::coverage::{Coverage, LineMap};
+use chip8::dap::{DapServer, Launch, Target};
+use chip8::database::{self, Database};
+use chip8::disasm::{disassemble_with, opcode_at};
+use chip8::filter::{Filter, FilterMode};
+use chip8::gdb::{self, GdbServer};
+use chip8::palette::Palette;
+use chip8::profile::Profile;
+use chip8::selfmod::SelfModifying;
+use chip8::settings::{self, RomSettings, RplStore, SettingsFile};
+use chip8::symbols::Symbols;
+use chip8::watch::Watch;
 use chip8::Chip8;
 
+mod browser;
+use browser::Browser;
+
+mod cheat_menu;
+use cheat_menu::CheatMenu;
+
+mod debugger;
+use debugger::{DebugAction, Debugger};
+
+mod font;
+
 mod game;
 use game::*;
+
+mod keymap;
+use keymap::Keymap;
+
+mod menu;
+use menu::{PauseAction, PauseMenu, SettingsMenu};
+
+mod options;
+use options::Options;
+
+mod overlay;
+use overlay::Panel;
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use std::io::Read;
+use sdl2::keyboard::{Keycode, Mod};
 use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 pub fn main() {
-    let program: Vec<u8> = load_chip8_program();
-    let mut game = Game::initialize();
-    let mut chip = Chip8::load(program);
+    let options = Options::from_args().unwrap_or_else(|err| {
+        println!("{}", err);
+        std::process::exit(1);
+    });
+
+    if options.headless {
+        let (rom_name, program) = load_chip8_program(options.rom.as_deref());
+        let mut chip = Chip8::load(program.clone()).expect("unable to load program");
+        let mut settings = configure(&options, &rom_name, &program, &mut chip);
+        run_headless(&options, &mut settings, &mut chip, program.len());
+    } else {
+        run_windowed(&options);
+    }
+}
+
+// how the rom is run: the rom database, then the saved settings of the rom,
+// then the command line. quirks are kept in the chip itself.
+struct Settings {
+    rom_name: String,
+    hash: String,
+    title: String,
+    palette: Palette,
+    filter: FilterMode,
+    tickrate: u32,
+    scale: u32,
+    save_slot: u32,
+    keymap: Keymap,
+    watches: Vec<Watch>,
+    // frozen every frame
+    cheats: Vec<Cheat>,
+    // labels from --symbols, shown in place of addresses
+    symbols: Symbols,
+    breakpoints: Breakpoints,
+    // where changes made in the window are saved, None if it could not be read
+    file: Option<SettingsFile>,
+    // where the RPL user flags are kept and the flags last saved there
+    rpl: Option<RplStore>,
+    rpl_flags: [u8; 16],
+    // what the rom ran with when it was configured or last remembered,
+    // remember only writes the settings that changed since
+    remembered: RomSettings,
+}
+
+fn configure(options: &Options, rom_name: &str, program: &[u8], chip: &mut Chip8) -> Settings {
+    let mut database = Database::bundled();
+    if let Some(path) = &options.database {
+        match std::fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|json| database.add_programs(&json))
+        {
+            Ok(()) => println!("loaded rom database {}", path),
+            Err(err) => println!("unable to load rom database {}: {}", path, err),
+        }
+    }
+
+    let mut title = rom_name.to_string();
+    let mut palette = None;
+    let mut tickrate = None;
+    if let Some(info) = database.lookup(program) {
+        println!(
+            "found {} in the rom database (platform {})",
+            info.title,
+            info.platform.as_deref().unwrap_or("unknown")
+        );
+        if !info.keys.is_empty() {
+            let keys: Vec<String> = info
+                .keys
+                .iter()
+                .map(|(action, key)| format!("{}: {:x}", action, key))
+                .collect();
+            println!("keys: {}", keys.join(", "));
+        }
+        title = info.title;
+        palette = info.palette;
+        tickrate = info.tickrate;
+        if let Some(quirks) = info.quirks {
+            chip.set_quirks(quirks);
+        }
+    }
+
+    let hash = database::rom_hash(program);
+    let file = load_settings_file(options);
+    let saved = file
+        .as_ref()
+        .map(|file| file.get(&hash))
+        .unwrap_or_default();
+    if saved != RomSettings::default() {
+        println!("using the saved settings of {}", rom_name);
+    }
+    if let Some(spec) = &saved.palette {
+        match Palette::parse(spec) {
+            Ok(saved) => palette = Some(saved),
+            Err(err) => println!("{}", err),
+        }
+    }
+    let mut filter = None;
+    if let Some(spec) = &saved.filter {
+        match FilterMode::parse(spec) {
+            Ok(saved) => filter = Some(saved),
+            Err(err) => println!("{}", err),
+        }
+    }
+    tickrate = saved.tickrate.or(tickrate);
+    if let Some(quirks) = saved.quirks {
+        chip.set_quirks(quirks);
+    }
+
+    let rpl = RplStore::default_dir().map(|dir| RplStore::new(&dir));
+    let mut rpl_flags = [0; 16];
+    if let Some(store) = &rpl {
+        match store.load(&hash) {
+            Ok(flags) => rpl_flags = flags,
+            Err(err) => println!("unable to load the rpl flags of {}: {}", rom_name, err),
+        }
+    }
+    chip.set_rpl_flags(rpl_flags);
+
+    if let Some(platform) = &options.platform {
+        match database.platform(platform) {
+            Some((quirks, platform_tickrate)) => {
+                chip.set_quirks(quirks);
+                tickrate = platform_tickrate.or(tickrate);
+            }
+            None => println!(
+                "unknown platform {}, expected one of {}",
+                platform,
+                database.platform_ids().join(", ")
+            ),
+        }
+    }
+
+    chip.set_engine(options.engine);
+
+    let symbols = load_symbols(options.symbols.as_deref());
+    let mut breakpoints = Breakpoints::default();
+    for name in &options.breakpoints {
+        match symbols.resolve(name) {
+            Ok(address) => breakpoints.toggle(address),
+            Err(err) => println!("unable to break at {}: {}", name, err),
+        }
+    }
+
+    let mut settings = Settings {
+        rom_name: rom_name.to_string(),
+        hash,
+        title,
+        palette: options.palette.clone().or(palette).unwrap_or_default(),
+        filter: options.filter.or(filter).unwrap_or_default(),
+        tickrate: options.tickrate.or(tickrate).unwrap_or(1),
+        scale: options.scale.or(saved.scale).unwrap_or(10),
+        save_slot: saved.save_slot.unwrap_or(1),
+        keymap: Keymap::new(&saved.keymap),
+        watches: saved
+            .watches
+            .iter()
+            .filter_map(|watch| Watch::parse(watch).map_err(|err| println!("{}", err)).ok())
+            .chain(options.watches.iter().cloned())
+            .collect(),
+        cheats: saved
+            .cheats
+            .iter()
+            .filter_map(|cheat| Cheat::parse(cheat).map_err(|err| println!("{}", err)).ok())
+            .chain(options.cheats.iter().cloned())
+            .collect(),
+
+        symbols,
+        breakpoints,
+        file,
+        rpl,
+        rpl_flags,
+        remembered: RomSettings::default(),
+    };
+    settings.remembered = rom_settings(&settings, chip);
+    settings
+}
+
+fn load_symbols(path: Option<&str>) -> Symbols {
+    let path = match path {
+        Some(path) => path,
+        None => return Symbols::default(),
+    };
+    match std::fs::read_to_string(path)
+        .map_err(|err| err.to_string())
+        .and_then(|text| Symbols::parse(&text))
+    {
+        Ok(symbols) => symbols,
+        Err(err) => {
+            println!("unable to load symbols {}: {}", path, err);
+            Symbols::default()
+        }
+    }
+}
+
+fn load_line_map(path: &str) -> Option<LineMap> {
+    match std::fs::read_to_string(path)
+        .map_err(|err| err.to_string())
+        .and_then(|text| LineMap::parse(&text))
+    {
+        Ok(lines) => Some(lines),
+        Err(err) => {
+            println!("unable to load line map {}: {}", path, err);
+            None
+        }
+    }
+}
+
+fn load_settings_file(options: &Options) -> Option<SettingsFile> {
+    let path = match &options.settings {
+        Some(path) => PathBuf::from(path),
+        None => SettingsFile::default_path()?,
+    };
+    match SettingsFile::load(&path) {
+        Ok(file) => Some(file),
+        Err(err) => {
+            println!("unable to load settings {}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+// what the rom runs with, as it would be saved
+fn rom_settings(settings: &Settings, chip: &Chip8) -> RomSettings {
+    RomSettings {
+        name: Some(settings.rom_name.clone()),
+        tickrate: Some(settings.tickrate),
+        palette: Some(settings.palette.spec()),
+        filter: Some(settings.filter.spec()),
+        scale: Some(settings.scale),
+        save_slot: Some(settings.save_slot),
+        quirks: Some(chip.quirks()),
+        keymap: settings.keymap.bindings(),
+        watches: settings
+            .watches
+            .iter()
+            .map(|watch| watch.to_string())
+            .collect(),
+        cheats: settings
+            .cheats
+            .iter()
+            .map(|cheat| cheat.to_string())
+            .collect(),
+    }
+}
+
+// saves the settings of the rom that were changed in the window. the ones
+// that come from the rom database, the command line or the defaults are left
+// out so the rom keeps following them.
+fn remember(settings: &mut Settings, chip: &Chip8) {
+    let current = rom_settings(settings, chip);
+    if current == settings.remembered {
+        return;
+    }
+    let before = std::mem::replace(&mut settings.remembered, current.clone());
+    if let Some(file) = &mut settings.file {
+        let mut rom = file.get(&settings.hash);
+        rom.name = current.name;
+        changed(&mut rom.tickrate, before.tickrate, current.tickrate);
+        changed(&mut rom.palette, before.palette, current.palette);
+        changed(&mut rom.filter, before.filter, current.filter);
+        changed(&mut rom.scale, before.scale, current.scale);
+        changed(&mut rom.save_slot, before.save_slot, current.save_slot);
+        changed(&mut rom.quirks, before.quirks, current.quirks);
+        changed(&mut rom.keymap, before.keymap, current.keymap);
+        changed(&mut rom.watches, before.watches, current.watches);
+        changed(&mut rom.cheats, before.cheats, current.cheats);
+        file.set(&settings.hash, rom);
+        match file.save() {
+            Ok(()) => println!("saved settings to {}", file.path().display()),
+            Err(err) => println!(
+                "unable to save settings to {}: {}",
+                file.path().display(),
+                err
+            ),
+        }
+    }
+}
+
+fn changed<T: PartialEq>(saved: &mut T, before: T, current: T) {
+    if current != before {
+        *saved = current;
+    }
+}
+
+// saves the RPL user flags when the rom has changed them
+fn persist_rpl_flags(settings: &mut Settings, chip: &Chip8) {
+    let flags = chip.rpl_flags();
+    if flags == settings.rpl_flags {
+        return;
+    }
+    settings.rpl_flags = flags;
+    if let Some(store) = &settings.rpl {
+        if let Err(err) = store.save(&settings.hash, &flags) {
+            println!(
+                "unable to save the rpl flags of {}: {}",
+                settings.rom_name, err
+            );
+        }
+    }
+}
+
+// runs the instructions of one 60hz frame, the cheats are applied first so
+// the rom sees the frozen values. returns true when it stopped early at a
+// breakpoint.
+fn run_frame(
+    chip: &mut Chip8,
+    settings: &mut Settings,
+    instruments: &mut Instruments,
+) -> Result<bool, Chip8Error> {
+    chip.vblank();
+    for cheat in &settings.cheats {
+        cheat.apply(chip);
+    }
+    // with nothing to check between instructions the frame runs in one go
+    if settings.breakpoints.is_empty() && !instruments.active() {
+        chip.run(settings.tickrate)?;
+        return Ok(false);
+    }
+    for _ in 0..settings.tickrate {
+        if settings.breakpoints.hit(chip.pc()) {
+            return Ok(true);
+        }
+        instruments.record(chip, &settings.symbols);
+        chip.cycle()?;
+    }
+    Ok(false)
+}
+
+// a rom loaded into the window
+struct Session {
+    path: PathBuf,
+    // when the file was last changed, for --watch
+    modified: Option<SystemTime>,
+    settings: Settings,
+    chip: Chip8,
+    recorder: Option<Recorder>,
+    instruments: Instruments,
+    // flicker filter of the rom, its history starts over with every rom
+    filter: Filter,
+    // the cheat search in progress, kept while the cheat menu is closed
+    search: Option<Search>,
+}
+
+// what is drawn over the screen, the emulator is paused while one is open
+// unless it is the debugger and that is running
+enum Screen {
+    Browser(Browser),
+    Pause(PauseMenu),
+    Settings(SettingsMenu),
+    Cheats(CheatMenu),
+    // boxed, it keeps two copies of memory
+    Debugger(Box<Debugger>),
+}
+
+// what happens to the open screen after a key press
+enum Next {
+    Stay,
+    Close,
+    Open(Screen),
+    Quit,
+}
+
+fn run_windowed(options: &Options) {
+    let palette = options.palette.clone().unwrap_or_default();
+    let mut game = Game::initialize(options, &palette, options.scale.unwrap_or(10), "no rom");
+    // --record applies to the first rom that is opened
+    let mut record = options.record.clone();
+    let mut session: Option<Session> = None;
+    let mut screen: Option<Screen> = None;
+
+    if let Some(path) = &options.rom {
+        match open_rom(options, Path::new(path), &mut game) {
+            Ok(opened) => replace_session(options, &mut session, opened, &mut record),
+            Err(err) => println!("{}", err),
+        }
+    }
+    if session.is_none() {
+        let dir = options
+            .rom
+            .as_deref()
+            .map_or_else(|| PathBuf::from("."), |path| rom_dir(Path::new(path)));
+        screen = Some(Screen::Browser(Browser::new(&dir)));
+    }
+
+    // --watch looks at the rom file a few times a second
+    let mut watched = Instant::now();
+    let mut remotes = Remotes::new(options);
 
     println!("entering loop");
     //chip.test_drawing();
     'running: loop {
-        for event in game.get_events() {
+        for event in game.get_events().collect::<Vec<_>>() {
             match event {
-                sdl2::event::Event::Quit { .. }
-                | Event::KeyDown {
+                Event::Quit { .. } => break 'running,
+                Event::DropFile { filename, .. } => {
+                    match open_rom(options, Path::new(&filename), &mut game) {
+                        Ok(opened) => {
+                            replace_session(options, &mut session, opened, &mut record);
+                            screen = None;
+                        }
+                        Err(err) => {
+                            println!("{}", err);
+                            if let Some(Screen::Browser(browser)) = screen.as_mut() {
+                                browser.set_message(err);
+                            }
+                        }
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            if let Some(open) = screen.as_mut() {
+                if let Event::KeyDown {
+                    keycode: Some(keycode),
+                    keymod,
+                    ..
+                } = event
+                {
+                    match screen_key(
+                        open,
+                        keycode,
+                        keymod,
+                        options,
+                        &mut session,
+                        &mut game,
+                        &mut record,
+                    ) {
+                        Next::Stay => {}
+                        Next::Close => screen = None,
+                        Next::Open(next) => screen = Some(next),
+                        Next::Quit => break 'running,
+                    }
+                }
+                continue;
+            }
+            let current = match session.as_mut() {
+                Some(current) => current,
+                None => continue,
+            };
+            match event {
+                Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     ..
-                } => break 'running,
+                } => screen = Some(Screen::Pause(PauseMenu::new())),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F1),
+                    ..
+                } => screen = Some(Screen::Settings(SettingsMenu::new())),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F2),
+                    ..
+                } => screen = Some(Screen::Debugger(Box::new(Debugger::new(&current.chip)))),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F3),
+                    ..
+                } => screen = Some(Screen::Cheats(CheatMenu::new())),
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    ..
+                } => {
+                    game.cycle_palette(true);
+                    current.settings.palette = game.palette().clone();
+                    remember(&mut current.settings, &current.chip);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    keymod,
+                    ..
+                } => match keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                    true => hard_reset(options, current),
+                    false => current.chip.reset(),
+                },
+                Event::KeyDown {
+                    keycode: Some(Keycode::F11),
+                    ..
+                } => game.toggle_fullscreen(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    ..
+                } => screenshot(
+                    Path::new(&capture_name(&current.settings.rom_name, "png")),
+                    &current.chip,
+                    game.palette(),
+                    current.settings.scale,
+                ),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    ..
+                } => {
+                    current.recorder = match current.recorder.take() {
+                        Some(recorder) => {
+                            stop_recording(recorder);
+                            None
+                        }
+                        None => start_recording(
+                            Path::new(&capture_name(&current.settings.rom_name, "gif")),
+                            &current.chip,
+                            game.palette(),
+                            current.settings.scale,
+                        ),
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    if let Some(key) = current.settings.keymap.chip_key(keycode) {
+                        current.chip.set_key(key, true);
+                    }
+                }
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    if let Some(key) = current.settings.keymap.chip_key(keycode) {
+                        current.chip.set_key(key, false);
+                    }
+                }
                 _ => {}
             }
         }
-        chip.cycle();
-        game.draw(&chip.gfx);
+
+        if let (true, Some(current)) = (options.watch, session.as_mut()) {
+            if watched.elapsed() >= Duration::from_millis(250) {
+                watch(options, current);
+                watched = Instant::now();
+            }
+        }
+
+        if let Some(launch) = remotes.poll(session.as_mut()) {
+            match open_rom(options, Path::new(&launch.program), &mut game) {
+                Ok(mut opened) => {
+                    if let Some(path) = &launch.symbols {
+                        opened.settings.symbols = load_symbols(Some(path));
+                    }
+                    replace_session(options, &mut session, opened, &mut record);
+                    screen = None;
+                    remotes.launched(&launch, Ok(()));
+                }
+                Err(err) => {
+                    println!("{}", err);
+                    remotes.launched(&launch, Err(err));
+                }
+            }
+        }
+
+        let running = match &screen {
+            None => true,
+            Some(Screen::Debugger(debugger)) => debugger.running(),
+            Some(_) => false,
+        } && !remotes.halted();
+        if let (true, Some(current)) = (running, session.as_mut()) {
+            // an error drops back to the browser, the rom can't go on
+            match run_frame(
+                &mut current.chip,
+                &mut current.settings,
+                &mut current.instruments,
+            ) {
+                // an attached debugger is told instead so the state can be looked at
+                Err(err) if remotes.connected() => {
+                    println!("{}", err);
+                    remotes.fail(&err);
+                }
+                // a broken call stack stops in the debugger to see how it got there
+                Err(
+                    err @ (Chip8Error::StackOverflow { .. } | Chip8Error::StackUnderflow { .. }),
+                ) => {
+                    println!("{}", err);
+                    let mut debugger = match screen.take() {
+                        Some(Screen::Debugger(debugger)) => debugger,
+                        _ => Box::new(Debugger::new(&current.chip)),
+                    };
+                    debugger.fault(&current.chip, err.to_string());
+                    screen = Some(Screen::Debugger(debugger));
+                }
+                Err(err) => {
+                    println!("{}", err);
+                    let mut browser = Browser::new(&rom_dir(&current.path));
+                    browser.set_message(err.to_string());
+                    screen = Some(Screen::Browser(browser));
+                }
+                Ok(stopped) => {
+                    if stopped && remotes.connected() {
+                        remotes.stop(&current.chip, &mut current.settings.breakpoints);
+                    } else if stopped {
+                        match screen.as_mut() {
+                            Some(Screen::Debugger(debugger)) => debugger.stop(&current.chip),
+                            _ => {
+                                screen =
+                                    Some(Screen::Debugger(Box::new(Debugger::new(&current.chip))))
+                            }
+                        }
+                    }
+                    record_frame(&mut current.recorder, &current.chip);
+                    persist_rpl_flags(&mut current.settings, &current.chip);
+                }
+            }
+        }
+
+        if let (Some(Screen::Debugger(debugger)), Some(current)) = (screen.as_mut(), &session) {
+            debugger.update(&current.chip);
+        }
+
+        let panel = screen
+            .as_ref()
+            .and_then(|open| screen_panel(open, &session));
+        match session.as_mut() {
+            Some(current) => {
+                // the watches are shown over the game while no screen is open
+                let hud: Vec<String> = match screen {
+                    None => current
+                        .settings
+                        .watches
+                        .iter()
+                        .map(|watch| watch.describe(&current.chip))
+                        .collect(),
+                    Some(_) => Vec::new(),
+                };
+                let (width, height) = current.chip.screen_size();
+                // the filter was changed in the settings menu
+                if current.filter.mode() != current.settings.filter {
+                    current.filter = Filter::new(current.settings.filter);
+                }
+                let intensity = current
+                    .filter
+                    .apply(&current.chip.gfx, current.chip.display_settled());
+                game.draw(intensity, width, height, panel.as_ref(), &hud);
+            }
+            None => game.draw(&[0.0; 64 * 32], 64, 32, panel.as_ref(), &[]),
+        }
+    }
+    if let Some(current) = session {
+        close_session(options, current);
     }
     println!("exited loop");
 }
 
-fn load_chip8_program() -> Vec<u8> {
+fn screen_key(
+    screen: &mut Screen,
+    keycode: Keycode,
+    keymod: Mod,
+    options: &Options,
+    session: &mut Option<Session>,
+    game: &mut Game,
+    record: &mut Option<String>,
+) -> Next {
+    match screen {
+        Screen::Browser(browser) => {
+            if keycode == Keycode::Escape {
+                return match session {
+                    Some(_) => Next::Close,
+                    None => Next::Quit,
+                };
+            }
+            let path = match browser.key_down(keycode) {
+                Some(path) => path,
+                None => return Next::Stay,
+            };
+            match open_rom(options, &path, game) {
+                Ok(opened) => {
+                    replace_session(options, session, opened, record);
+                    Next::Close
+                }
+                Err(err) => {
+                    browser.set_message(err);
+                    Next::Stay
+                }
+            }
+        }
+        Screen::Pause(menu) => {
+            let current = match session.as_mut() {
+                Some(current) => current,
+                None => return Next::Close,
+            };
+            match menu.key_down(keycode) {
+                None => Next::Stay,
+                Some(PauseAction::Resume) => Next::Close,
+                Some(PauseAction::SoftReset) => {
+                    current.chip.reset();
+                    Next::Close
+                }
+                Some(PauseAction::HardReset) => {
+                    hard_reset(options, current);
+                    Next::Close
+                }
+                Some(PauseAction::SaveState) => {
+                    save_state(current);
+                    Next::Close
+                }
+                Some(PauseAction::LoadState) => {
+                    load_state(current);
+                    Next::Close
+                }
+                Some(PauseAction::Settings) => Next::Open(Screen::Settings(SettingsMenu::new())),
+                Some(PauseAction::OpenRom) => {
+                    Next::Open(Screen::Browser(Browser::new(&rom_dir(&current.path))))
+                }
+                Some(PauseAction::Quit) => Next::Quit,
+            }
+        }
+        Screen::Settings(menu) => {
+            let current = match session.as_mut() {
+                Some(current) => current,
+                None => return Next::Close,
+            };
+            if menu.key_down(keycode, &mut current.settings, &mut current.chip, game) {
+                return Next::Stay;
+            }
+            remember(&mut current.settings, &current.chip);
+            Next::Close
+        }
+        Screen::Cheats(menu) => {
+            let current = match session.as_mut() {
+                Some(current) => current,
+                None => return Next::Close,
+            };
+            let (search, settings) = (&mut current.search, &mut current.settings);
+            if menu.key_down(keycode, search, settings, &current.chip) {
+                return Next::Stay;
+            }
+            remember(&mut current.settings, &current.chip);
+            Next::Close
+        }
+        Screen::Debugger(debugger) => {
+            let current = match session.as_mut() {
+                Some(current) => current,
+                None => return Next::Close,
+            };
+            match debugger.key_down(keycode, keymod, &mut current.chip) {
+                None => Next::Stay,
+                Some(DebugAction::Close) => Next::Close,
+                Some(DebugAction::HardReset) => {
+                    hard_reset(options, current);
+                    Next::Stay
+                }
+                Some(DebugAction::ToggleBreakpoint(address)) => {
+                    current.settings.breakpoints.toggle(address);
+                    Next::Stay
+                }
+            }
+        }
+    }
+}
+
+fn screen_panel(screen: &Screen, session: &Option<Session>) -> Option<Panel> {
+    match (screen, session) {
+        (Screen::Browser(browser), _) => Some(browser.panel()),
+        (Screen::Pause(menu), Some(current)) => Some(menu.panel(&current.settings)),
+        (Screen::Settings(menu), Some(current)) => {
+            Some(menu.panel(&current.settings, &current.chip))
+        }
+        (Screen::Cheats(menu), Some(current)) => {
+            Some(menu.panel(&current.search, &current.settings, &current.chip))
+        }
+        (Screen::Debugger(debugger), Some(current)) => {
+            Some(debugger.panel(&current.chip, &current.settings))
+        }
+        _ => None,
+    }
+}
+
+fn open_rom(options: &Options, path: &Path, game: &mut Game) -> Result<Session, String> {
+    let program =
+        std::fs::read(path).map_err(|err| format!("unable to read {}: {}", path.display(), err))?;
+    println!("loading program {}...", path.display());
+    let mut chip = Chip8::load(program.clone()).map_err(|err| err.to_string())?;
+    let settings = configure(options, &file_name(path), &program, &mut chip);
+    game.set_rom(&settings.title, &settings.palette, settings.scale);
+    let filter = Filter::new(settings.filter);
+    Ok(Session {
+        path: path.to_path_buf(),
+        modified: modified(path),
+        settings,
+        chip,
+        recorder: None,
+        instruments: Instruments::new(options, program.len()),
+        filter,
+        search: None,
+    })
+}
+
+// swaps in a newly opened rom, recording it if --record is still waiting for one
+fn replace_session(
+    options: &Options,
+    session: &mut Option<Session>,
+    mut opened: Session,
+    record: &mut Option<String>,
+) {
+    if let Some(previous) = session.take() {
+        close_session(options, previous);
+    }
+    if let Some(path) = record.take() {
+        opened.recorder = start_recording(
+            Path::new(&path),
+            &opened.chip,
+            &opened.settings.palette,
+            opened.settings.scale,
+        );
+    }
+    *session = Some(opened);
+}
+
+fn close_session(options: &Options, mut session: Session) {
+    if let Some(recorder) = session.recorder {
+        stop_recording(recorder);
+    }
+    session
+        .instruments
+        .write(options, &session.chip, &session.settings.symbols);
+}
+
+// the directory the browser opens in for a rom
+fn rom_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(parent) if parent != Path::new("") => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+fn hard_reset(options: &Options, session: &mut Session) {
+    reload(options, session, true);
+}
+
+// reloads the rom when its file changed since it was last read
+fn watch(options: &Options, session: &mut Session) {
+    let modified = modified(&session.path);
+    if modified.is_some() && modified != session.modified {
+        session.modified = modified;
+        reload(options, session, options.keep_rpl_flags);
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+// reads the rom from disk again and restarts it. it goes through configure
+// like a newly opened rom so the hash, the stored rpl flags and the
+// instruments match the new file, keeping the quirks, the engine, the
+// breakpoints and possibly the rpl flags of the running rom. the running rom
+// is left alone when the file can't be loaded.
+fn reload(options: &Options, session: &mut Session, keep_rpl_flags: bool) {
+    let path = &session.path;
+    let loaded = std::fs::read(path)
+        .map_err(|err| err.to_string())
+        .and_then(|program| match Chip8::load(program.clone()) {
+            Ok(chip) => Ok((program, chip)),
+            Err(err) => Err(err.to_string()),
+        });
+    match loaded {
+        Ok((program, mut chip)) => {
+            let mut settings = configure(options, &session.settings.rom_name, &program, &mut chip);
+            chip.set_quirks(session.chip.quirks());
+            chip.set_engine(session.chip.engine());
+            if keep_rpl_flags {
+                chip.set_rpl_flags(session.chip.rpl_flags());
+            }
+            settings.breakpoints = std::mem::take(&mut session.settings.breakpoints);
+            session.settings = settings;
+            session.chip = chip;
+            session.instruments = Instruments::new(options, program.len());
+            session.filter = Filter::new(session.settings.filter);
+            println!("reloaded {}", path.display());
+        }
+        Err(err) => println!("unable to reload {}: {}", path.display(), err),
+    }
+}
+
+fn save_state(session: &Session) {
+    let settings = &session.settings;
+    let path = match settings::save_state_path(&settings.hash, settings.save_slot) {
+        Some(path) => path,
+        None => return println!("no data directory to keep save states in"),
+    };
+    let result = match path.parent() {
+        Some(dir) => std::fs::create_dir_all(dir),
+        None => Ok(()),
+    }
+    .and_then(|()| std::fs::write(&path, session.chip.save_state()));
+    match result {
+        Ok(()) => println!("saved state to {}", path.display()),
+        Err(err) => println!("unable to save state to {}: {}", path.display(), err),
+    }
+}
+
+fn load_state(session: &mut Session) {
+    let settings = &session.settings;
+    let path = match settings::save_state_path(&settings.hash, settings.save_slot) {
+        Some(path) => path,
+        None => return println!("no data directory to keep save states in"),
+    };
+    let chip = &mut session.chip;
+    match std::fs::read(&path)
+        .map_err(|err| err.to_string())
+        .and_then(|state| chip.load_state(&state).map_err(|err| err.to_string()))
+    {
+        Ok(()) => println!("loaded state from {}", path.display()),
+        Err(err) => println!("unable to load state from {}: {}", path.display(), err),
+    }
+}
+
+// runs options.frames frames without opening a window, for recording and screenshots
+fn run_headless(options: &Options, settings: &mut Settings, chip: &mut Chip8, program_len: usize) {
+    let mut recorder = options
+        .record
+        .as_ref()
+        .and_then(|path| start_recording(Path::new(path), chip, &settings.palette, settings.scale));
+    let mut instruments = Instruments::new(options, program_len);
+    // with --gdb or --dap the rom only starts once a debugger has attached
+    // and let it go on, frames spent halted are not counted
+    let mut remotes = Remotes::new(options);
+    let mut frames = 0;
+    while frames < options.frames {
+        if let Some(launch) = remotes.poll_chip(chip, settings) {
+            let refused = "headless runs the rom given on the command line, attach to it instead";
+            remotes.launched(&launch, Err(refused.to_string()));
+        }
+        if remotes.listening() && (!remotes.attached || remotes.halted()) {
+            std::thread::sleep(Duration::from_millis(5));
+            continue;
+        }
+        frames += 1;
+        match run_frame(chip, settings, &mut instruments) {
+            Ok(false) => {}
+            Ok(true) if remotes.connected() => remotes.stop(chip, &mut settings.breakpoints),
+            // there is no debugger to open, the rom goes on from the next frame
+            Ok(true) => println!(
+                "stopped at breakpoint {} ({:03x})",
+                settings.symbols.describe(chip.pc()),
+                chip.pc()
+            ),
+            Err(err) if remotes.connected() => {
+                println!("{}", err);
+                remotes.fail(&err);
+            }
+            Err(err) => {
+                println!("{}", err);
+                break;
+            }
+        }
+        record_frame(&mut recorder, chip);
+        persist_rpl_flags(settings, chip);
+    }
+    if let Some(recorder) = recorder {
+        stop_recording(recorder);
+    }
+    if let Some(path) = &options.screenshot {
+        screenshot(Path::new(path), chip, &settings.palette, settings.scale);
+    }
+    instruments.write(options, chip, &settings.symbols);
+}
+
+// the debuggers that attach from outside, --gdb and --dap
+struct Remotes {
+    gdb: Option<GdbServer>,
+    dap: Option<DapServer>,
+    // whether one has attached yet, headless runs wait for that
+    attached: bool,
+}
+
+impl Remotes {
+    fn new(options: &Options) -> Remotes {
+        let gdb = options
+            .gdb
+            .as_deref()
+            .and_then(|address| listen("gdb", address, |a| GdbServer::bind(a)));
+        let mut dap = options
+            .dap
+            .as_deref()
+            .and_then(|address| listen("an editor", address, |a| DapServer::bind(a)));
+        if let (Some(server), Some(path)) = (dap.as_mut(), &options.line_map) {
+            if let Some(lines) = load_line_map(path) {
+                server.set_line_map(lines, &rom_dir(Path::new(path)));
+            }
+        }
+        Remotes {
+            gdb,
+            dap,
+            attached: false,
+        }
+    }
+
+    fn listening(&self) -> bool {
+        self.gdb.is_some() || self.dap.is_some()
+    }
+
+    fn connected(&self) -> bool {
+        self.gdb.as_ref().is_some_and(GdbServer::connected)
+            || self.dap.as_ref().is_some_and(DapServer::connected)
+    }
+
+    // a debugger has the chip stopped
+    fn halted(&self) -> bool {
+        self.gdb.as_ref().is_some_and(GdbServer::halted)
+            || self.dap.as_ref().is_some_and(DapServer::halted)
+    }
+
+    // answers the debuggers, returns a rom an editor asked to launch
+    fn poll(&mut self, session: Option<&mut Session>) -> Option<Launch> {
+        match session {
+            Some(current) => self.poll_chip(&mut current.chip, &mut current.settings),
+            None => self.poll_dap(None),
+        }
+    }
+
+    fn poll_chip(&mut self, chip: &mut Chip8, settings: &mut Settings) -> Option<Launch> {
+        if let Some(server) = self.gdb.as_mut() {
+            let connected = server.connected();
+            if let Err(err) = server.poll(chip, &mut settings.breakpoints) {
+                println!("gdb disconnected: {}", err);
+            }
+            report_attach("gdb", connected, server.connected());
+        }
+        let launch = self.poll_dap(Some(Target {
+            chip,
+            breakpoints: &mut settings.breakpoints,
+            symbols: &settings.symbols,
+        }));
+        self.attached |= self.connected();
+        launch
+    }
+
+    fn poll_dap(&mut self, target: Option<Target>) -> Option<Launch> {
+        let server = self.dap.as_mut()?;
+        let connected = server.connected();
+        let launch = server.poll(target).unwrap_or_else(|err| {
+            println!("editor disconnected: {}", err);
+            None
+        });
+        report_attach("editor", connected, server.connected());
+        launch
+    }
+
+    // answers a launch with the rom the frontend opened for it
+    fn launched(&mut self, launch: &Launch, result: Result<(), String>) {
+        let server = match self.dap.as_mut() {
+            Some(server) => server,
+            None => return,
+        };
+        if let (Ok(()), Some(path)) = (&result, &launch.line_map) {
+            if let Some(lines) = load_line_map(path) {
+                server.set_line_map(lines, &rom_dir(Path::new(path)));
+            }
+        }
+        if let Err(err) = server.launched(result) {
+            println!("editor disconnected: {}", err);
+        }
+    }
+
+    // tells the debuggers the rom stopped at a breakpoint
+    fn stop(&mut self, chip: &Chip8, breakpoints: &mut Breakpoints) {
+        if let Some(Err(err)) = self.gdb.as_mut().map(|server| server.stop(gdb::SIGTRAP)) {
+            println!("gdb disconnected: {}", err);
+        }
+        if let Some(Err(err)) = self
+            .dap
+            .as_mut()
+            .map(|server| server.stop(chip, breakpoints))
+        {
+            println!("editor disconnected: {}", err);
+        }
+    }
+
+    // tells the debuggers the rom failed
+    fn fail(&mut self, error: &Chip8Error) {
+        if let Some(Err(err)) = self.gdb.as_mut().map(|server| server.stop(gdb::SIGSEGV)) {
+            println!("gdb disconnected: {}", err);
+        }
+        let error = error.to_string();
+        if let Some(Err(err)) = self.dap.as_mut().map(|server| server.fail(&error)) {
+            println!("editor disconnected: {}", err);
+        }
+    }
+}
+
+// binds a server for --gdb or --dap, a bare port listens on localhost only
+fn listen<T, F>(client: &str, address: &str, bind: F) -> Option<T>
+where
+    F: FnOnce(&str) -> std::io::Result<T>,
+{
+    let address = match address.parse::<u16>() {
+        Ok(port) => format!("127.0.0.1:{}", port),
+        Err(_) => address.to_string(),
+    };
+    match bind(&address) {
+        Ok(server) => {
+            println!("waiting for {} on {}", client, address);
+            Some(server)
+        }
+        Err(err) => {
+            println!("unable to listen for {} on {}: {}", client, address, err);
+            None
+        }
+    }
+}
+
+fn report_attach(client: &str, was: bool, is: bool) {
+    match (was, is) {
+        (false, true) => println!("{} attached", client),
+        (true, false) => println!("{} detached", client),
+        _ => {}
+    }
+}
+
+// what --profile, --coverage, --self-modifying and --trace collect while a
+// rom runs, written out when it is closed
+struct Instruments {
+    profile: Option<Profile>,
+    coverage: Option<Coverage>,
+    self_modifying: Option<SelfModifying>,
+    // every instruction run, one per line
+    trace: Option<BufWriter<File>>,
+}
+
+impl Instruments {
+    fn new(options: &Options, program_len: usize) -> Instruments {
+        let covered = options.coverage.is_some() || options.lcov.is_some();
+        Instruments {
+            profile: options.profile.as_ref().map(|_| Profile::new()),
+            coverage: Some(Coverage::new(program_len)).filter(|_| covered),
+            self_modifying: options
+                .self_modifying
+                .as_ref()
+                .map(|_| SelfModifying::new()),
+            trace: options
+                .trace
+                .as_ref()
+                .and_then(|path| match File::create(path) {
+                    Ok(file) => Some(BufWriter::new(file)),
+                    Err(err) => {
+                        println!("unable to trace to {}: {}", path, err);
+                        None
+                    }
+                }),
+        }
+    }
+
+    // whether record has anything to do
+    fn active(&self) -> bool {
+        self.profile.is_some()
+            || self.coverage.is_some()
+            || self.self_modifying.is_some()
+            || self.trace.is_some()
+    }
+
+    // called before each cycle
+    fn record(&mut self, chip: &Chip8, symbols: &Symbols) {
+        if let Some(profile) = &mut self.profile {
+            profile.record(chip);
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(chip);
+        }
+        if let Some(detector) = &mut self.self_modifying {
+            detector.record(chip);
+            for modification in detector.take_fresh() {
+                println!("self-modifying code: {}", modification.describe(symbols));
+            }
+        }
+        if let Some(trace) = &mut self.trace {
+            if let Err(err) = trace_instruction(trace, chip, symbols) {
+                println!("trace stopped: {}", err);
+                self.trace = None;
+            }
+        }
+    }
+
+    fn write(&mut self, options: &Options, chip: &Chip8, symbols: &Symbols) {
+        if let Some(mut trace) = self.trace.take() {
+            match trace.flush() {
+                Ok(()) => println!("saved trace"),
+                Err(err) => println!("unable to save trace: {}", err),
+            }
+        }
+        let memory = chip.memory();
+        if let (Some(path), Some(profile)) = (&options.profile, &self.profile) {
+            // the hot spot report followed by the annotated disassembly
+            let text = format!(
+                "{}\n{}",
+                profile.report(memory, 20),
+                profile.annotate(memory)
+            );
+            write_report(path, "profile", text);
+        }
+        if let (Some(path), Some(detector)) = (&options.self_modifying, &mut self.self_modifying) {
+            detector.settle(chip);
+            write_report(path, "self-modifying code report", detector.report(symbols));
+        }
+        let coverage = match &self.coverage {
+            Some(coverage) => coverage,
+            None => return,
+        };
+        if let Some(path) = &options.coverage {
+            write_report(path, "coverage", coverage.text());
+        }
+        if let (Some(path), Some(line_map)) = (&options.lcov, &options.line_map) {
+            if let Some(lines) = load_line_map(line_map) {
+                write_report(path, "lcov coverage", coverage.lcov(&lines));
+            }
+        }
+    }
+}
+
+// "2a6 draw_player+4: d125  drw v1, v2, 5", without the label when there
+// are no symbols
+fn trace_instruction(
+    trace: &mut BufWriter<File>,
+    chip: &Chip8,
+    symbols: &Symbols,
+) -> std::io::Result<()> {
+    if chip.waiting_for_vblank() {
+        return Ok(());
+    }
+    let pc = chip.pc();
+    let opcode = opcode_at(chip.memory(), pc);
+    let instruction = disassemble_with(opcode, symbols);
+    match symbols.is_empty() {
+        true => writeln!(trace, "{:03x}: {:04x}  {}", pc, opcode, instruction),
+        false => writeln!(
+            trace,
+            "{:03x} {}: {:04x}  {}",
+            pc,
+            symbols.describe(pc),
+            opcode,
+            instruction
+        ),
+    }
+}
+
+fn write_report(path: &str, what: &str, text: String) {
+    match std::fs::write(path, text) {
+        Ok(()) => println!("saved {} {}", what, path),
+        Err(err) => println!("unable to save {} {}: {}", what, path, err),
+    }
+}
+
+// <rom name>-<milliseconds since epoch>.<extension> in the working directory
+fn capture_name(rom_name: &str, extension: &str) -> String {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis())
+        .unwrap_or_default();
+    let stem = Path::new(rom_name)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "chip8".to_string());
+    format!("{}-{}.{}", stem, millis, extension)
+}
+
+fn screenshot(path: &Path, chip: &Chip8, palette: &Palette, scale: u32) {
+    let (width, height) = chip.screen_size();
+    match capture::save_png(path, &chip.gfx, width, height, palette, scale) {
+        Ok(()) => println!("saved screenshot {}", path.display()),
+        Err(err) => println!("unable to save screenshot {}: {}", path.display(), err),
+    }
+}
+
+fn start_recording(path: &Path, chip: &Chip8, palette: &Palette, scale: u32) -> Option<Recorder> {
+    let (width, height) = chip.screen_size();
+    match Recorder::create(path, width, height, palette, scale) {
+        Ok(recorder) => {
+            println!("recording to {}", path.display());
+            Some(recorder)
+        }
+        Err(err) => {
+            println!("unable to record to {}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+// a failed write stops the recording instead of the emulator
+fn record_frame(recorder: &mut Option<Recorder>, chip: &Chip8) {
+    if let Some(active) = recorder {
+        if let Err(err) = active.add_frame(&chip.gfx) {
+            println!("recording stopped: {}", err);
+            *recorder = None;
+        }
+    }
+}
+
+fn stop_recording(recorder: Recorder) {
+    match recorder.finish() {
+        Ok(()) => println!("recording saved"),
+        Err(err) => println!("unable to finish recording: {}", err),
+    }
+}
+
+// returns the file name of the rom and its contents, the path is read from
+// stdin when not given on the command line
+fn load_chip8_program(path: Option<&str>) -> (String, Vec<u8>) {
     let mut filename = String::new();
-    std::io::stdin().read_line(&mut filename).expect("Error reading input");
+    match path {
+        Some(path) => filename.push_str(path),
+        None => {
+            std::io::stdin()
+                .read_line(&mut filename)
+                .expect("Error reading input");
+        }
+    }
     println!("loading program {}...", &filename);
 
-    let mut f = File::open(&filename.trim()).expect("no file found");
-    let metadata = std::fs::metadata(&filename.trim()).expect("unable to read metadata");
+    let mut f = File::open(filename.trim()).expect("no file found");
+    let metadata = std::fs::metadata(filename.trim()).expect("unable to read metadata");
     let mut buffer = vec![0; metadata.len() as usize];
-    f.read(&mut buffer).expect("buffer overflow");
+    f.read_exact(&mut buffer).expect("buffer overflow");
+
+    (file_name(Path::new(filename.trim())), buffer)
+}
 
-    buffer
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
 }
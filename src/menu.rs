@@ -0,0 +1,236 @@
+use crate::game::Game;
+use crate::overlay::Panel;
+use crate::Settings;
+use chip8::chip8::Quirks;
+use chip8::Chip8;
+use sdl2::keyboard::Keycode;
+
+// what the pause menu asks the frontend to do
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PauseAction {
+    Resume,
+    SoftReset,
+    HardReset,
+    SaveState,
+    LoadState,
+    Settings,
+    OpenRom,
+    Quit,
+}
+
+const PAUSE_ACTIONS: [PauseAction; 8] = [
+    PauseAction::Resume,
+    PauseAction::SoftReset,
+    PauseAction::HardReset,
+    PauseAction::SaveState,
+    PauseAction::LoadState,
+    PauseAction::Settings,
+    PauseAction::OpenRom,
+    PauseAction::Quit,
+];
+
+pub struct PauseMenu {
+    selected: usize,
+}
+
+impl PauseMenu {
+    pub fn new() -> PauseMenu {
+        PauseMenu { selected: 0 }
+    }
+
+    // returns the action picked with return, escape resumes
+    pub fn key_down(&mut self, keycode: Keycode) -> Option<PauseAction> {
+        let count = PAUSE_ACTIONS.len();
+        match keycode {
+            Keycode::Up => self.selected = (self.selected + count - 1) % count,
+            Keycode::Down => self.selected = (self.selected + 1) % count,
+            Keycode::Return => return Some(PAUSE_ACTIONS[self.selected]),
+            Keycode::Escape => return Some(PauseAction::Resume),
+            _ => {}
+        }
+        None
+    }
+
+    pub fn panel(&self, settings: &Settings) -> Panel {
+        let items = PAUSE_ACTIONS
+            .iter()
+            .map(|action| match action {
+                PauseAction::Resume => "resume".to_string(),
+                PauseAction::SoftReset => "reset (f5)".to_string(),
+                PauseAction::HardReset => "reload rom from disk (shift+f5)".to_string(),
+                PauseAction::SaveState => format!("save state (slot {})", settings.save_slot),
+                PauseAction::LoadState => format!("load state (slot {})", settings.save_slot),
+                PauseAction::Settings => "settings".to_string(),
+                PauseAction::OpenRom => "open rom".to_string(),
+                PauseAction::Quit => "quit".to_string(),
+            })
+            .collect();
+        Panel {
+            title: format!("paused - {}", settings.title),
+            items,
+            selected: self.selected,
+            footer: "return: select  escape: resume".to_string(),
+            marks: Vec::new(),
+        }
+    }
+}
+
+// the per rom settings. up/down picks an entry, left/right or return
+// changes it and escape closes the menu.
+pub struct SettingsMenu {
+    selected: usize,
+    // the selected chip8 key is waiting for the keyboard key to bind to it
+    rebinding: bool,
+}
+
+#[derive(Clone, Copy)]
+enum Entry {
+    Tickrate,
+    Palette,
+    Filter,
+    Scale,
+    SaveSlot,
+    Quirk(usize),
+    StackDepth,
+    Key(usize),
+}
+
+const QUIRKS: usize = 7;
+const ENTRIES: usize = 5 + QUIRKS + 1 + 16;
+
+impl SettingsMenu {
+    pub fn new() -> SettingsMenu {
+        SettingsMenu {
+            selected: 0,
+            rebinding: false,
+        }
+    }
+
+    // returns false once the menu is closed
+    pub fn key_down(
+        &mut self,
+        keycode: Keycode,
+        settings: &mut Settings,
+        chip: &mut Chip8,
+        game: &mut Game,
+    ) -> bool {
+        let entry = entry(self.selected);
+        if self.rebinding {
+            if let (Entry::Key(chip_key), false) = (entry, keycode == Keycode::Escape) {
+                settings.keymap.bind(chip_key, keycode);
+            }
+            self.rebinding = false;
+            return true;
+        }
+        match keycode {
+            Keycode::Escape | Keycode::F1 => return false,
+            Keycode::Up => self.selected = (self.selected + ENTRIES - 1) % ENTRIES,
+            Keycode::Down => self.selected = (self.selected + 1) % ENTRIES,
+            Keycode::Left | Keycode::Right | Keycode::Return => {
+                let step = if keycode == Keycode::Left { -1 } else { 1 };
+                match entry {
+                    Entry::Tickrate => settings.tickrate = adjust(settings.tickrate, step, 1, 1000),
+                    Entry::Palette => {
+                        game.cycle_palette(step > 0);
+                        settings.palette = game.palette().clone();
+                    }
+                    Entry::Filter => settings.filter = settings.filter.cycle(step > 0),
+                    Entry::Scale => {
+                        settings.scale = adjust(settings.scale, step, 1, 40);
+                        game.set_scale(settings.scale);
+                    }
+                    Entry::SaveSlot => settings.save_slot = adjust(settings.save_slot, step, 1, 9),
+                    Entry::Quirk(i) => {
+                        let mut quirks = chip.quirks();
+                        let (_, quirk) = quirk(&mut quirks, i);
+                        *quirk = !*quirk;
+                        chip.set_quirks(quirks);
+                    }
+                    // the two depths platforms have
+                    Entry::StackDepth => {
+                        let mut quirks = chip.quirks();
+                        quirks.stack_depth = if quirks.stack_depth == 12 { 16 } else { 12 };
+                        chip.set_quirks(quirks);
+                    }
+                    Entry::Key(_) => self.rebinding = keycode == Keycode::Return,
+                }
+            }
+            _ => {}
+        }
+        true
+    }
+
+    pub fn panel(&self, settings: &Settings, chip: &Chip8) -> Panel {
+        let footer = match (entry(self.selected), self.rebinding) {
+            (Entry::Key(chip_key), true) => {
+                format!("press the key for {:X}, escape cancels", chip_key)
+            }
+            (Entry::Key(_), false) => "return: rebind  escape: close".to_string(),
+            _ => "left/right: change  escape: close".to_string(),
+        };
+        Panel {
+            title: format!("settings - {}", settings.title),
+            items: (0..ENTRIES)
+                .map(|index| describe(entry(index), settings, chip))
+                .collect(),
+            selected: self.selected,
+            footer,
+            marks: Vec::new(),
+        }
+    }
+}
+
+fn describe(entry: Entry, settings: &Settings, chip: &Chip8) -> String {
+    match entry {
+        Entry::Tickrate => format!("tickrate < {} >", settings.tickrate),
+        Entry::Palette => format!("palette < {} >", settings.palette.name),
+        Entry::Filter => format!("filter < {} >", settings.filter.spec()),
+        Entry::Scale => format!("scale < {} >", settings.scale),
+        Entry::SaveSlot => format!("save slot < {} >", settings.save_slot),
+        Entry::Quirk(i) => {
+            let mut quirks = chip.quirks();
+            let (name, quirk) = quirk(&mut quirks, i);
+            format!("{} quirk < {} >", name, if *quirk { "on" } else { "off" })
+        }
+        Entry::StackDepth => format!("stack depth < {} >", chip.stack_depth()),
+        Entry::Key(chip_key) => {
+            format!(
+                "key {:X}: {}",
+                chip_key,
+                settings.keymap.key(chip_key).name()
+            )
+        }
+    }
+}
+
+fn entry(index: usize) -> Entry {
+    match index {
+        0 => Entry::Tickrate,
+        1 => Entry::Palette,
+        2 => Entry::Filter,
+        3 => Entry::Scale,
+        4 => Entry::SaveSlot,
+        i if i < 5 + QUIRKS => Entry::Quirk(i - 5),
+        i if i == 5 + QUIRKS => Entry::StackDepth,
+        i => Entry::Key(i - 6 - QUIRKS),
+    }
+}
+
+fn quirk(quirks: &mut Quirks, index: usize) -> (&'static str, &mut bool) {
+    match index {
+        0 => ("shift", &mut quirks.shift),
+        1 => ("memory increment by x", &mut quirks.memory_increment_by_x),
+        2 => (
+            "memory leave i unchanged",
+            &mut quirks.memory_leave_i_unchanged,
+        ),
+        3 => ("wrap", &mut quirks.wrap),
+        4 => ("jump", &mut quirks.jump),
+        5 => ("vblank", &mut quirks.vblank),
+        _ => ("logic", &mut quirks.logic),
+    }
+}
+
+fn adjust(value: u32, step: i32, min: u32, max: u32) -> u32 {
+    (value as i64 + step as i64).clamp(min as i64, max as i64) as u32
+}
@@ -0,0 +1,221 @@
+use chip8::cheat::Cheat;
+use chip8::chip8::Engine;
+use chip8::filter::FilterMode;
+use chip8::palette::Palette;
+use chip8::watch::Watch;
+
+const USAGE: &str = "usage: chip8 [--palette NAME|#rrggbb,#rrggbb] [--scale N] [--grid]
+             [--filter none|blend[:FRAMES]|decay[:FACTOR]|settled]
+             [--scaling integer|fit] [--headless] [--frames N]
+             [--screenshot PATH] [--record PATH] [--platform ID]
+             [--tickrate N] [--database PROGRAMS_JSON]
+             [--settings PATH] [--watch [--keep-rpl-flags]]
+             [--watch-expr [NAME=]EXPR]... [--cheat ADDRESS=VALUE]...
+             [--profile PATH] [--coverage PATH]
+             [--lcov PATH --line-map PATH] [--trace PATH]
+             [--self-modifying PATH]
+             [--symbols PATH] [--break LABEL|ADDRESS]...
+             [--gdb [HOST:]PORT] [--dap [HOST:]PORT]
+             [--engine reference|cached|jit|lockstep] [ROM]";
+
+// how the screen is scaled to fill the window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scaling {
+    // largest whole multiple of the chip8 resolution that fits
+    Integer,
+    // as large as fits, keeping the aspect ratio
+    Fit,
+}
+
+// frontend settings taken from the command line. the ones that are optional
+// override what the rom database and the saved settings of the rom say.
+pub struct Options {
+    pub palette: Option<Palette>,
+    // size of a chip8 pixel in window pixels when the window opens
+    pub scale: Option<u32>,
+    pub scaling: Scaling,
+    // leave a one pixel gap between chip8 pixels
+    pub grid: bool,
+    // flicker reduction applied before drawing
+    pub filter: Option<FilterMode>,
+    // rom to run, when missing the rom browser opens (headless runs ask on stdin)
+    pub rom: Option<String>,
+    // run without a window for a fixed number of frames
+    pub headless: bool,
+    pub frames: u32,
+    // png written when a headless run ends
+    pub screenshot: Option<String>,
+    // gif recorded from the first frame
+    pub record: Option<String>,
+    // platform id whose quirks and tickrate are used, e.g. originalChip8
+    pub platform: Option<String>,
+    // instructions run per frame
+    pub tickrate: Option<u32>,
+    // extra programs.json looked up before the bundled one
+    pub database: Option<String>,
+    // per rom settings file to use instead of the one in the config directory
+    pub settings: Option<String>,
+    // reload and reset the rom when its file changes
+    pub watch: bool,
+    // keep the rpl user flags across those reloads instead of clearing them
+    pub keep_rpl_flags: bool,
+    // shown by the debugger and over the game, on top of the saved ones
+    pub watches: Vec<Watch>,
+    // frozen every frame, on top of the saved ones
+    pub cheats: Vec<Cheat>,
+    // profile report and annotated disassembly written when the rom is closed
+    pub profile: Option<String>,
+    // map of the bytes run, drawn and written, also written when the rom is closed
+    pub coverage: Option<String>,
+    // the same as lcov records, keyed to the source lines in the line map
+    pub lcov: Option<String>,
+    pub line_map: Option<String>,
+    // every instruction run, written as the rom runs
+    pub trace: Option<String>,
+    // writes to code that has run or is run later, flagged as they happen
+    // and written out when the rom is closed
+    pub self_modifying: Option<String>,
+    // labels shown in place of addresses, see Symbols::parse
+    pub symbols: Option<String>,
+    // where the debugger opens, as labels, label+offset or addresses
+    pub breakpoints: Vec<String>,
+    // address the gdb remote protocol stub listens on
+    pub gdb: Option<String>,
+    // address the debug adapter protocol server listens on for editors
+    pub dap: Option<String>,
+    // how instructions are run, the cached engine and the jit are for
+    // running roms in bulk. lockstep checks the jit as it goes.
+    pub engine: Engine,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            palette: None,
+            scale: None,
+            scaling: Scaling::Integer,
+            grid: false,
+            filter: None,
+            rom: None,
+            headless: false,
+            frames: 600,
+            screenshot: None,
+            record: None,
+            platform: None,
+            tickrate: None,
+            database: None,
+            settings: None,
+            watch: false,
+            keep_rpl_flags: false,
+            watches: Vec::new(),
+            cheats: Vec::new(),
+            profile: None,
+            coverage: None,
+            lcov: None,
+            line_map: None,
+            trace: None,
+            self_modifying: None,
+            symbols: None,
+            breakpoints: Vec::new(),
+            gdb: None,
+            dap: None,
+            engine: Engine::default(),
+        }
+    }
+}
+
+impl Options {
+    pub fn from_args() -> Result<Options, String> {
+        Options::parse(std::env::args().skip(1))
+    }
+
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+        let mut options = Options::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--palette" => options.palette = Some(Palette::parse(&value(&mut args, &arg)?)?),
+                "--scale" => {
+                    options.scale = match value(&mut args, &arg)?.parse() {
+                        Ok(scale) if scale > 0 => Some(scale),
+                        _ => return Err(format!("--scale expects a positive number\n{}", USAGE)),
+                    }
+                }
+                "--scaling" => {
+                    options.scaling = match value(&mut args, &arg)?.as_str() {
+                        "integer" => Scaling::Integer,
+                        "fit" => Scaling::Fit,
+                        _ => return Err(format!("--scaling expects integer or fit\n{}", USAGE)),
+                    }
+                }
+                "--grid" => options.grid = true,
+                "--filter" => options.filter = Some(FilterMode::parse(&value(&mut args, &arg)?)?),
+                "--headless" => options.headless = true,
+                "--frames" => {
+                    options.frames = value(&mut args, &arg)?
+                        .parse()
+                        .map_err(|_| format!("--frames expects a number\n{}", USAGE))?
+                }
+                "--screenshot" => options.screenshot = Some(value(&mut args, &arg)?),
+                "--record" => options.record = Some(value(&mut args, &arg)?),
+                "--platform" => options.platform = Some(value(&mut args, &arg)?),
+                "--tickrate" => {
+                    options.tickrate = match value(&mut args, &arg)?.parse() {
+                        Ok(tickrate) if tickrate > 0 => Some(tickrate),
+                        _ => {
+                            return Err(format!("--tickrate expects a positive number\n{}", USAGE))
+                        }
+                    }
+                }
+                "--database" => options.database = Some(value(&mut args, &arg)?),
+                "--settings" => options.settings = Some(value(&mut args, &arg)?),
+                "--watch" => options.watch = true,
+                "--keep-rpl-flags" => options.keep_rpl_flags = true,
+                "--watch-expr" => options
+                    .watches
+                    .push(Watch::parse(&value(&mut args, &arg)?)?),
+                "--cheat" => options.cheats.push(Cheat::parse(&value(&mut args, &arg)?)?),
+                "--profile" => options.profile = Some(value(&mut args, &arg)?),
+                "--coverage" => options.coverage = Some(value(&mut args, &arg)?),
+                "--lcov" => options.lcov = Some(value(&mut args, &arg)?),
+                "--line-map" => options.line_map = Some(value(&mut args, &arg)?),
+                "--trace" => options.trace = Some(value(&mut args, &arg)?),
+                "--self-modifying" => options.self_modifying = Some(value(&mut args, &arg)?),
+                "--symbols" => options.symbols = Some(value(&mut args, &arg)?),
+                "--break" => options.breakpoints.push(value(&mut args, &arg)?),
+                "--gdb" => options.gdb = Some(value(&mut args, &arg)?),
+                "--dap" => options.dap = Some(value(&mut args, &arg)?),
+                "--engine" => {
+                    options.engine = match value(&mut args, &arg)?.as_str() {
+                        "reference" => Engine::Reference,
+                        "cached" => Engine::Cached,
+                        #[cfg(feature = "jit")]
+                        "jit" => Engine::Jit,
+                        #[cfg(feature = "jit")]
+                        "lockstep" => Engine::Lockstep,
+                        #[cfg(not(feature = "jit"))]
+                        "jit" | "lockstep" => {
+                            return Err("this build has no jit, build it with --features jit".into())
+                        }
+                        _ => {
+                            return Err(format!(
+                                "--engine expects reference, cached, jit or lockstep\n{}",
+                                USAGE
+                            ))
+                        }
+                    }
+                }
+                _ if !arg.starts_with("--") && options.rom.is_none() => options.rom = Some(arg),
+                _ => return Err(format!("unknown argument {}\n{}", arg, USAGE)),
+            }
+        }
+        if options.lcov.is_some() && options.line_map.is_none() {
+            return Err(format!("--lcov needs a --line-map\n{}", USAGE));
+        }
+        Ok(options)
+    }
+}
+
+fn value<I: Iterator<Item = String>>(args: &mut I, flag: &str) -> Result<String, String> {
+    args.next()
+        .ok_or_else(|| format!("{} expects a value\n{}", flag, USAGE))
+}
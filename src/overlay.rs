@@ -0,0 +1,187 @@
+use crate::font;
+use crate::game::rgb;
+use chip8::palette::Palette;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, WindowCanvas};
+
+// columns of text that fit across the window, the font is scaled to match
+const COLUMNS: u32 = 40;
+
+// a titled list drawn over the dimmed screen, used by the rom browser, the
+// menus and the debugger
+pub struct Panel {
+    pub title: String,
+    pub items: Vec<String>,
+    pub selected: usize,
+    // help or a message, drawn below the list
+    pub footer: String,
+    // highlighted runs of characters. when there are any the selected item is
+    // only kept in view instead of being drawn inverted.
+    pub marks: Vec<Mark>,
+}
+
+#[derive(Clone, Copy)]
+pub struct Mark {
+    pub item: usize,
+    pub column: usize,
+    pub len: usize,
+    pub style: Style,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Style {
+    // drawn inverted
+    Cursor,
+    // drawn on a half bright background
+    Strong,
+    // underlined
+    Underline,
+    // drawn on a red background that fades out as the amount goes from 1.0 to 0.0
+    Flash(f32),
+}
+
+// draws with the palette so the overlay matches the game, the selected item
+// is drawn inverted. items scroll to keep the selected one in view.
+#[allow(unused_must_use)]
+pub fn draw(canvas: &mut WindowCanvas, panel: &Panel, palette: &Palette) {
+    let (width, height) = canvas.output_size().unwrap_or((1, 1));
+    let size = (width / (COLUMNS * (font::WIDTH + 1))).max(1);
+    let line = (font::HEIGHT + 2) * size;
+    let margin = line as i32;
+
+    canvas.set_blend_mode(BlendMode::Blend);
+    canvas.set_draw_color(Color::RGBA(0, 0, 0, 200));
+    canvas.fill_rect(None);
+    canvas.set_blend_mode(BlendMode::None);
+
+    let foreground = rgb(palette.foreground);
+    let background = rgb(palette.background);
+    draw_text(canvas, &panel.title, margin, margin, size, foreground);
+
+    // title, a blank line, the items, a blank line and the footer
+    let rows = ((height as i32 - 2 * margin) / line as i32 - 4).max(1) as usize;
+    let first = panel
+        .selected
+        .saturating_sub(rows / 2)
+        .min(panel.items.len().saturating_sub(rows));
+    for (row, item) in panel.items.iter().enumerate().skip(first).take(rows) {
+        let y = margin + ((row - first) as u32 + 2) as i32 * line as i32;
+        let colour = if row == panel.selected && panel.marks.is_empty() {
+            canvas.set_draw_color(foreground);
+            canvas.fill_rect(Rect::new(
+                margin - size as i32,
+                y - size as i32,
+                width - 2 * (margin - size as i32) as u32,
+                line,
+            ));
+            background
+        } else {
+            foreground
+        };
+        draw_text(canvas, item, margin, y, size, colour);
+        for mark in panel.marks.iter().filter(|mark| mark.item == row) {
+            draw_mark(canvas, item, mark, margin, y, size, palette);
+        }
+    }
+
+    let footer = margin + (rows as u32 + 3) as i32 * line as i32;
+    draw_text(canvas, &panel.footer, margin, footer, size, foreground);
+}
+
+// a few lines in the top left corner over the running game, e.g. watches
+#[allow(unused_must_use)]
+pub fn draw_hud(canvas: &mut WindowCanvas, lines: &[String], palette: &Palette) {
+    let (width, _) = canvas.output_size().unwrap_or((1, 1));
+    let size = (width / (2 * COLUMNS * (font::WIDTH + 1))).max(1);
+    let line = (font::HEIGHT + 2) * size;
+    let columns = lines.iter().map(|text| text.len()).max().unwrap_or(0) as u32;
+
+    canvas.set_blend_mode(BlendMode::Blend);
+    canvas.set_draw_color(Color::RGBA(0, 0, 0, 160));
+    canvas.fill_rect(Rect::new(
+        0,
+        0,
+        (columns * (font::WIDTH + 1) + 2) * size,
+        lines.len() as u32 * line + size,
+    ));
+    canvas.set_blend_mode(BlendMode::None);
+    for (row, text) in lines.iter().enumerate() {
+        let y = (row as u32 * line + size) as i32;
+        draw_text(canvas, text, size as i32, y, size, rgb(palette.foreground));
+    }
+}
+
+// redraws the marked characters of an item over their highlight
+#[allow(unused_must_use)]
+fn draw_mark(
+    canvas: &mut WindowCanvas,
+    item: &str,
+    mark: &Mark,
+    x: i32,
+    y: i32,
+    size: u32,
+    palette: &Palette,
+) {
+    let text: String = item.chars().skip(mark.column).take(mark.len).collect();
+    let advance = (font::WIDTH + 1) * size;
+    let left = x + (mark.column as u32 * advance) as i32 - (size / 2) as i32;
+    let area = Rect::new(
+        left,
+        y - size as i32,
+        mark.len as u32 * advance,
+        (font::HEIGHT + 2) * size,
+    );
+    let foreground = rgb(palette.foreground);
+    let text_x = x + (mark.column as u32 * advance) as i32;
+    match mark.style {
+        Style::Cursor => {
+            canvas.set_draw_color(foreground);
+            canvas.fill_rect(area);
+            draw_text(canvas, &text, text_x, y, size, rgb(palette.background));
+        }
+        Style::Strong => {
+            canvas.set_blend_mode(BlendMode::Blend);
+            canvas.set_draw_color(Color::RGBA(foreground.r, foreground.g, foreground.b, 96));
+            canvas.fill_rect(area);
+            canvas.set_blend_mode(BlendMode::None);
+            draw_text(canvas, &text, text_x, y, size, foreground);
+        }
+        Style::Underline => {
+            canvas.set_draw_color(foreground);
+            canvas.fill_rect(Rect::new(
+                left,
+                y + (font::HEIGHT * size) as i32,
+                area.width(),
+                size,
+            ));
+        }
+        Style::Flash(amount) => {
+            canvas.set_blend_mode(BlendMode::Blend);
+            canvas.set_draw_color(Color::RGBA(0xff, 0x40, 0x40, (amount * 192.0) as u8));
+            canvas.fill_rect(area);
+            canvas.set_blend_mode(BlendMode::None);
+            draw_text(canvas, &text, text_x, y, size, foreground);
+        }
+    }
+}
+
+#[allow(unused_must_use)]
+fn draw_text(canvas: &mut WindowCanvas, text: &str, x: i32, y: i32, size: u32, colour: Color) {
+    canvas.set_draw_color(colour);
+    for (column, c) in text.chars().enumerate() {
+        let left = x + (column as u32 * (font::WIDTH + 1) * size) as i32;
+        for (row, bits) in font::glyph(c).iter().enumerate() {
+            for bit in 0..font::WIDTH {
+                if bits & (1 << (font::WIDTH - 1 - bit)) != 0 {
+                    canvas.fill_rect(Rect::new(
+                        left + (bit * size) as i32,
+                        y + (row as u32 * size) as i32,
+                        size,
+                        size,
+                    ));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,109 @@
+// colours used to draw the framebuffer, kept as plain rgb so they can be
+// used without sdl (e.g. when writing screenshots)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    pub name: String,
+    pub background: [u8; 3],
+    pub foreground: [u8; 3],
+}
+
+// (name, background, foreground) of the built in palettes, the first one is the default
+const BUILTIN: [(&str, [u8; 3], [u8; 3]); 5] = [
+    ("cyan", [0x00, 0xff, 0xff], [0xff, 0xff, 0xff]),
+    ("classic", [0x00, 0x00, 0x00], [0xff, 0xff, 0xff]),
+    ("amber", [0x1a, 0x0e, 0x00], [0xff, 0xb0, 0x00]),
+    ("green", [0x00, 0x14, 0x00], [0x33, 0xff, 0x33]),
+    ("octo", [0x99, 0x66, 0x00], [0xff, 0xcc, 0x00]),
+];
+
+impl Palette {
+    pub fn builtin() -> Vec<Palette> {
+        BUILTIN
+            .iter()
+            .map(|&(name, background, foreground)| Palette {
+                name: name.to_string(),
+                background,
+                foreground,
+            })
+            .collect()
+    }
+
+    pub fn named(name: &str) -> Option<Palette> {
+        Palette::builtin().into_iter().find(|p| p.name == name)
+    }
+
+    // accepts either a builtin palette name or "#rrggbb,#rrggbb" (background, foreground)
+    pub fn parse(s: &str) -> Result<Palette, String> {
+        if let Some(palette) = Palette::named(s) {
+            return Ok(palette);
+        }
+        let mut colours = s.split(',');
+        match (colours.next(), colours.next(), colours.next()) {
+            (Some(background), Some(foreground), None) => Ok(Palette {
+                name: s.to_string(),
+                background: parse_hex_colour(background)?,
+                foreground: parse_hex_colour(foreground)?,
+            }),
+            _ => Err(format!(
+                "unknown palette {}, expected one of {} or #rrggbb,#rrggbb",
+                s,
+                BUILTIN.iter().map(|p| p.0).collect::<Vec<_>>().join(", ")
+            )),
+        }
+    }
+
+    // the string parse turns back into this palette
+    pub fn spec(&self) -> String {
+        if Palette::named(&self.name).as_ref() == Some(self) {
+            return self.name.clone();
+        }
+        let hex = |c: [u8; 3]| format!("#{:02x}{:02x}{:02x}", c[0], c[1], c[2]);
+        format!("{},{}", hex(self.background), hex(self.foreground))
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::builtin().remove(0)
+    }
+}
+
+fn parse_hex_colour(s: &str) -> Result<[u8; 3], String> {
+    let hex = s.trim().trim_start_matches('#');
+    if hex.len() != 6 || !hex.is_ascii() {
+        return Err(format!("invalid colour {}, expected #rrggbb", s));
+    }
+    let mut rgb = [0; 3];
+    for (i, channel) in rgb.iter_mut().enumerate() {
+        *channel = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|_| format!("invalid colour {}, expected #rrggbb", s))?;
+    }
+    Ok(rgb)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_palette() {
+        assert_eq!(
+            Palette::parse("amber"),
+            Ok(Palette::named("amber").unwrap())
+        );
+
+        let custom = Palette::parse("#102030,#ffeedd").unwrap();
+        assert_eq!(custom.background, [0x10, 0x20, 0x30]);
+        assert_eq!(custom.foreground, [0xff, 0xee, 0xdd]);
+
+        assert_eq!(
+            Palette::parse(&custom.spec()).unwrap().foreground,
+            custom.foreground
+        );
+        assert_eq!(Palette::named("amber").unwrap().spec(), "amber");
+
+        assert!(Palette::parse("nope").is_err());
+        assert!(Palette::parse("#10203,#ffeedd").is_err());
+        assert!(Palette::parse("#10203g,#ffeedd").is_err());
+    }
+}
@@ -0,0 +1,240 @@
+// counts what a rom spends its cycles on: each instruction address, each
+// class of opcode and each subroutine, followed through 2NNN calls and 00EE
+// returns. the report is for fitting a rom into the few instructions per
+// frame the COSMAC VIP managed.
+use crate::chip8::Chip8;
+use crate::disasm::{self, disassemble, opcode_at};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Routine {
+    pub calls: u64,
+    // cycles spent in the routine itself
+    pub own: u64,
+    // cycles from the calls to the returns, including nested calls. recursion
+    // counts the inner calls again.
+    pub total: u64,
+}
+
+pub struct Profile {
+    cycles: u64,
+    // cycles spent waiting for vblank with the vblank quirk
+    waiting: u64,
+    counts: Vec<u64>,
+    classes: BTreeMap<&'static str, u64>,
+    routines: BTreeMap<u16, Routine>,
+    // cycles outside any subroutine
+    main: u64,
+    // the routines being run and the cycle each was called on
+    stack: Vec<(u16, u64)>,
+}
+
+impl Default for Profile {
+    fn default() -> Profile {
+        Profile::new()
+    }
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile {
+            cycles: 0,
+            waiting: 0,
+            counts: vec![0; 4096],
+            classes: BTreeMap::new(),
+            routines: BTreeMap::new(),
+            main: 0,
+            stack: Vec::new(),
+        }
+    }
+
+    // called before each cycle with the instruction about to run
+    pub fn record(&mut self, chip: &Chip8) {
+        self.cycles += 1;
+        if chip.waiting_for_vblank() {
+            self.waiting += 1;
+            return;
+        }
+        let pc = chip.pc() & 0xfff;
+        let opcode = opcode_at(chip.memory(), pc);
+        self.counts[pc as usize] += 1;
+        *self.classes.entry(disasm::pattern(opcode)).or_default() += 1;
+        match self.stack.last() {
+            Some(&(routine, _)) => self.routines.entry(routine).or_default().own += 1,
+            None => self.main += 1,
+        }
+
+        match opcode & 0xf000 {
+            0x2000 => {
+                let routine = opcode & 0xfff;
+                self.routines.entry(routine).or_default().calls += 1;
+                self.stack.push((routine, self.cycles));
+            }
+            // a return without a call was made before profiling started
+            0x0000 if opcode == 0x00ee => {
+                if let Some((routine, called)) = self.stack.pop() {
+                    self.routines.entry(routine).or_default().total += self.cycles - called;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // times the instruction at address was run
+    pub fn count(&self, address: u16) -> u64 {
+        self.counts[address as usize & 0xfff]
+    }
+
+    pub fn routines(&self) -> &BTreeMap<u16, Routine> {
+        &self.routines
+    }
+
+    // the most run instructions, the opcode classes and the subroutines, each
+    // sorted with the most cycles first
+    pub fn report(&self, memory: &[u8; 4096], top: usize) -> String {
+        let share = |count: u64| 100.0 * count as f64 / self.cycles.max(1) as f64;
+        let mut report = String::new();
+        let _ = writeln!(
+            report,
+            "{} cycles, {} of them waiting for vblank\n",
+            self.cycles, self.waiting
+        );
+
+        let mut hot: Vec<(u16, u64)> = (0..0x1000)
+            .map(|address| (address, self.count(address)))
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let _ = writeln!(report, "hot spots:");
+        for (address, count) in hot.into_iter().take(top) {
+            let opcode = opcode_at(memory, address);
+            let _ = writeln!(
+                report,
+                "  {:03x}  {:>10}  {:5.1}%  {:04x}  {}",
+                address,
+                count,
+                share(count),
+                opcode,
+                disassemble(opcode)
+            );
+        }
+
+        let mut classes: Vec<(&str, u64)> = self.classes.iter().map(|(&c, &n)| (c, n)).collect();
+        classes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        let _ = writeln!(report, "\nopcode classes:");
+        for (class, count) in classes {
+            let _ = writeln!(report, "  {}  {:>10}  {:5.1}%", class, count, share(count));
+        }
+
+        let mut routines: Vec<(u16, Routine)> =
+            self.routines.iter().map(|(&a, &r)| (a, r)).collect();
+        routines.sort_by(|a, b| b.1.total.cmp(&a.1.total).then(a.0.cmp(&b.0)));
+        let _ = writeln!(report, "\nsubroutines:");
+        let _ = writeln!(
+            report,
+            "  addr  {:>8}  {:>10}  {:>10}  {:>6}",
+            "calls", "own", "total", "total%"
+        );
+        let _ = writeln!(
+            report,
+            "  main  {:>8}  {:>10}  {:>10}  {:5.1}%",
+            "-",
+            self.main,
+            self.cycles - self.waiting,
+            share(self.cycles - self.waiting)
+        );
+        for (address, routine) in routines {
+            let _ = writeln!(
+                report,
+                "  {:03x}   {:>8}  {:>10}  {:>10}  {:5.1}%",
+                address,
+                routine.calls,
+                routine.own,
+                routine.total,
+                share(routine.total)
+            );
+        }
+        report
+    }
+
+    // every instruction that was run with its count, gaps in between are
+    // marked with "..."
+    pub fn annotate(&self, memory: &[u8; 4096]) -> String {
+        let mut listing = String::new();
+        let mut next = None;
+        for address in 0..0x1000 {
+            let count = self.count(address);
+            if count == 0 {
+                continue;
+            }
+            if next.is_some() && next != Some(address) {
+                let _ = writeln!(listing, "{:>10}  ...", "");
+            }
+            if self.routines.contains_key(&address) {
+                let _ = writeln!(listing, "{:>10}  sub_{:03x}:", "", address);
+            }
+            let opcode = opcode_at(memory, address);
+            let _ = writeln!(
+                listing,
+                "{:>10}  {:03x}: {:04x}  {}",
+                count,
+                address,
+                opcode,
+                disassemble(opcode)
+            );
+            next = Some(address + 2);
+        }
+        listing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile() {
+        // 200: call 206, 202: jp 202, 206: ld v0 1, 208: ret
+        let program = vec![0x22, 0x06, 0x12, 0x02, 0x00, 0x00, 0x60, 0x01, 0x00, 0xee];
+        let mut chip = Chip8::load(program).unwrap();
+        let mut profile = Profile::new();
+        for _ in 0..6 {
+            profile.record(&chip);
+            chip.cycle().unwrap();
+        }
+
+        assert_eq!(profile.cycles(), 6);
+        assert_eq!(profile.count(0x200), 1);
+        assert_eq!(profile.count(0x202), 3);
+        assert_eq!(profile.count(0x206), 1);
+        assert_eq!(
+            profile.routines()[&0x206],
+            Routine {
+                calls: 1,
+                own: 2,
+                total: 2
+            }
+        );
+
+        let report = profile.report(chip.memory(), 1);
+        assert!(report.contains("  202           3   50.0%  1202  jp 0x202"));
+        assert!(report.contains("  1nnn           3   50.0%"));
+        let listing = profile.annotate(chip.memory());
+        assert_eq!(
+            listing.lines().collect::<Vec<_>>(),
+            [
+                "         1  200: 2206  call 0x206",
+                "         3  202: 1202  jp 0x202",
+                "            ...",
+                "            sub_206:",
+                "         1  206: 6001  ld v0, 0x01",
+                "         1  208: 00ee  ret",
+            ]
+        );
+    }
+}
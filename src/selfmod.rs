@@ -0,0 +1,229 @@
+// finds roms changing their own code as they run. FX33 and FX55 can write
+// anywhere, so each write is checked against the bytes that have run: a
+// write over code that already ran, or written bytes that are run later.
+// each is reported once per writing instruction and address, with the bytes
+// before and after the first time it happened.
+use crate::chip8::Chip8;
+use crate::disasm::{self, opcode_at};
+use crate::symbols::Symbols;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kind {
+    // written over code that had already run
+    Overwrite,
+    // written, then run
+    RunAfterWrite,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Modification {
+    pub kind: Kind,
+    // the instruction that wrote
+    pub pc: u16,
+    pub address: u16,
+    pub old: u8,
+    pub new: u8,
+    pub count: u64,
+}
+
+pub struct SelfModifying {
+    executed: Vec<bool>,
+    // bytes written since they last ran, as (writing pc, byte before)
+    written: Vec<Option<(u16, u8)>>,
+    // the bytes the last instruction was about to write, checked once it
+    // has run, as (pc, address, bytes before)
+    pending: Option<(u16, u16, Vec<u8>)>,
+    found: BTreeMap<(u16, u16, Kind), Modification>,
+    // found since the frontend last took them
+    fresh: Vec<Modification>,
+}
+
+impl Default for SelfModifying {
+    fn default() -> SelfModifying {
+        SelfModifying::new()
+    }
+}
+
+impl SelfModifying {
+    pub fn new() -> SelfModifying {
+        SelfModifying {
+            executed: vec![false; 4096],
+            written: vec![None; 4096],
+            pending: None,
+            found: BTreeMap::new(),
+            fresh: Vec::new(),
+        }
+    }
+
+    // called before each cycle with the instruction about to run
+    pub fn record(&mut self, chip: &Chip8) {
+        self.settle(chip);
+        if chip.waiting_for_vblank() {
+            return;
+        }
+        let pc = chip.pc() & 0xfff;
+        for address in [pc, (pc + 1) & 0xfff] {
+            if let Some((writer, old)) = self.written[address as usize].take() {
+                let new = chip.memory()[address as usize];
+                self.found(Kind::RunAfterWrite, writer, address, old, new);
+            }
+            self.executed[address as usize] = true;
+        }
+        let opcode = opcode_at(chip.memory(), pc);
+        let len = match disasm::pattern(opcode) {
+            "fx33" => 3,
+            "fx55" => ((opcode >> 8) & 0xf) as usize + 1,
+            _ => return,
+        };
+        // a write past the end of memory fails, only what fits is checked
+        let start = chip.index() as usize;
+        let end = (start + len).min(4096);
+        if start < end {
+            let old = chip.memory()[start..end].to_vec();
+            self.pending = Some((pc, start as u16, old));
+        }
+    }
+
+    // checks the write of the last instruction run, record does this too
+    pub fn settle(&mut self, chip: &Chip8) {
+        let (pc, start, old) = match self.pending.take() {
+            Some(pending) => pending,
+            None => return,
+        };
+        for (offset, &old) in old.iter().enumerate() {
+            let address = start + offset as u16;
+            let new = chip.memory()[address as usize];
+            if new == old {
+                continue;
+            }
+            if self.executed[address as usize] {
+                self.found(Kind::Overwrite, pc, address, old, new);
+            }
+            // the byte it had when it last ran, or was loaded
+            let before = self.written[address as usize].map_or(old, |(_, before)| before);
+            self.written[address as usize] = Some((pc, before));
+        }
+    }
+
+    fn found(&mut self, kind: Kind, pc: u16, address: u16, old: u8, new: u8) {
+        let modification = self
+            .found
+            .entry((pc, address, kind))
+            .or_insert(Modification {
+                kind,
+                pc,
+                address,
+                old,
+                new,
+                count: 0,
+            });
+        modification.count += 1;
+        if modification.count == 1 {
+            self.fresh.push(*modification);
+        }
+    }
+
+    // what was found since the last call, for flagging as the rom runs
+    pub fn take_fresh(&mut self) -> Vec<Modification> {
+        std::mem::take(&mut self.fresh)
+    }
+
+    // everything found, by writing instruction and address
+    pub fn modifications(&self) -> impl Iterator<Item = &Modification> {
+        self.found.values()
+    }
+
+    pub fn report(&self, symbols: &Symbols) -> String {
+        let mut report = String::new();
+        let _ = writeln!(report, "{} self-modifying writes", self.found.len());
+        for modification in self.found.values() {
+            let _ = writeln!(report, "  {}", modification.describe(symbols));
+        }
+        report
+    }
+}
+
+impl Modification {
+    // "main+4 wrote loop+1: 00 -> 12, over code that had run (3 times)"
+    pub fn describe(&self, symbols: &Symbols) -> String {
+        let what = match self.kind {
+            Kind::Overwrite => "over code that had run",
+            Kind::RunAfterWrite => "run afterwards",
+        };
+        let mut text = format!(
+            "{} wrote {}: {:02x} -> {:02x}, {}",
+            symbols.describe(self.pc),
+            symbols.describe(self.address),
+            self.old,
+            self.new,
+            what
+        );
+        if self.count > 1 {
+            let _ = write!(text, " ({} times)", self.count);
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_self_modifying() {
+        let program = vec![
+            0xa2, 0x00, // 200: ld i, 200
+            0x60, 0x60, // 202: ld v0, 0x60
+            0xf0, 0x55, // 204: ld [i], v0, over the instruction at 200
+            0xa2, 0x0c, // 206: ld i, 20c
+            0xf0, 0x55, // 208: ld [i], v0, turning 20c into ld v0, 5
+            0x12, 0x0c, // 20a: jp 20c
+            0x00, 0x05, // 20c: written before it runs
+            0x12, 0x0e, // 20e: jp 20e
+        ];
+        let mut chip = Chip8::load(program).unwrap();
+        let mut detector = SelfModifying::new();
+        for _ in 0..8 {
+            detector.record(&chip);
+            chip.cycle().unwrap();
+        }
+        detector.settle(&chip);
+
+        let found: Vec<Modification> = detector.modifications().copied().collect();
+        assert_eq!(
+            found,
+            [
+                Modification {
+                    kind: Kind::Overwrite,
+                    pc: 0x204,
+                    address: 0x200,
+                    old: 0xa2,
+                    new: 0x60,
+                    count: 1,
+                },
+                Modification {
+                    kind: Kind::RunAfterWrite,
+                    pc: 0x208,
+                    address: 0x20c,
+                    old: 0x00,
+                    new: 0x60,
+                    count: 1,
+                },
+            ]
+        );
+        assert_eq!(detector.take_fresh().len(), 2);
+        assert!(detector.take_fresh().is_empty());
+
+        let mut symbols = Symbols::default();
+        symbols.insert("start", 0x200);
+        assert_eq!(
+            found[0].describe(&symbols),
+            "start+4 wrote start: a2 -> 60, over code that had run"
+        );
+        assert!(detector
+            .report(&symbols)
+            .starts_with("2 self-modifying writes\n"));
+    }
+}
@@ -0,0 +1,193 @@
+// settings the frontend remembers for each rom, stored as toml keyed by the
+// SHA-1 of the rom (see database::rom_hash) in the user's config directory
+use crate::chip8::Quirks;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// anything that is None was never changed for the rom and falls back to the
+// rom database or the defaults
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RomSettings {
+    // file name the rom was last loaded from, only there to make the file readable
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    // instructions per frame
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tickrate: Option<u32>,
+    // a builtin palette name or "#rrggbb,#rrggbb", see Palette::parse
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub palette: Option<String>,
+    // flicker filter, see FilterMode::parse
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scale: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub save_slot: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quirks: Option<Quirks>,
+    // chip8 key as a hex digit -> name of the keyboard key bound to it, e.g. "5" = "W"
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub keymap: BTreeMap<String, String>,
+    // watch expressions shown by the debugger, see watch::Watch::parse
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub watches: Vec<String>,
+    // values frozen every frame, see cheat::Cheat::parse
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub cheats: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct File {
+    #[serde(default)]
+    roms: BTreeMap<String, RomSettings>,
+}
+
+pub struct SettingsFile {
+    path: PathBuf,
+    roms: BTreeMap<String, RomSettings>,
+}
+
+impl SettingsFile {
+    // <config dir>/chip8/roms.toml, None when the platform has no config dir
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("chip8").join("roms.toml"))
+    }
+
+    // a missing file is the same as an empty one
+    pub fn load(path: &Path) -> Result<SettingsFile, String> {
+        let file: File = match std::fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text).map_err(|e| e.to_string())?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => File::default(),
+            Err(err) => return Err(err.to_string()),
+        };
+        Ok(SettingsFile {
+            path: path.to_path_buf(),
+            roms: file.roms,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn get(&self, hash: &str) -> RomSettings {
+        self.roms.get(hash).cloned().unwrap_or_default()
+    }
+
+    pub fn set(&mut self, hash: &str, settings: RomSettings) {
+        self.roms.insert(hash.to_string(), settings);
+    }
+
+    // writes every rom's settings back, creating the directory if needed
+    pub fn save(&self) -> Result<(), String> {
+        let file = File {
+            roms: self.roms.clone(),
+        };
+        let text = toml::to_string_pretty(&file).map_err(|e| e.to_string())?;
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        std::fs::write(&self.path, text).map_err(|e| e.to_string())
+    }
+}
+
+// the SCHIP RPL user flags of each rom (see Chip8::rpl_flags), kept apart from
+// the settings as they change while the rom runs. one file of 16 raw bytes per
+// rom, named after its hash.
+pub struct RplStore {
+    dir: PathBuf,
+}
+
+impl RplStore {
+    // <data dir>/chip8/rpl, None when the platform has no data dir
+    pub fn default_dir() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("chip8").join("rpl"))
+    }
+
+    pub fn new(dir: &Path) -> RplStore {
+        RplStore {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    // flags that were never saved are all 0, like on a fresh calculator
+    pub fn load(&self, hash: &str) -> io::Result<[u8; 16]> {
+        let mut flags = [0; 16];
+        match std::fs::read(self.dir.join(hash)) {
+            Ok(bytes) => {
+                let len = bytes.len().min(16);
+                flags[..len].copy_from_slice(&bytes[..len]);
+                Ok(flags)
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(flags),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn save(&self, hash: &str, flags: &[u8; 16]) -> io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(self.dir.join(hash), flags)
+    }
+}
+
+// where a save state of a rom goes, <data dir>/chip8/states/<hash>-<slot>.state
+pub fn save_state_path(hash: &str, slot: u32) -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| {
+        dir.join("chip8")
+            .join("states")
+            .join(format!("{}-{}.state", hash, slot))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = std::env::temp_dir().join(format!("chip8-settings-{}", std::process::id()));
+        let path = dir.join("roms.toml");
+        let mut file = SettingsFile::load(&path).unwrap();
+        assert_eq!(file.get("abc"), RomSettings::default());
+
+        let mut settings = RomSettings {
+            name: Some("pong.ch8".to_string()),
+            tickrate: Some(20),
+            palette: Some("amber".to_string()),
+            filter: Some("blend:3".to_string()),
+            quirks: Some(Quirks {
+                vblank: true,
+                ..Quirks::default()
+            }),
+            ..RomSettings::default()
+        };
+        settings.keymap.insert("5".to_string(), "Up".to_string());
+        settings.watches.push("score=byte[0x3f0]".to_string());
+        settings.cheats.push("0x3f0=3".to_string());
+        file.set("abc", settings.clone());
+        file.save().unwrap();
+
+        let loaded = SettingsFile::load(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded.get("abc"), settings);
+        assert_eq!(loaded.get("def"), RomSettings::default());
+    }
+
+    #[test]
+    fn test_rpl_store() {
+        let dir = std::env::temp_dir().join(format!("chip8-rpl-{}", std::process::id()));
+        let store = RplStore::new(&dir);
+        assert_eq!(store.load("abc").unwrap(), [0; 16]);
+
+        let mut flags = [0; 16];
+        flags[..3].copy_from_slice(&[1, 2, 3]);
+        store.save("abc", &flags).unwrap();
+        let loaded = store.load("abc").unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded, flags);
+    }
+}
@@ -0,0 +1,129 @@
+// labels for addresses, read from the symbol file an assembler writes next
+// to the rom, so addresses can be shown as "draw_player+4" and looked up by
+// name
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Symbols {
+    // the first label of each address
+    labels: BTreeMap<u16, String>,
+    addresses: BTreeMap<String, u16>,
+}
+
+impl Symbols {
+    // one label per line as "NAME ADDRESS", "ADDRESS NAME" or "NAME = ADDRESS"
+    // with the address in hex as 0x2a6 or $2a6, or decimal. blank lines and
+    // lines starting with # or ; are skipped.
+    pub fn parse(text: &str) -> Result<Symbols, String> {
+        let mut symbols = Symbols::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            let words: Vec<&str> = line
+                .split(|c: char| c.is_whitespace() || c == '=')
+                .filter(|word| !word.is_empty())
+                .collect();
+            let parsed = match words[..] {
+                [a, b] => match (parse_address(a), parse_address(b)) {
+                    (_, Some(address)) => Some((a, address)),
+                    (Some(address), None) => Some((b, address)),
+                    (None, None) => None,
+                },
+                _ => None,
+            };
+            match parsed {
+                Some((name, address)) => symbols.insert(name, address),
+                None => {
+                    return Err(format!(
+                        "line {}: expected NAME ADDRESS, found {}",
+                        number + 1,
+                        line
+                    ))
+                }
+            }
+        }
+        Ok(symbols)
+    }
+
+    pub fn insert(&mut self, name: &str, address: u16) {
+        self.labels
+            .entry(address)
+            .or_insert_with(|| name.to_string());
+        self.addresses.insert(name.to_string(), address);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.addresses.get(name).copied()
+    }
+
+    // the label at exactly address
+    pub fn label(&self, address: u16) -> Option<&str> {
+        self.labels.get(&address).map(String::as_str)
+    }
+
+    // the closest label at or before address with the offset from it, e.g.
+    // "draw_player+4", or the address as hex when there is no label before it
+    pub fn describe(&self, address: u16) -> String {
+        match self.labels.range(..=address).next_back() {
+            Some((&start, name)) if start == address => name.clone(),
+            Some((&start, name)) => format!("{}+{}", name, address - start),
+            None => format!("{:#05x}", address),
+        }
+    }
+
+    // a label, a label with an offset like "draw_player+4" or an address
+    pub fn resolve(&self, s: &str) -> Result<u16, String> {
+        let s = s.trim();
+        if let Some(address) = parse_address(s) {
+            return Ok(address);
+        }
+        let (name, offset) = match s.split_once('+') {
+            Some((name, offset)) => (name.trim(), parse_address(offset.trim())),
+            None => (s, Some(0)),
+        };
+        match (self.address(name), offset) {
+            (Some(address), Some(offset)) if address + offset < 0x1000 => Ok(address + offset),
+            (None, _) => Err(format!("unknown label {}", name)),
+            _ => Err(format!("invalid address {}", s)),
+        }
+    }
+}
+
+fn parse_address(s: &str) -> Option<u16> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix('$')) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    };
+    parsed.filter(|&address| address < 0x1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbols() {
+        let symbols =
+            Symbols::parse("# game\nmain 0x200\n0x2a2 draw_player\nscore = $3f0\nalias 674\n")
+                .unwrap();
+        assert_eq!(symbols.address("score"), Some(0x3f0));
+        assert_eq!(symbols.describe(0x2a6), "draw_player+4");
+        assert_eq!(symbols.describe(0x2a2), "draw_player");
+        assert_eq!(symbols.describe(0x1fe), "0x1fe");
+        assert_eq!(symbols.label(0x2a2), Some("draw_player"));
+        assert_eq!(symbols.label(0x2a6), None);
+        assert_eq!(symbols.resolve("draw_player+4"), Ok(0x2a6));
+        assert_eq!(symbols.resolve("alias"), Ok(0x2a2));
+        assert_eq!(symbols.resolve("0x300"), Ok(0x300));
+        assert!(symbols.resolve("missing").is_err());
+
+        assert!(Symbols::parse("main").is_err());
+        assert!(Symbols::parse("main start").is_err());
+    }
+}
@@ -0,0 +1,157 @@
+// named watch expressions for debugging, e.g. "score=byte[0x3f0]" or "v3"
+use crate::chip8::Chip8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expr {
+    // a byte of memory
+    Byte(u16),
+    // two bytes of memory, big endian like chip8 instructions
+    Word(u16),
+    Register(usize),
+    Index,
+    Pc,
+    Sp,
+    DelayTimer,
+    SoundTimer,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watch {
+    pub name: String,
+    pub expr: Expr,
+}
+
+impl Watch {
+    // "name=expr", or just "expr" which is then also the name
+    pub fn parse(s: &str) -> Result<Watch, String> {
+        let (name, expr) = match s.split_once('=') {
+            Some((name, expr)) => (name.trim(), expr.trim()),
+            None => (s.trim(), s.trim()),
+        };
+        Ok(Watch {
+            name: name.to_string(),
+            expr: Expr::parse(expr)?,
+        })
+    }
+
+    pub fn evaluate(&self, chip: &Chip8) -> u16 {
+        self.expr.evaluate(chip)
+    }
+
+    // "name = 0x2a (42)"
+    pub fn describe(&self, chip: &Chip8) -> String {
+        let value = self.evaluate(chip);
+        format!("{} = {:#x} ({})", self.name, value, value)
+    }
+}
+
+impl Expr {
+    // byte[ADDRESS], word[ADDRESS], v0-vf, i, pc, sp, dt or st. addresses are
+    // hex with 0x or decimal.
+    pub fn parse(s: &str) -> Result<Expr, String> {
+        let s = s.trim().to_lowercase();
+        let memory = |prefix: &str| {
+            s.strip_prefix(prefix)
+                .and_then(|rest| rest.strip_suffix(']'))
+                .map(parse_address)
+        };
+        if let Some(address) = memory("byte[") {
+            return Ok(Expr::Byte(address?));
+        }
+        if let Some(address) = memory("word[") {
+            return Ok(Expr::Word(address?));
+        }
+        match s.as_str() {
+            "i" => Ok(Expr::Index),
+            "pc" => Ok(Expr::Pc),
+            "sp" => Ok(Expr::Sp),
+            "dt" => Ok(Expr::DelayTimer),
+            "st" => Ok(Expr::SoundTimer),
+            _ => match s.strip_prefix('v').map(|x| usize::from_str_radix(x, 16)) {
+                Some(Ok(x)) if x < 16 => Ok(Expr::Register(x)),
+                _ => Err(format!(
+                    "invalid watch {}, expected byte[ADDRESS], word[ADDRESS], v0-vf, i, pc, sp, dt or st",
+                    s
+                )),
+            },
+        }
+    }
+
+    pub fn evaluate(&self, chip: &Chip8) -> u16 {
+        let memory = chip.memory();
+        let byte = |address: u16| memory[address as usize & 0xfff] as u16;
+        match *self {
+            Expr::Byte(address) => byte(address),
+            Expr::Word(address) => byte(address) << 8 | byte(address.wrapping_add(1)),
+            Expr::Register(x) => chip.registers()[x] as u16,
+            Expr::Index => chip.index(),
+            Expr::Pc => chip.pc(),
+            Expr::Sp => chip.sp() as u16,
+            Expr::DelayTimer => chip.get_delay_timer() as u16,
+            Expr::SoundTimer => chip.get_sound_timer() as u16,
+        }
+    }
+}
+
+// written back the way Watch::parse reads it
+impl std::fmt::Display for Watch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}={}", self.name, self.expr)
+    }
+}
+
+impl std::fmt::Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Expr::Byte(address) => write!(f, "byte[{:#05x}]", address),
+            Expr::Word(address) => write!(f, "word[{:#05x}]", address),
+            Expr::Register(x) => write!(f, "v{:x}", x),
+            Expr::Index => write!(f, "i"),
+            Expr::Pc => write!(f, "pc"),
+            Expr::Sp => write!(f, "sp"),
+            Expr::DelayTimer => write!(f, "dt"),
+            Expr::SoundTimer => write!(f, "st"),
+        }
+    }
+}
+
+fn parse_address(s: &str) -> Result<u16, String> {
+    let s = s.trim();
+    let parsed = match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    match parsed {
+        Ok(address) if address < 0x1000 => Ok(address),
+        _ => Err(format!("invalid address {}, expected 0x000 to 0xfff", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watch() {
+        let mut chip = Chip8::load(vec![0x63, 0x2a]).unwrap();
+        chip.cycle().unwrap();
+        chip.poke(0x3f0, 0x12);
+        chip.poke(0x3f1, 0x34);
+
+        let score = Watch::parse("score = byte[0x3f0]").unwrap();
+        assert_eq!(score.name, "score");
+        assert_eq!(score.evaluate(&chip), 0x12);
+        assert_eq!(Watch::parse("word[1008]").unwrap().evaluate(&chip), 0x1234);
+        assert_eq!(
+            Watch::parse("V3").unwrap().describe(&chip),
+            "V3 = 0x2a (42)"
+        );
+        assert_eq!(Watch::parse("pc").unwrap().evaluate(&chip), 0x202);
+
+        assert_eq!(Watch::parse(&score.to_string()), Ok(score));
+
+        assert!(Watch::parse("byte[0x1000]").is_err());
+        assert!(Watch::parse("vg").is_err());
+        assert!(Watch::parse("x=nothing").is_err());
+    }
+}
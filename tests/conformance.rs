@@ -0,0 +1,199 @@
+// runs test roms from tests/roms headlessly and compares the final framebuffer
+// against a golden hash stored next to the rom as <rom>.hash.
+// run with CHIP8_BLESS=1 to (re)write the golden hashes, the framebuffer is
+// printed so it can be checked by eye. the cached engine has to end up with
+// the same framebuffer as the reference interpreter.
+use chip8::chip8::Engine;
+use chip8::Chip8;
+use std::fs;
+use std::path::PathBuf;
+
+struct Case {
+    rom: &'static str,
+    cycles: u32,
+    // (cycle, key, pressed) events fed to the keypad while running
+    keys: &'static [(u32, usize, bool)],
+}
+
+fn rom_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("roms")
+        .join(name)
+}
+
+fn run_rom(case: &Case, engine: Engine) -> [bool; 64 * 32] {
+    let program = fs::read(rom_path(case.rom))
+        .unwrap_or_else(|_| panic!("missing rom {}, see tests/roms/README.md", case.rom));
+    let mut chip = Chip8::load(program).unwrap();
+    chip.set_engine(engine);
+    for cycle in 0..case.cycles {
+        for &(at, key, pressed) in case.keys {
+            if at == cycle {
+                chip.set_key(key, pressed);
+            }
+        }
+        chip.cycle().unwrap();
+    }
+    chip.gfx
+}
+
+// fnv-1a over the framebuffer, one byte per pixel. used instead of
+// DefaultHasher since its output is not stable between rust versions.
+fn frame_hash(gfx: &[bool; 64 * 32]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &pixel in gfx.iter() {
+        hash ^= pixel as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+fn render(gfx: &[bool; 64 * 32]) -> String {
+    let mut out = String::new();
+    for row in gfx.chunks(64) {
+        out.extend(row.iter().map(|&p| if p { '#' } else { '.' }));
+        out.push('\n');
+    }
+    out
+}
+
+fn check(case: Case) {
+    let gfx = run_rom(&case, Engine::Reference);
+    assert!(
+        run_rom(&case, Engine::Cached)[..] == gfx[..],
+        "the cached engine drew something else for {}",
+        case.rom
+    );
+    #[cfg(feature = "jit")]
+    for engine in [Engine::Jit, Engine::Lockstep] {
+        assert!(
+            run_rom(&case, engine)[..] == gfx[..],
+            "{:?} drew something else for {}",
+            engine,
+            case.rom
+        );
+    }
+    let hash = format!("{:016x}", frame_hash(&gfx));
+    let hash_path = rom_path(&format!("{}.hash", case.rom));
+
+    if std::env::var_os("CHIP8_BLESS").is_some() {
+        println!("{}:\n{}", case.rom, render(&gfx));
+        fs::write(&hash_path, format!("{}\n", hash)).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(&hash_path)
+        .unwrap_or_else(|_| panic!("no golden hash for {}, run with CHIP8_BLESS=1", case.rom));
+    assert_eq!(
+        expected.trim(),
+        hash,
+        "framebuffer of {} changed:\n{}",
+        case.rom,
+        render(&gfx)
+    );
+}
+
+#[test]
+fn test_digits() {
+    check(Case {
+        rom: "digits.ch8",
+        cycles: 500,
+        keys: &[],
+    });
+}
+
+#[test]
+fn test_opcodes() {
+    check(Case {
+        rom: "opcodes.ch8",
+        cycles: 1000,
+        keys: &[],
+    });
+}
+
+#[test]
+fn test_flags() {
+    check(Case {
+        rom: "flags.ch8",
+        cycles: 1000,
+        keys: &[],
+    });
+}
+
+#[test]
+fn test_quirks() {
+    check(Case {
+        rom: "quirks.ch8",
+        cycles: 1000,
+        keys: &[],
+    });
+}
+
+#[test]
+fn test_keypad() {
+    // hold 5 and let go of it, then press and release a for FX0A
+    check(Case {
+        rom: "keypad.ch8",
+        cycles: 1000,
+        keys: &[
+            (100, 0x5, true),
+            (200, 0x5, false),
+            (300, 0xa, true),
+            (400, 0xa, false),
+        ],
+    });
+}
+
+// the public test suites, ignored until their roms are checked in
+#[test]
+#[ignore = "rom not vendored, see tests/roms/README.md"]
+fn test_corax_plus() {
+    check(Case {
+        rom: "3-corax+.ch8",
+        cycles: 2000,
+        keys: &[],
+    });
+}
+
+#[test]
+#[ignore = "rom not vendored, see tests/roms/README.md"]
+fn test_suite_flags() {
+    check(Case {
+        rom: "4-flags.ch8",
+        cycles: 2000,
+        keys: &[],
+    });
+}
+
+#[test]
+#[ignore = "rom not vendored, see tests/roms/README.md"]
+fn test_suite_quirks() {
+    // pick the chip-8 platform from the menu
+    check(Case {
+        rom: "5-quirks.ch8",
+        cycles: 10000,
+        keys: &[(100, 0x1, true), (200, 0x1, false)],
+    });
+}
+
+#[test]
+#[ignore = "rom not vendored, see tests/roms/README.md"]
+fn test_suite_keypad() {
+    // pick the EX9E test, then hold key 5 so its sprite lights up
+    check(Case {
+        rom: "6-keypad.ch8",
+        cycles: 2000,
+        keys: &[(100, 0x1, true), (200, 0x1, false), (500, 0x5, true)],
+    });
+}
+
+#[test]
+#[ignore = "rom not vendored, see tests/roms/README.md"]
+fn test_bc_test() {
+    check(Case {
+        rom: "BC_test.ch8",
+        cycles: 2000,
+        keys: &[],
+    });
+}